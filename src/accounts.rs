@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use glob::Pattern;
+
+//how the leading directories of a mail root form the account name, e.g. <domain>/<user>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountLayout {
+    depth: usize,
+    format: Option<String>,
+}
+
+impl Default for AccountLayout {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            format: None,
+        }
+    }
+}
+
+impl AccountLayout {
+    //the format references the directories with {0}, {1}, ..., without one they are joined with /
+    pub fn new(depth: usize, format: Option<&str>) -> Self {
+        Self {
+            depth: depth.max(1),
+            format: format.map(str::to_owned),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    //the account name of a path of the leading directories
+    pub fn name(&self, dirs: &str) -> String {
+        match &self.format {
+            Some(format) => dirs
+                .split('/')
+                .enumerate()
+                .fold(format.clone(), |name, (index, dir)| {
+                    name.replace(&format!("{{{}}}", index), dir)
+                }),
+            None => dirs.to_owned(),
+        }
+    }
+}
+
+//mailboxes of accounts which are not synced on changes
+#[derive(Debug, Clone, Default)]
+pub struct MailboxFilter {
+    ignored: HashMap<String, Vec<Pattern>>,
+    //accounts kept on disk, but never synced
+    disabled: HashSet<String>,
+}
+
+impl MailboxFilter {
+    pub fn with_ignored_mailboxes(
+        mut self,
+        account: &str,
+        patterns: &[String],
+    ) -> Result<Self, glob::PatternError> {
+        self.ignored.insert(
+            account.to_owned(),
+            patterns
+                .iter()
+                .map(|pattern| Pattern::new(pattern))
+                .collect::<Result<_, _>>()?,
+        );
+        Ok(self)
+    }
+
+    pub fn with_disabled_account(mut self, account: &str) -> Self {
+        self.disabled.insert(account.to_owned());
+        self
+    }
+
+    pub fn is_disabled(&self, account: &str) -> bool {
+        self.disabled.contains(account)
+    }
+
+    //sorted, also those without a directory
+    pub fn disabled(&self) -> Vec<String> {
+        let mut disabled = self.disabled.iter().cloned().collect::<Vec<_>>();
+        disabled.sort_unstable();
+        disabled
+    }
+
+    pub fn is_ignored(&self, account: &str, mailbox: &str) -> bool {
+        self.is_disabled(account)
+            || self
+                .ignored
+                .get(account)
+                .is_some_and(|patterns| patterns.iter().any(|pattern| pattern.matches(mailbox)))
+    }
+}
+
+//the accounts are the directories in the mail roots, rescanned to find accounts created at runtime
+#[derive(Debug, Clone)]
+pub struct Accounts {
+    roots: Arc<Vec<PathBuf>>,
+    layout: AccountLayout,
+    mailbox_filter: MailboxFilter,
+    accounts: Arc<RwLock<Vec<String>>>,
+    //the mailboxes seen in events, for the mailbox patterns of the timer
+    mailboxes: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    //the last change of the accounts, for the adaptive timer
+    activity: Arc<RwLock<HashMap<String, Instant>>>,
+}
+
+impl Accounts {
+    pub fn new(roots: Vec<PathBuf>) -> Result<Self, io::Error> {
+        Self::with_layout(roots, AccountLayout::default())
+    }
+
+    pub fn with_layout(roots: Vec<PathBuf>, layout: AccountLayout) -> Result<Self, io::Error> {
+        let accounts = Self {
+            roots: Arc::new(roots),
+            layout,
+            mailbox_filter: MailboxFilter::default(),
+            accounts: Arc::default(),
+            mailboxes: Arc::default(),
+            activity: Arc::default(),
+        };
+        accounts.refresh()?;
+        Ok(accounts)
+    }
+
+    pub fn with_mailbox_filter(mut self, filter: MailboxFilter) -> Self {
+        self.mailbox_filter = filter;
+        self
+    }
+
+    pub fn is_mailbox_ignored(&self, account: &str, mailbox: &str) -> bool {
+        self.mailbox_filter.is_ignored(account, mailbox)
+    }
+
+    pub fn is_disabled(&self, account: &str) -> bool {
+        self.mailbox_filter.is_disabled(account)
+    }
+
+    pub fn disabled(&self) -> Vec<String> {
+        self.mailbox_filter.disabled()
+    }
+
+    //without the disabled accounts
+    pub fn list(&self) -> Vec<String> {
+        self.accounts
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|account| !self.is_disabled(account))
+            .cloned()
+            .collect()
+    }
+
+    pub fn add(&self, account: &str) {
+        let mut accounts = self.accounts.write().unwrap();
+        if !accounts.iter().any(|known| known == account) {
+            log::info!("new account {}", account);
+            accounts.push(account.to_owned());
+            accounts.sort();
+        }
+    }
+
+    //the account of a user of dovecot, e.g. frank@example.org of the dir frank or example.org/frank
+    pub fn account_of_user(&self, user: &str) -> Option<String> {
+        let mut candidates = vec![user.to_owned()];
+        if let Some((local, domain)) = user.rsplit_once('@') {
+            candidates.push(self.layout.name(&format!("{}/{}", domain, local)));
+            candidates.push(local.to_owned());
+        }
+        let accounts = self.accounts.read().unwrap();
+        candidates
+            .into_iter()
+            .find(|candidate| accounts.contains(candidate))
+    }
+
+    pub fn add_mailbox(&self, account: &str, mailbox: &str) {
+        self.add(account);
+        let mut mailboxes = self.mailboxes.write().unwrap();
+        let account_mailboxes = mailboxes.entry(account.to_owned()).or_default();
+        if !account_mailboxes.iter().any(|known| known == mailbox) {
+            account_mailboxes.push(mailbox.to_owned());
+            account_mailboxes.sort();
+        }
+    }
+
+    pub fn mailboxes(&self, account: &str) -> Vec<String> {
+        self.mailboxes
+            .read()
+            .unwrap()
+            .get(account)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn record_activity(&self, account: &str) {
+        self.activity
+            .write()
+            .unwrap()
+            .insert(account.to_owned(), Instant::now());
+    }
+
+    pub fn last_activity(&self, account: &str) -> Option<Instant> {
+        self.activity.read().unwrap().get(account).copied()
+    }
+
+    pub fn scan(&self, root: &Path) -> Result<Vec<String>, io::Error> {
+        let mut dirs = vec![String::new()];
+        for _ in 0..self.layout.depth() {
+            let mut next_dirs = Vec::new();
+            for dir in dirs {
+                for entry in fs::read_dir(root.join(&dir))? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        if let Some(name) = entry.file_name().to_str() {
+                            next_dirs.push(match dir.as_str() {
+                                "" => name.to_owned(),
+                                dir => format!("{}/{}", dir, name),
+                            });
+                        }
+                    }
+                }
+            }
+            dirs = next_dirs;
+        }
+        Ok(dirs.iter().map(|dir| self.layout.name(dir)).collect())
+    }
+
+    pub fn refresh(&self) -> Result<(), io::Error> {
+        let mut result = Vec::new();
+        for root in self.roots.iter() {
+            //a root may be temporarily unavailable, e.g. an unmounted remote filesystem
+            match self.scan(root) {
+                Ok(accounts) => result.extend(accounts),
+                Err(err) => log::warn!("could not read mail root {:?}: {}", root, err),
+            }
+        }
+        //an account may be split across several roots
+        result.sort();
+        result.dedup();
+        let mut accounts = self.accounts.write().unwrap();
+        for account in result.iter().filter(|account| !accounts.contains(account)) {
+            log::info!("new account {}", account);
+        }
+        *accounts = result;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::{AccountLayout, Accounts, MailboxFilter};
+
+    #[test]
+    fn it_should_discover_new_accounts() {
+        let root1 = TempDir::new().unwrap();
+        let root2 = TempDir::new().unwrap();
+        fs::create_dir(root1.path().join("acc1")).unwrap();
+        fs::create_dir(root2.path().join("acc1")).unwrap();
+        fs::write(root1.path().join("file"), "").unwrap();
+        let accounts =
+            Accounts::new(vec![root1.path().to_path_buf(), root2.path().to_path_buf()]).unwrap();
+        assert_eq!(vec!["acc1"], accounts.list());
+        fs::create_dir(root2.path().join("acc2")).unwrap();
+        assert_eq!(vec!["acc1"], accounts.list());
+        accounts.refresh().unwrap();
+        assert_eq!(vec!["acc1", "acc2"], accounts.list());
+        accounts.add("acc0");
+        assert_eq!(vec!["acc0", "acc1", "acc2"], accounts.list());
+    }
+
+    #[test]
+    fn it_should_form_accounts_of_several_dirs() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("example.org/frank/Mail")).unwrap();
+        fs::create_dir_all(root.path().join("example.org/tom")).unwrap();
+        let accounts = Accounts::with_layout(
+            vec![root.path().to_path_buf()],
+            AccountLayout::new(2, Some("{1}@{0}")),
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["frank@example.org", "tom@example.org"],
+            accounts.list()
+        );
+    }
+
+    #[test]
+    fn it_should_find_the_accounts_of_users() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("example.org/frank")).unwrap();
+        let accounts =
+            Accounts::with_layout(vec![root.path().to_path_buf()], AccountLayout::new(2, None))
+                .unwrap();
+        assert_eq!(
+            Some("example.org/frank".to_owned()),
+            accounts.account_of_user("frank@example.org")
+        );
+        assert_eq!(None, accounts.account_of_user("tom@example.org"));
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("frank")).unwrap();
+        let accounts = Accounts::new(vec![root.path().to_path_buf()]).unwrap();
+        assert_eq!(
+            Some("frank".to_owned()),
+            accounts.account_of_user("frank@example.org")
+        );
+        assert_eq!(Some("frank".to_owned()), accounts.account_of_user("frank"));
+        assert_eq!(None, accounts.account_of_user("tom"));
+    }
+
+    #[test]
+    fn it_should_ignore_mailboxes_of_accounts() {
+        let filter = MailboxFilter::default()
+            .with_ignored_mailboxes("work", &["Archive/*".to_owned(), "Spam".to_owned()])
+            .unwrap();
+        assert!(filter.is_ignored("work", "Archive/2020"));
+        assert!(filter.is_ignored("work", "Spam"));
+        assert!(!filter.is_ignored("work", "INBOX"));
+        assert!(!filter.is_ignored("private", "Spam"));
+    }
+
+    #[test]
+    fn it_should_skip_disabled_accounts() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("archive")).unwrap();
+        fs::create_dir(root.path().join("work")).unwrap();
+        let accounts = Accounts::new(vec![root.path().to_path_buf()])
+            .unwrap()
+            .with_mailbox_filter(MailboxFilter::default().with_disabled_account("archive"));
+        assert_eq!(vec!["work"], accounts.list());
+        assert!(accounts.is_mailbox_ignored("archive", "INBOX"));
+        assert!(!accounts.is_mailbox_ignored("work", "INBOX"));
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    process::Command,
+    time::Duration,
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectivityCheck {
+    //a tcp connection to the address, e.g. of the imap server
+    Tcp(String),
+    //the connectivity state of NetworkManager
+    NetworkManager,
+}
+
+impl ConnectivityCheck {
+    pub fn is_online(&self) -> bool {
+        match self {
+            Self::Tcp(address) => Self::probe_tcp(address),
+            Self::NetworkManager => Self::probe_network_manager(),
+        }
+    }
+
+    fn probe_tcp(address: &str) -> bool {
+        let addresses = match address.to_socket_addrs() {
+            Ok(addresses) => addresses,
+            //mostly a failed dns lookup
+            Err(err) => {
+                log::debug!("could not resolve {}: {}", address, err);
+                return false;
+            }
+        };
+        addresses
+            .into_iter()
+            .any(|address| TcpStream::connect_timeout(&address, PROBE_TIMEOUT).is_ok())
+    }
+
+    //without NetworkManager the connectivity is unknown, so it is assumed to be online
+    fn probe_network_manager() -> bool {
+        match Command::new("nmcli")
+            .args(["networking", "connectivity"])
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                let state = String::from_utf8_lossy(&output.stdout);
+                matches!(state.trim(), "full" | "unknown")
+            }
+            Ok(output) => {
+                log::warn!("nmcli failed with {}", output.status);
+                true
+            }
+            Err(err) => {
+                log::warn!("could not run nmcli: {}", err);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::ConnectivityCheck;
+
+    #[test]
+    fn it_should_probe_tcp_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        assert!(ConnectivityCheck::Tcp(address.to_string()).is_online());
+        drop(listener);
+        assert!(!ConnectivityCheck::Tcp(address.to_string()).is_online());
+        assert!(!ConnectivityCheck::Tcp("not an address".to_owned()).is_online());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{
+    accounts::Accounts,
+    executor::{execute_accounts, ExecError, ExecReport, Executor},
+    updater::MailUpdaterTask,
+};
+
+//skips the syncs of disabled accounts. a sync of all accounts excludes them if the executor can,
+//otherwise the enabled accounts with a directory are synced one after another, e.g. --all of
+//mbsync without the mbsyncrc would sync the disabled ones as well. the same applies to the
+//accounts excluded by the hooks, e.g. those with an unreachable server.
+pub struct DisabledAccountsExecutor<E: Executor> {
+    executor: E,
+    accounts: Accounts,
+}
+
+impl<E: Executor> DisabledAccountsExecutor<E> {
+    //the disabled accounts are those of the mailbox filter of the accounts
+    pub fn new(executor: E, accounts: Accounts) -> Self {
+        Self { executor, accounts }
+    }
+
+    fn execute_each(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Result<ExecReport, ExecError> {
+        let accounts = self.accounts.list();
+        execute_accounts(
+            accounts
+                .iter()
+                .filter(|account| !excluded.contains(account)),
+            |account| {
+                self.executor.execute(&MailUpdaterTask {
+                    specific_account: Some((*account).clone()),
+                    ..task.clone()
+                })
+            },
+        )
+    }
+
+    fn execute_all(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Result<ExecReport, ExecError> {
+        if excluded.is_empty() {
+            return self.executor.execute(task);
+        }
+        if let Some(result) = self.executor.execute_except(task, excluded) {
+            return result;
+        }
+        log::info!(
+            "sync the accounts one by one without {:?}, accounts without a directory are not synced",
+            excluded
+        );
+        self.execute_each(task, excluded)
+    }
+}
+
+impl<E: Executor> Executor for DisabledAccountsExecutor<E> {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        match &task.specific_account {
+            Some(account) if self.accounts.is_disabled(account) => {
+                log::info!("skip sync of the disabled account {}", account);
+                Ok(ExecReport::default())
+            }
+            Some(_) => self.executor.execute(task),
+            None => self.execute_all(task, &self.accounts.disabled()),
+        }
+    }
+
+    //the disabled accounts are excluded as well, so any executor can exclude accounts
+    fn execute_except(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Option<Result<ExecReport, ExecError>> {
+        if task.specific_account.is_some() {
+            return Some(self.execute(task));
+        }
+        let mut excluded = [excluded, &self.accounts.disabled()].concat();
+        excluded.sort_unstable();
+        excluded.dedup();
+        Some(self.execute_all(task, &excluded))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Mutex};
+
+    use tempfile::TempDir;
+
+    use super::DisabledAccountsExecutor;
+    use crate::{
+        accounts::{Accounts, MailboxFilter},
+        executor::{ExecError, ExecReport, Executor},
+        updater::MailUpdaterTask,
+    };
+
+    #[test]
+    fn it_should_not_sync_disabled_accounts() {
+        let root = TempDir::new().unwrap();
+        for account in ["archive", "private", "work"] {
+            fs::create_dir(root.path().join(account)).unwrap();
+        }
+        let accounts = Accounts::new(vec![root.path().to_path_buf()])
+            .unwrap()
+            .with_mailbox_filter(MailboxFilter::default().with_disabled_account("archive"));
+        let synced = Mutex::new(Vec::new());
+        let executor = DisabledAccountsExecutor::new(
+            |task: &MailUpdaterTask| {
+                synced.lock().unwrap().push(task.specific_account.clone());
+                Ok::<_, String>(())
+            },
+            accounts,
+        );
+        executor.execute(&MailUpdaterTask::new(None, None)).unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("archive".to_owned()), None))
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("work".to_owned()), None))
+            .unwrap();
+        assert_eq!(
+            vec![
+                Some("private".to_owned()),
+                Some("work".to_owned()),
+                Some("work".to_owned())
+            ],
+            *synced.lock().unwrap()
+        );
+    }
+
+    //records the accounts excluded from the syncs of all accounts
+    struct Excluding(Mutex<Vec<Vec<String>>>);
+
+    impl Executor for Excluding {
+        fn execute(&self, _: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+            self.0.lock().unwrap().push(Vec::new());
+            Ok(ExecReport::default())
+        }
+
+        fn execute_except(
+            &self,
+            _: &MailUpdaterTask,
+            excluded: &[String],
+        ) -> Option<Result<ExecReport, ExecError>> {
+            self.0.lock().unwrap().push(excluded.to_vec());
+            Some(Ok(ExecReport::default()))
+        }
+    }
+
+    #[test]
+    fn it_should_exclude_the_disabled_accounts_from_syncs_of_all_accounts() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("work")).unwrap();
+        let all = MailUpdaterTask::new(None, None);
+        let accounts = Accounts::new(vec![root.path().to_path_buf()]).unwrap();
+        let executor = DisabledAccountsExecutor::new(Excluding(Mutex::default()), accounts.clone());
+        executor.execute(&all).unwrap();
+        assert_eq!(
+            vec![Vec::<String>::new()],
+            *executor.executor.0.lock().unwrap()
+        );
+        let executor = DisabledAccountsExecutor::new(
+            Excluding(Mutex::default()),
+            accounts.with_mailbox_filter(MailboxFilter::default().with_disabled_account("archive")),
+        );
+        executor.execute(&all).unwrap();
+        assert_eq!(
+            vec![vec!["archive".to_owned()]],
+            *executor.executor.0.lock().unwrap()
+        );
+    }
+    #[test]
+    fn it_should_exclude_the_accounts_of_the_hooks_with_the_disabled_ones() {
+        let root = TempDir::new().unwrap();
+        for account in ["archive", "private", "work"] {
+            fs::create_dir(root.path().join(account)).unwrap();
+        }
+        let all = MailUpdaterTask::new(None, None);
+        let accounts = Accounts::new(vec![root.path().to_path_buf()])
+            .unwrap()
+            .with_mailbox_filter(MailboxFilter::default().with_disabled_account("archive"));
+        let executor = DisabledAccountsExecutor::new(Excluding(Mutex::default()), accounts.clone());
+        executor
+            .execute_except(&all, &["work".to_owned(), "archive".to_owned()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![vec!["archive".to_owned(), "work".to_owned()]],
+            *executor.executor.0.lock().unwrap()
+        );
+        //executors which can only sync all accounts sync the others one by one
+        let synced = Mutex::new(Vec::new());
+        let executor = DisabledAccountsExecutor::new(
+            |task: &MailUpdaterTask| {
+                synced.lock().unwrap().push(task.specific_account.clone());
+                Ok::<_, String>(())
+            },
+            accounts,
+        );
+        executor
+            .execute_except(&all, &["work".to_owned()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec![Some("private".to_owned())], *synced.lock().unwrap());
+    }
+}
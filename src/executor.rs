@@ -0,0 +1,617 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, BufRead, BufReader, Read},
+    process::{Child, Command, ExitStatus, Stdio},
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::process::CommandExt;
+
+use thiserror::Error;
+
+use crate::suppressor::SyncSuppressor;
+use crate::updater::MailUpdaterTask;
+use crate::user::LocalUser;
+
+//the cause of a failed sync, it decides about retries and the circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    //e.g. a refused connection or failed dns lookup, retried with backoff until it succeeds
+    Network,
+    //e.g. an unknown channel, not retried until fixed
+    Config,
+    //killed by a signal, retried without counting as failure of the account
+    Interrupted,
+    //vetoed before the sync, e.g. by a pre sync hook, deferred without counting as failure
+    Deferred,
+    Other,
+}
+
+#[derive(Debug, Error)]
+pub enum ExecError {
+    #[error("IO-Error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("command failed with {0} ({1:?})")]
+    Failed(ExitStatus, FailureKind),
+    #[error("command killed after {0:?}")]
+    Timeout(Duration),
+    //e.g. a task of an unknown channel, detected before running the command
+    #[error("invalid config: {0}")]
+    Config(String),
+    #[error("deferred by {0}")]
+    Deferred(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+//the messages and flags transferred by a sync
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncCounts {
+    pub pulled: u64,
+    pub pulled_flags: u64,
+    pub pushed: u64,
+    pub pushed_flags: u64,
+    pub expunged: u64,
+}
+
+impl SyncCounts {
+    pub fn add(&mut self, counts: SyncCounts) {
+        self.pulled += counts.pulled;
+        self.pulled_flags += counts.pulled_flags;
+        self.pushed += counts.pushed;
+        self.pushed_flags += counts.pushed_flags;
+        self.expunged += counts.expunged;
+    }
+}
+
+impl ExecError {
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            //the command is missing or not executable
+            Self::IoError(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                FailureKind::Config
+            }
+            Self::IoError(_) | Self::Other(_) => FailureKind::Other,
+            Self::Config(_) => FailureKind::Config,
+            Self::Deferred(_) => FailureKind::Deferred,
+            Self::Failed(_, kind) => *kind,
+            //mostly a dead connection
+            Self::Timeout(_) => FailureKind::Network,
+        }
+    }
+}
+
+//what a successful sync reports beside its success
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecReport {
+    pub status: Option<ExitStatus>,
+    pub counts: Option<SyncCounts>,
+}
+
+impl ExecReport {
+    pub fn with_status(status: ExitStatus) -> Self {
+        Self {
+            status: Some(status),
+            counts: None,
+        }
+    }
+
+    pub fn with_counts(mut self, counts: SyncCounts) -> Self {
+        self.counts = Some(counts);
+        self
+    }
+
+    //the report of several commands, with the status of the last one
+    pub fn merge(&mut self, report: ExecReport) {
+        if report.status.is_some() {
+            self.status = report.status;
+        }
+        if let Some(counts) = report.counts {
+            self.counts.get_or_insert_default().add(counts);
+        }
+    }
+}
+
+//syncs the accounts one after another, a failed account does not stop the syncs of the others
+//and the first error is returned after them
+pub fn execute_accounts<A, F>(
+    accounts: impl IntoIterator<Item = A>,
+    mut execute: F,
+) -> Result<ExecReport, ExecError>
+where
+    A: Display,
+    F: FnMut(&A) -> Result<ExecReport, ExecError>,
+{
+    let mut report = ExecReport::default();
+    let mut error = None;
+    for account in accounts {
+        match execute(&account) {
+            Ok(account_report) => report.merge(account_report),
+            Err(err) => {
+                log::warn!("sync of {} failed: {}", account, err);
+                error.get_or_insert(err);
+            }
+        }
+    }
+    match error {
+        Some(err) => Err(err),
+        None => Ok(report),
+    }
+}
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+//how long the output of a killed command is read before it is given up
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    //only disk time not needed by others
+    Idle,
+    //the level from 0 (highest) to 7 (lowest)
+    BestEffort(u8),
+}
+
+//the cpu and io priority of the commands, like nice and ionice
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Priority {
+    pub nice: Option<i32>,
+    pub io: Option<IoPriority>,
+}
+
+//the class is in the upper bits of the io priority of ioprio_set
+#[cfg(target_os = "linux")]
+const IOPRIO_CLASS_SHIFT: i32 = 13;
+#[cfg(target_os = "linux")]
+const IOPRIO_WHO_PROCESS: i32 = 1;
+
+impl Priority {
+    //the priority is set by the child before the exec, so it applies to its children as well
+    #[cfg(target_os = "linux")]
+    fn apply(self, command: &mut Command) {
+        //only async signal safe calls between fork and exec
+        let set = move || {
+            if let Some(nice) = self.nice {
+                if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(io) = self.io {
+                let (class, level) = match io {
+                    IoPriority::Idle => (3, 0),
+                    IoPriority::BestEffort(level) => (2, i32::from(level.min(7))),
+                };
+                let ioprio = class << IOPRIO_CLASS_SHIFT | level;
+                if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) }
+                    == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        };
+        unsafe { command.pre_exec(set) };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn apply(self, _command: &mut Command) {
+        log::warn!("priorities of the syncs are only supported on linux");
+    }
+}
+
+//the exit status and the lines of stdout and stderr, without lines on passthrough
+pub(crate) struct CommandOutput {
+    pub status: ExitStatus,
+    pub lines: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+//runs the commands of the executors, their output is logged line by line with the label
+#[derive(Debug, Clone, Default)]
+pub struct CommandRunner {
+    //a hanging command, e.g. by a dead connection, is killed after the timeout
+    pub timeout: Option<Duration>,
+    //the output is written to the inherited stdout and stderr instead of the log
+    pub passthrough: bool,
+    //the output is kept and only logged as warnings if the command fails
+    pub quiet: bool,
+    //the priority of all commands, that of an account takes precedence
+    pub priority: Option<Priority>,
+    pub account_priorities: HashMap<String, Priority>,
+    //variables set in the environment of the commands of an account, e.g. for gpg or a proxy
+    pub environments: HashMap<String, HashMap<String, String>>,
+    //the users the commands of the accounts run as
+    pub users: HashMap<String, LocalUser>,
+    //suppresses the watcher events caused by the running syncs
+    pub suppressor: Option<SyncSuppressor>,
+}
+
+impl CommandRunner {
+    pub fn set_priority(&mut self, account: Option<&str>, priority: Priority) {
+        match account {
+            Some(account) => {
+                self.account_priorities.insert(account.to_owned(), priority);
+            }
+            None => self.priority = Some(priority),
+        }
+    }
+
+    pub fn set_env(&mut self, account: &str, name: &str, value: &str) {
+        self.environments
+            .entry(account.to_owned())
+            .or_default()
+            .insert(name.to_owned(), value.to_owned());
+    }
+
+    //sets the priority, the user and the environment of the account on the command. the priority
+    //comes first, a negative nice value can only be set before root drops its privileges
+    pub fn prepare(&self, command: &mut Command, account: Option<&str>) {
+        let priority = account
+            .and_then(|account| self.account_priorities.get(account))
+            .or(self.priority.as_ref());
+        if let Some(priority) = priority {
+            priority.apply(command);
+        }
+        if let Some(user) = account.and_then(|account| self.users.get(account)) {
+            user.apply(command);
+        }
+        if let Some(environment) = account.and_then(|account| self.environments.get(account)) {
+            command.envs(environment);
+        }
+    }
+
+    //runs the sync of the task, its writes are not reported by the watchers
+    pub fn suppressed<T>(&self, task: &MailUpdaterTask, sync: impl FnOnce() -> T) -> T {
+        if let Some(suppressor) = &self.suppressor {
+            suppressor.begin(task);
+        }
+        let result = sync();
+        if let Some(suppressor) = &self.suppressor {
+            suppressor.end(task);
+        }
+        result
+    }
+
+    pub(crate) fn run(
+        &self,
+        mut command: Command,
+        label: &str,
+    ) -> Result<CommandOutput, ExecError> {
+        //its own process group, so a timeout kills the children of a shell or helper as well
+        #[cfg(target_os = "linux")]
+        command.process_group(0);
+        if self.passthrough {
+            command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        } else {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let mut child = command.spawn()?;
+        let level = |level| (!self.quiet).then_some(level);
+        let readers =
+            [
+                child.stdout.take().map(|stdout| {
+                    Self::log_lines(stdout, label.to_owned(), level(log::Level::Info))
+                }),
+                child.stderr.take().map(|stderr| {
+                    Self::log_lines(stderr, label.to_owned(), level(log::Level::Warn))
+                }),
+            ];
+        let program = command.get_program().to_string_lossy();
+        let status = self.wait(&program, child);
+        //after a kill the output may be kept opened by the children of the command, e.g. a
+        //detached ssh master, so the output read until then is used
+        if status.is_err() {
+            let started = Instant::now();
+            while readers.iter().flatten().any(|reader| !reader.is_finished())
+                && started.elapsed() < KILLED_OUTPUT_GRACE
+            {
+                sleep(WAIT_INTERVAL);
+            }
+        }
+        let [lines, errors] = readers.map(|reader| {
+            reader
+                .filter(|reader| status.is_ok() || reader.is_finished())
+                .and_then(|reader| reader.join().ok())
+                .unwrap_or_default()
+        });
+        //the output of a hanging command is needed the most
+        if self.quiet && !status.as_ref().is_ok_and(ExitStatus::success) {
+            for line in lines.iter().chain(&errors) {
+                log::warn!("[{}] {}", label, line);
+            }
+        }
+        let status = status?;
+        Ok(CommandOutput {
+            status,
+            lines,
+            errors,
+        })
+    }
+
+    //without a level the lines are only collected
+    fn log_lines<R>(output: R, label: String, level: Option<log::Level>) -> JoinHandle<Vec<String>>
+    where
+        R: Read + Send + 'static,
+    {
+        thread::spawn(move || {
+            let mut lines = Vec::new();
+            for line in BufReader::new(output).lines() {
+                match line {
+                    Ok(line) => {
+                        if let Some(level) = level {
+                            log::log!(level, "[{}] {}", label, line);
+                        }
+                        lines.push(line);
+                    }
+                    Err(err) => {
+                        log::warn!("could not read output of [{}]: {}", label, err);
+                        break;
+                    }
+                }
+            }
+            lines
+        })
+    }
+
+    #[cfg(target_os = "linux")]
+    fn kill(child: &mut Child) -> io::Result<()> {
+        if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn kill(child: &mut Child) -> io::Result<()> {
+        child.kill()
+    }
+
+    fn wait(&self, program: &str, mut child: Child) -> Result<ExitStatus, ExecError> {
+        let Some(timeout) = self.timeout else {
+            return Ok(child.wait()?);
+        };
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if started.elapsed() >= timeout {
+                log::error!("command {} hangs, kill it after {:?}", program, timeout);
+                Self::kill(&mut child)?;
+                child.wait()?;
+                return Err(ExecError::Timeout(timeout));
+            }
+            sleep(WAIT_INTERVAL);
+        }
+    }
+}
+
+//the options of the executors running their commands with a runner
+pub trait RunnerOptions: Sized {
+    fn runner_mut(&mut self) -> &mut CommandRunner;
+
+    fn with_suppressor(mut self, suppressor: SyncSuppressor) -> Self {
+        self.runner_mut().suppressor = Some(suppressor);
+        self
+    }
+
+    //a hanging command, e.g. by a dead connection, is killed after the timeout
+    fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.runner_mut().timeout = Some(timeout);
+        self
+    }
+
+    //the output is written to the inherited stdout and stderr instead of the log
+    fn with_passthrough(mut self) -> Self {
+        self.runner_mut().passthrough = true;
+        self
+    }
+
+    //the output is only logged if the command fails
+    fn with_quiet(mut self) -> Self {
+        self.runner_mut().quiet = true;
+        self
+    }
+
+    //the priority of the commands of the account or of all commands
+    fn with_priority(mut self, account: Option<&str>, priority: Priority) -> Self {
+        self.runner_mut().set_priority(account, priority);
+        self
+    }
+
+    //a variable in the environment of the commands of the account
+    fn with_env(mut self, account: &str, name: &str, value: &str) -> Self {
+        self.runner_mut().set_env(account, name, value);
+        self
+    }
+
+    //the commands of the account run with the ids and home of the user
+    fn with_user(mut self, account: &str, user: LocalUser) -> Self {
+        self.runner_mut().users.insert(account.to_owned(), user);
+        self
+    }
+}
+
+//runs the sync of a task, called by the updater in a thread per task
+pub trait Executor: Send + Sync {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError>;
+
+    //a sync of all accounts but the excluded ones, none if the executor can only sync all of them
+    fn execute_except(
+        &self,
+        _task: &MailUpdaterTask,
+        _excluded: &[String],
+    ) -> Option<Result<ExecReport, ExecError>> {
+        None
+    }
+}
+
+//the executor chosen by the config
+impl Executor for Box<dyn Executor> {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.as_ref().execute(task)
+    }
+
+    fn execute_except(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Option<Result<ExecReport, ExecError>> {
+        self.as_ref().execute_except(task, excluded)
+    }
+}
+
+//plain closures, e.g. for tests or embedding
+impl<F, E> Executor for F
+where
+    F: Fn(&MailUpdaterTask) -> Result<(), E> + Send + Sync,
+    E: Display,
+{
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self(task)
+            .map(|_| ExecReport::default())
+            .map_err(|err| ExecError::Other(err.to_string()))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{
+        fs,
+        os::unix::process::ExitStatusExt,
+        process::{Command, ExitStatus},
+        sync::Mutex,
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::TempDir;
+
+    use super::{
+        execute_accounts, CommandRunner, ExecError, ExecReport, FailureKind, IoPriority, Priority,
+        SyncCounts,
+    };
+
+    //the warnings logged by the tests
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct WarningLogger;
+
+    impl log::Log for WarningLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn it_should_sync_the_other_accounts_after_a_failure() {
+        let mut synced = Vec::new();
+        let result = execute_accounts(["archive", "private", "work"], |account| {
+            synced.push(account.to_string());
+            match *account {
+                "archive" => Err(ExecError::Failed(
+                    ExitStatus::from_raw(1 << 8),
+                    FailureKind::Network,
+                )),
+                "private" => Err(ExecError::Other("failed".to_owned())),
+                _ => Ok(ExecReport::default()),
+            }
+        });
+        assert!(matches!(
+            result,
+            Err(ExecError::Failed(_, FailureKind::Network))
+        ));
+        assert_eq!(vec!["archive", "private", "work"], synced);
+        let counts = |pulled| SyncCounts {
+            pulled,
+            ..Default::default()
+        };
+        let report = execute_accounts(["private", "work"], |_| {
+            Ok(ExecReport::with_status(ExitStatus::from_raw(0)).with_counts(counts(2)))
+        })
+        .unwrap();
+        assert_eq!(Some(counts(4)), report.counts);
+        assert!(report.status.is_some_and(|status| status.success()));
+    }
+
+    #[test]
+    fn it_should_kill_the_children_on_timeout() {
+        let dir = TempDir::new().unwrap();
+        let pid_file = dir.path().join("pid");
+        let runner = CommandRunner {
+            timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("sleep 30 & echo $! > {}; wait", pid_file.display()));
+        assert!(matches!(
+            runner.run(command, "sh"),
+            Err(ExecError::Timeout(_))
+        ));
+        sleep(Duration::from_millis(200));
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        //gone or a zombie, if nobody reaps the orphan
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+    }
+
+    #[test]
+    fn it_should_log_the_output_of_hanging_quiet_commands() {
+        let _ = log::set_logger(&WarningLogger);
+        log::set_max_level(log::LevelFilter::Warn);
+        let runner = CommandRunner {
+            timeout: Some(Duration::from_millis(300)),
+            quiet: true,
+            ..Default::default()
+        };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo connecting; echo no response >&2; sleep 30");
+        let started = Instant::now();
+        assert!(matches!(
+            runner.run(command, "hanging"),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let warnings = WARNINGS.lock().unwrap();
+        assert!(warnings.contains(&"[hanging] connecting".to_owned()));
+        assert!(warnings.contains(&"[hanging] no response".to_owned()));
+    }
+
+    #[test]
+    fn it_should_run_commands_with_the_priority() {
+        let mut runner = CommandRunner::default();
+        runner.set_priority(
+            Some("archive"),
+            Priority {
+                nice: Some(19),
+                io: Some(IoPriority::Idle),
+            },
+        );
+        let nice = |account: &str| {
+            let mut command = Command::new("nice");
+            runner.prepare(&mut command, Some(account));
+            runner.run(command, account).unwrap().lines
+        };
+        assert_eq!(vec!["19".to_owned()], nice("archive"));
+        assert_ne!(vec!["19".to_owned()], nice("work"));
+    }
+}
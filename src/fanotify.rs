@@ -0,0 +1,414 @@
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs, io,
+    mem::size_of,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    thread,
+};
+
+use notify::{
+    event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode},
+    Event, EventKind,
+};
+
+//watches the whole filesystem of the path with fanotify, which reports the process causing a
+//change. the events name the directory and the entry, so created, removed and moved mails are
+//reported like those of inotify. fanotify needs CAP_SYS_ADMIN and linux 5.9.
+pub struct FanotifyWatcher {
+    stop: Arc<AtomicBool>,
+}
+
+//an event with the handle of the directory and the name of the changed entry
+#[derive(Debug, PartialEq, Eq)]
+struct RawEvent {
+    mask: u64,
+    pid: i32,
+    dir_handle: Option<Vec<u8>>,
+    name: Option<OsString>,
+}
+
+//the kind of the changes of the mask. several changes of an entry may be merged into one event,
+//e.g. of a mail created and moved to cur, so the existence of the entry tells the last one.
+fn event_kind(mask: u64, exists: bool) -> Option<EventKind> {
+    let added = mask & (libc::FAN_CREATE | libc::FAN_MOVED_TO) != 0;
+    let gone = mask & (libc::FAN_DELETE | libc::FAN_MOVED_FROM) != 0;
+    if added && (exists || !gone) {
+        Some(match mask & libc::FAN_CREATE != 0 {
+            true => EventKind::Create(CreateKind::Any),
+            false => EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+        })
+    } else if gone {
+        Some(match mask & libc::FAN_DELETE != 0 {
+            true => EventKind::Remove(RemoveKind::Any),
+            false => EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+        })
+    } else if mask & (libc::FAN_MODIFY | libc::FAN_CLOSE_WRITE) != 0 {
+        Some(EventKind::Modify(ModifyKind::Data(DataChange::Any)))
+    } else {
+        None
+    }
+}
+
+//the events of a read of the fanotify fd
+fn parse_events(buffer: &[u8]) -> io::Result<Vec<RawEvent>> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while offset + size_of::<libc::fanotify_event_metadata>() <= buffer.len() {
+        let metadata: libc::fanotify_event_metadata =
+            unsafe { ptr::read_unaligned(buffer.as_ptr().add(offset).cast()) };
+        let event_len = metadata.event_len as usize;
+        if event_len == 0 || offset + event_len > buffer.len() {
+            break;
+        }
+        if metadata.vers != libc::FANOTIFY_METADATA_VERSION {
+            return Err(io::Error::other("unsupported fanotify version"));
+        }
+        let info = &buffer[offset + metadata.metadata_len as usize..offset + event_len];
+        offset += event_len;
+        if metadata.mask & libc::FAN_Q_OVERFLOW != 0 {
+            log::warn!("fanotify queue overflow, events are lost");
+            continue;
+        }
+        let (dir_handle, name) = parse_dir_info(info).unzip();
+        events.push(RawEvent {
+            mask: metadata.mask,
+            pid: metadata.pid,
+            dir_handle,
+            name: name.flatten(),
+        });
+    }
+    Ok(events)
+}
+
+//the file handle of the directory and the name of the entry, without a name for changes of the
+//directory itself
+fn parse_dir_info(mut info: &[u8]) -> Option<(Vec<u8>, Option<OsString>)> {
+    let header_len = size_of::<libc::fanotify_event_info_header>();
+    //the fsid is followed by the struct file_handle with its size and type
+    let handle_offset = size_of::<libc::fanotify_event_info_fid>();
+    while info.len() >= header_len {
+        let header: libc::fanotify_event_info_header =
+            unsafe { ptr::read_unaligned(info.as_ptr().cast()) };
+        let len = header.len as usize;
+        if len < header_len || len > info.len() {
+            return None;
+        }
+        let (record, rest) = info.split_at(len);
+        info = rest;
+        if header.info_type != libc::FAN_EVENT_INFO_TYPE_DFID_NAME
+            && header.info_type != libc::FAN_EVENT_INFO_TYPE_DFID
+        {
+            continue;
+        }
+        let handle_bytes = u32::from_ne_bytes(
+            record
+                .get(handle_offset..handle_offset + 4)?
+                .try_into()
+                .ok()?,
+        ) as usize;
+        let handle_end = handle_offset + 8 + handle_bytes;
+        let handle = record.get(handle_offset..handle_end)?.to_vec();
+        let name = match header.info_type == libc::FAN_EVENT_INFO_TYPE_DFID_NAME {
+            true => CStr::from_bytes_until_nul(record.get(handle_end..)?)
+                .ok()
+                .map(|name| OsStr::from_bytes(name.to_bytes()).to_owned())
+                .filter(|name| name != "."),
+            false => None,
+        };
+        return Some((handle, name));
+    }
+    None
+}
+
+impl FanotifyWatcher {
+    pub fn new(path: &Path, events_tx: Sender<Result<Event, notify::Error>>) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_REPORT_DFID_NAME,
+                (libc::O_RDONLY | libc::O_LARGEFILE) as u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        //the events of directory entries are only reported by marks of the filesystem
+        let marked = unsafe {
+            libc::fanotify_mark(
+                fd.as_raw_fd(),
+                libc::FAN_MARK_ADD | libc::FAN_MARK_FILESYSTEM,
+                libc::FAN_MODIFY
+                    | libc::FAN_CLOSE_WRITE
+                    | libc::FAN_CREATE
+                    | libc::FAN_DELETE
+                    | libc::FAN_MOVED_FROM
+                    | libc::FAN_MOVED_TO
+                    | libc::FAN_ONDIR,
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+            )
+        };
+        if marked < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        //the handles of the events are opened relative to the filesystem of the path
+        let mount_fd = unsafe {
+            libc::open(
+                c_path.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if mount_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mount_fd = unsafe { OwnedFd::from_raw_fd(mount_fd) };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(err) = Self::read_events(&fd, &mount_fd, &thread_stop, &events_tx) {
+                let _ = events_tx.send(Err(notify::Error::io(err)));
+            }
+        });
+        Ok(Self { stop })
+    }
+
+    //the path of the directory is gone, if it was removed meanwhile
+    fn dir_path(mount_fd: &OwnedFd, handle: &[u8]) -> Option<PathBuf> {
+        let mut handle = handle.to_vec();
+        let dir_fd = unsafe {
+            libc::syscall(
+                libc::SYS_open_by_handle_at,
+                mount_fd.as_raw_fd(),
+                handle.as_mut_ptr(),
+                libc::O_PATH | libc::O_CLOEXEC,
+            )
+        };
+        if dir_fd < 0 {
+            return None;
+        }
+        let dir_fd = unsafe { OwnedFd::from_raw_fd(dir_fd as RawFd) };
+        fs::read_link(format!("/proc/self/fd/{}", dir_fd.as_raw_fd())).ok()
+    }
+
+    fn read_events(
+        fd: &OwnedFd,
+        mount_fd: &OwnedFd,
+        stop: &AtomicBool,
+        events_tx: &Sender<Result<Event, notify::Error>>,
+    ) -> io::Result<()> {
+        let mut buffer = [0u8; 8192];
+        while !stop.load(Ordering::Relaxed) {
+            //poll with timeout to notice the stop flag
+            let mut pollfd = libc::pollfd {
+                fd: fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, 500) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                continue;
+            }
+            let len =
+                unsafe { libc::read(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            for raw in parse_events(&buffer[..len as usize])? {
+                let Some(dir) = raw
+                    .dir_handle
+                    .as_ref()
+                    .and_then(|handle| Self::dir_path(mount_fd, handle))
+                else {
+                    continue;
+                };
+                let path = match &raw.name {
+                    Some(name) => dir.join(name),
+                    None => dir,
+                };
+                let Some(kind) = event_kind(raw.mask, path.exists()) else {
+                    continue;
+                };
+                let mut event = Event::new(kind)
+                    .add_path(path)
+                    .set_process_id(raw.pid as u32);
+                if let Ok(comm) = fs::read_to_string(format!("/proc/{}/comm", raw.pid)) {
+                    event = event.set_info(comm.trim_end());
+                }
+                if events_tx.send(Ok(event)).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FanotifyWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, fs, mem::size_of, ptr, slice, sync::mpsc, time::Duration};
+
+    use notify::{
+        event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode},
+        EventKind,
+    };
+    use tempfile::TempDir;
+
+    use super::{event_kind, parse_events, FanotifyWatcher, RawEvent};
+
+    fn bytes<T>(value: &T) -> Vec<u8> {
+        unsafe { slice::from_raw_parts(ptr::from_ref(value).cast::<u8>(), size_of::<T>()) }.to_vec()
+    }
+
+    fn event(mask: u64, info: &[u8]) -> Vec<u8> {
+        let metadata_len = size_of::<libc::fanotify_event_metadata>();
+        let mut event = bytes(&libc::fanotify_event_metadata {
+            event_len: (metadata_len + info.len()) as u32,
+            vers: libc::FANOTIFY_METADATA_VERSION,
+            reserved: 0,
+            metadata_len: metadata_len as u16,
+            mask,
+            fd: libc::FAN_NOFD,
+            pid: 42,
+        });
+        event.extend_from_slice(info);
+        event
+    }
+
+    fn dir_info(handle: &[u8], name: &str) -> Vec<u8> {
+        let mut record = vec![libc::FAN_EVENT_INFO_TYPE_DFID_NAME, 0, 0, 0];
+        record.extend_from_slice(&[0; 8]);
+        record.extend_from_slice(&(handle.len() as u32).to_ne_bytes());
+        record.extend_from_slice(&1i32.to_ne_bytes());
+        record.extend_from_slice(handle);
+        record.extend_from_slice(name.as_bytes());
+        record.push(0);
+        //records are padded to 4 bytes
+        record.resize(record.len().next_multiple_of(4), 0);
+        let len = record.len() as u16;
+        record[2..4].copy_from_slice(&len.to_ne_bytes());
+        record
+    }
+
+    #[test]
+    fn it_should_parse_the_directory_entries_of_events() {
+        let mut buffer = event(
+            libc::FAN_CREATE | libc::FAN_CLOSE_WRITE,
+            &dir_info(&[1, 2, 3, 4, 5, 6, 7, 8], "1700000000.M1P2.host"),
+        );
+        buffer.extend(event(libc::FAN_MODIFY, &dir_info(&[8; 8], ".")));
+        buffer.extend(event(libc::FAN_Q_OVERFLOW, &[]));
+        let mut handle = 8u32.to_ne_bytes().to_vec();
+        handle.extend_from_slice(&1i32.to_ne_bytes());
+        assert_eq!(
+            vec![
+                RawEvent {
+                    mask: libc::FAN_CREATE | libc::FAN_CLOSE_WRITE,
+                    pid: 42,
+                    dir_handle: Some([&handle[..], &[1, 2, 3, 4, 5, 6, 7, 8]].concat()),
+                    name: Some(OsString::from("1700000000.M1P2.host")),
+                },
+                RawEvent {
+                    mask: libc::FAN_MODIFY,
+                    pid: 42,
+                    dir_handle: Some([&handle[..], &[8; 8]].concat()),
+                    name: None,
+                },
+            ],
+            parse_events(&buffer).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_map_the_masks_to_event_kinds() {
+        assert_eq!(
+            Some(EventKind::Create(CreateKind::Any)),
+            event_kind(libc::FAN_CREATE | libc::FAN_MODIFY, true)
+        );
+        assert_eq!(
+            Some(EventKind::Remove(RemoveKind::Any)),
+            event_kind(libc::FAN_DELETE | libc::FAN_ONDIR, false)
+        );
+        assert_eq!(
+            Some(EventKind::Modify(ModifyKind::Name(RenameMode::To))),
+            event_kind(libc::FAN_MOVED_TO, true)
+        );
+        assert_eq!(
+            Some(EventKind::Modify(ModifyKind::Name(RenameMode::From))),
+            event_kind(libc::FAN_MOVED_FROM, false)
+        );
+        assert_eq!(
+            Some(EventKind::Modify(ModifyKind::Data(DataChange::Any))),
+            event_kind(libc::FAN_CLOSE_WRITE, true)
+        );
+        assert_eq!(None, event_kind(libc::FAN_ONDIR, true));
+        //e.g. a mail written to tmp and moved to new
+        assert_eq!(
+            Some(EventKind::Modify(ModifyKind::Name(RenameMode::From))),
+            event_kind(
+                libc::FAN_CREATE | libc::FAN_CLOSE_WRITE | libc::FAN_MOVED_FROM,
+                false
+            )
+        );
+        assert_eq!(
+            Some(EventKind::Create(CreateKind::Any)),
+            event_kind(libc::FAN_DELETE | libc::FAN_CREATE, true)
+        );
+    }
+
+    #[test]
+    fn it_should_report_created_and_removed_mails() {
+        let dir = TempDir::new().unwrap();
+        let (events_tx, events_rx) = mpsc::channel();
+        //without CAP_SYS_ADMIN there is no fanotify
+        let Ok(_watcher) = FanotifyWatcher::new(dir.path(), events_tx) else {
+            return;
+        };
+        let dir = dir.path().canonicalize().unwrap();
+        let kept = dir.join("1700000000.M1P2.host");
+        let removed = dir.join("1700000001.M1P2.host");
+        fs::write(&kept, "Subject: kept\n").unwrap();
+        fs::write(&removed, "Subject: removed\n").unwrap();
+        fs::remove_file(&removed).unwrap();
+        //the filesystem is shared with other processes
+        let (mut kept_kinds, mut removed_kinds) = (Vec::new(), Vec::new());
+        while let Ok(Ok(event)) = events_rx.recv_timeout(Duration::from_secs(1)) {
+            if event.paths == [kept.clone()] {
+                kept_kinds.push(event.kind);
+            } else if event.paths == [removed.clone()] {
+                removed_kinds.push(event.kind);
+            }
+        }
+        assert_eq!(
+            Some(&EventKind::Create(CreateKind::Any)),
+            kept_kinds.first()
+        );
+        assert_eq!(
+            Some(&EventKind::Remove(RemoveKind::Any)),
+            removed_kinds.last()
+        );
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connectivity::ConnectivityCheck,
+    executor::{CommandRunner, ExecError, ExecReport, Executor, RunnerOptions},
+    updater::MailUpdaterTask,
+};
+
+//runs the hook commands around the syncs of an executor
+pub struct HookedExecutor<E: Executor> {
+    executor: E,
+    runner: CommandRunner,
+    //e.g. the imap server has to be reachable, otherwise the sync is deferred
+    checks: Vec<ConnectivityCheck>,
+    account_checks: HashMap<String, Vec<ConnectivityCheck>>,
+    //the commands refreshing the oauth2 tokens of the accounts with the lifetime of a token
+    token_refresh: HashMap<String, (String, Duration)>,
+    token_expiry: Mutex<HashMap<String, Instant>>,
+    pre_sync: Vec<String>,
+    account_pre_sync: HashMap<String, Vec<String>>,
+    post_sync: Vec<String>,
+    account_post_sync: HashMap<String, Vec<String>>,
+}
+
+//the hooks of the task with the task of their account, a sync of all accounts runs the hooks of
+//every account
+fn hooks<'a, T>(
+    global: &'a [T],
+    accounts: &'a HashMap<String, Vec<T>>,
+    task: &MailUpdaterTask,
+) -> Vec<(MailUpdaterTask, &'a T)> {
+    let accounts = match &task.specific_account {
+        Some(account) => accounts.get_key_value(account).into_iter().collect(),
+        None => accounts.iter().collect::<Vec<_>>(),
+    };
+    global
+        .iter()
+        .map(|hook| (task.clone(), hook))
+        .chain(accounts.into_iter().flat_map(|(account, hooks)| {
+            let task = MailUpdaterTask {
+                specific_account: Some(account.clone()),
+                ..task.clone()
+            };
+            hooks.iter().map(move |hook| (task.clone(), hook))
+        }))
+        .collect()
+}
+
+//a failed check or hook of an account only excludes it from a sync of all accounts, otherwise
+//the sync fails with the error
+fn exclude(
+    task: &MailUpdaterTask,
+    hook_task: &MailUpdaterTask,
+    err: ExecError,
+    excluded: &mut Vec<String>,
+) -> Result<(), ExecError> {
+    match (&task.specific_account, &hook_task.specific_account) {
+        (None, Some(account)) => {
+            log::warn!("exclude {} from the sync of all accounts: {}", account, err);
+            excluded.push(account.clone());
+            Ok(())
+        }
+        _ => Err(err),
+    }
+}
+
+fn is_excluded(hook_task: &MailUpdaterTask, excluded: &[String]) -> bool {
+    hook_task
+        .specific_account
+        .as_ref()
+        .is_some_and(|account| excluded.contains(account))
+}
+
+impl<E: Executor> HookedExecutor<E> {
+    pub fn new(executor: E) -> Self {
+        Self {
+            executor,
+            runner: CommandRunner::default(),
+            checks: Vec::new(),
+            account_checks: HashMap::new(),
+            token_refresh: HashMap::new(),
+            token_expiry: Mutex::new(HashMap::new()),
+            pre_sync: Vec::new(),
+            account_pre_sync: HashMap::new(),
+            post_sync: Vec::new(),
+            account_post_sync: HashMap::new(),
+        }
+    }
+
+    //checked before the pre sync hooks, the sync is deferred while offline. a sync of all accounts
+    //only excludes the account of the check
+    pub fn with_connectivity_check(
+        mut self,
+        account: Option<&str>,
+        check: ConnectivityCheck,
+    ) -> Self {
+        match account {
+            Some(account) => self
+                .account_checks
+                .entry(account.to_owned())
+                .or_default()
+                .push(check),
+            None => self.checks.push(check),
+        }
+        self
+    }
+
+    //the command runs before a sync once the last token expired, a failure fails the sync or
+    //excludes the account from a sync of all accounts
+    pub fn with_token_refresh(mut self, account: &str, command: &str, lifetime: Duration) -> Self {
+        self.token_refresh
+            .insert(account.to_owned(), (command.to_owned(), lifetime));
+        self
+    }
+
+    fn refresh_tokens(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &mut Vec<String>,
+    ) -> Result<(), ExecError> {
+        let mut accounts = match &task.specific_account {
+            Some(account) => self
+                .token_refresh
+                .get_key_value(account)
+                .into_iter()
+                .collect(),
+            None => self.token_refresh.iter().collect::<Vec<_>>(),
+        };
+        accounts.sort_unstable_by_key(|(account, _)| *account);
+        for (account, (command, lifetime)) in accounts {
+            if excluded.contains(account) {
+                continue;
+            }
+            let now = Instant::now();
+            let valid = self
+                .token_expiry
+                .lock()
+                .unwrap()
+                .get(account)
+                .is_some_and(|expiry| *expiry > now);
+            if valid {
+                continue;
+            }
+            log::info!("refresh the token of {}", account);
+            let account_task = MailUpdaterTask::new(Some(account.clone()), None);
+            if !self.run_hook(command, &account_task)? {
+                let err = ExecError::Other(format!("token refresh of {} failed", account));
+                exclude(task, &account_task, err, excluded)?;
+                continue;
+            }
+            self.token_expiry
+                .lock()
+                .unwrap()
+                .insert(account.clone(), now + *lifetime);
+        }
+        Ok(())
+    }
+
+    //a shell command run before the sync, a failure defers the sync or excludes its account
+    pub fn with_pre_sync(mut self, account: Option<&str>, command: &str) -> Self {
+        match account {
+            Some(account) => self
+                .account_pre_sync
+                .entry(account.to_owned())
+                .or_default()
+                .push(command.to_owned()),
+            None => self.pre_sync.push(command.to_owned()),
+        }
+        self
+    }
+
+    //a shell command run after a successful sync, of the account or of every sync
+    pub fn with_post_sync(mut self, account: Option<&str>, command: &str) -> Self {
+        match account {
+            Some(account) => self
+                .account_post_sync
+                .entry(account.to_owned())
+                .or_default()
+                .push(command.to_owned()),
+            None => self.post_sync.push(command.to_owned()),
+        }
+        self
+    }
+
+    fn run_hook(&self, command_line: &str, task: &MailUpdaterTask) -> Result<bool, ExecError> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(command_line)
+            .env(
+                "MAILWATCH_ACCOUNT",
+                task.specific_account.as_deref().unwrap_or_default(),
+            )
+            .env(
+                "MAILWATCH_MAILBOX",
+                task.specific_mailbox.as_deref().unwrap_or_default(),
+            );
+        self.runner
+            .prepare(&mut command, task.specific_account.as_deref());
+        Ok(self.runner.run(command, command_line)?.status.success())
+    }
+
+    //the accounts excluded from a sync of all accounts, e.g. one unreachable server does not defer
+    //the syncs of the others
+    fn pre_sync(&self, task: &MailUpdaterTask) -> Result<Vec<String>, ExecError> {
+        let mut excluded = Vec::new();
+        for (check_task, check) in hooks(&self.checks, &self.account_checks, task) {
+            if !is_excluded(&check_task, &excluded) && !check.is_online() {
+                let err = ExecError::Deferred(format!("{:?}", check));
+                exclude(task, &check_task, err, &mut excluded)?;
+            }
+        }
+        self.refresh_tokens(task, &mut excluded)?;
+        for (hook_task, hook) in hooks(&self.pre_sync, &self.account_pre_sync, task) {
+            if !is_excluded(&hook_task, &excluded) && !self.run_hook(hook, &hook_task)? {
+                exclude(
+                    task,
+                    &hook_task,
+                    ExecError::Deferred(hook.clone()),
+                    &mut excluded,
+                )?;
+            }
+        }
+        Ok(excluded)
+    }
+
+    //a failed hook is logged, the sync itself succeeded
+    fn post_sync(&self, task: &MailUpdaterTask, excluded: &[String]) {
+        for (task, hook) in hooks(&self.post_sync, &self.account_post_sync, task) {
+            if is_excluded(&task, excluded) {
+                continue;
+            }
+            match self.run_hook(hook, &task) {
+                Ok(true) => {}
+                Ok(false) => log::warn!("post sync hook {} failed", hook),
+                Err(err) => log::warn!("could not run post sync hook {}: {}", hook, err),
+            }
+        }
+    }
+}
+
+//the hooks of an account run with the priority, environment and user of its syncs
+impl<E: Executor> RunnerOptions for HookedExecutor<E> {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl<E: Executor> Executor for HookedExecutor<E> {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        let excluded = self.pre_sync(task)?;
+        let report = match excluded.is_empty() {
+            true => self.executor.execute(task),
+            false => self
+                .executor
+                .execute_except(task, &excluded)
+                .unwrap_or_else(|| {
+                    log::warn!("can not exclude {:?}, sync all accounts", excluded);
+                    self.executor.execute(task)
+                }),
+        }?;
+        self.post_sync(task, &excluded);
+        Ok(report)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        fs,
+        net::TcpListener,
+        sync::Mutex,
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::TempDir;
+
+    use super::HookedExecutor;
+    use crate::{
+        connectivity::ConnectivityCheck,
+        executor::{ExecError, ExecReport, Executor, RunnerOptions},
+        updater::MailUpdaterTask,
+    };
+
+    //records the accounts excluded from the syncs of all accounts
+    #[derive(Default)]
+    struct Excluding(Mutex<Vec<Vec<String>>>);
+
+    impl Executor for Excluding {
+        fn execute(&self, _: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+            self.0.lock().unwrap().push(Vec::new());
+            Ok(ExecReport::default())
+        }
+
+        fn execute_except(
+            &self,
+            _: &MailUpdaterTask,
+            excluded: &[String],
+        ) -> Option<Result<ExecReport, ExecError>> {
+            self.0.lock().unwrap().push(excluded.to_vec());
+            Some(Ok(ExecReport::default()))
+        }
+    }
+
+    #[test]
+    fn it_should_run_the_post_sync_hooks() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let hook = |name: &str| {
+            format!(
+                "echo {} $MAILWATCH_ACCOUNT $MAILWATCH_MAILBOX >> {}",
+                name,
+                out.display()
+            )
+        };
+        let executor = HookedExecutor::new(|task: &MailUpdaterTask| match task.specific_mailbox {
+            Some(_) => Err("failed"),
+            None => Ok(()),
+        })
+        .with_post_sync(None, &hook("index"))
+        .with_post_sync(Some("work"), &hook("work"));
+        executor
+            .execute(&MailUpdaterTask::new(Some("work".to_owned()), None))
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("private".to_owned()), None))
+            .unwrap();
+        assert!(executor
+            .execute(&MailUpdaterTask::new(
+                Some("work".to_owned()),
+                Some("INBOX".to_owned())
+            ))
+            .is_err());
+        executor.execute(&MailUpdaterTask::new(None, None)).unwrap();
+        assert_eq!(
+            "index work\nwork work\nindex private\nindex\nwork work\n",
+            fs::read_to_string(&out).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_run_the_hooks_with_the_environment_of_the_account() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let hook = format!("echo $MAILWATCH_ACCOUNT $SERVER >> {}", out.display());
+        let executor = HookedExecutor::new(|_: &MailUpdaterTask| Ok::<_, String>(()))
+            .with_pre_sync(None, &hook)
+            .with_env("work", "SERVER", "imap.work.example");
+        executor
+            .execute(&MailUpdaterTask::new(Some("work".to_owned()), None))
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("private".to_owned()), None))
+            .unwrap();
+        assert_eq!(
+            "work imap.work.example\nprivate\n",
+            fs::read_to_string(&out).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_defer_syncs_vetoed_by_pre_sync_hooks() {
+        let dir = TempDir::new().unwrap();
+        let unlocked = dir.path().join("unlocked");
+        let executor = HookedExecutor::new(|_: &MailUpdaterTask| Ok::<_, String>(()))
+            .with_pre_sync(Some("work"), &format!("test -e {}", unlocked.display()));
+        let task = MailUpdaterTask::new(Some("work".to_owned()), None);
+        assert!(matches!(
+            executor.execute(&task),
+            Err(ExecError::Deferred(_))
+        ));
+        assert!(executor
+            .execute(&MailUpdaterTask::new(Some("private".to_owned()), None))
+            .is_ok());
+        fs::write(&unlocked, "").unwrap();
+        assert!(executor.execute(&task).is_ok());
+    }
+
+    #[test]
+    fn it_should_kill_hanging_pre_sync_hooks() {
+        let executor = HookedExecutor::new(|_: &MailUpdaterTask| Ok::<_, String>(()))
+            .with_pre_sync(None, "sleep 5")
+            .with_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        assert!(matches!(
+            executor.execute(&MailUpdaterTask::new(Some("work".to_owned()), None)),
+            Err(ExecError::Timeout(_))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn it_should_defer_syncs_of_unreachable_servers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let executor = HookedExecutor::new(|_: &MailUpdaterTask| Ok::<_, String>(()))
+            .with_connectivity_check(Some("work"), ConnectivityCheck::Tcp(address));
+        let task = MailUpdaterTask::new(Some("work".to_owned()), Some("INBOX".to_owned()));
+        assert!(executor.execute(&task).is_ok());
+        drop(listener);
+        assert!(matches!(
+            executor.execute(&task),
+            Err(ExecError::Deferred(_))
+        ));
+    }
+
+    #[test]
+    fn it_should_exclude_unreachable_servers_from_syncs_of_all_accounts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let reachable = listener.local_addr().unwrap().to_string();
+        let unreachable = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let executor = HookedExecutor::new(Excluding::default())
+            .with_connectivity_check(Some("work"), ConnectivityCheck::Tcp(unreachable))
+            .with_connectivity_check(Some("private"), ConnectivityCheck::Tcp(reachable));
+        executor.execute(&MailUpdaterTask::new(None, None)).unwrap();
+        assert!(matches!(
+            executor.execute(&MailUpdaterTask::new(Some("work".to_owned()), None)),
+            Err(ExecError::Deferred(_))
+        ));
+        assert_eq!(
+            vec![vec!["work".to_owned()]],
+            *executor.executor.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_refresh_tokens_once_they_expire() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let executor = HookedExecutor::new(|_: &MailUpdaterTask| Ok::<_, String>(()))
+            .with_token_refresh(
+                "office",
+                &format!("echo refresh >> {}", out.display()),
+                Duration::from_millis(300),
+            )
+            .with_token_refresh("gmail", "false", Duration::from_secs(3600));
+        let task = |mailbox: &str| {
+            MailUpdaterTask::new(Some("office".to_owned()), Some(mailbox.to_owned()))
+        };
+        executor.execute(&task("INBOX")).unwrap();
+        executor.execute(&task("Sent")).unwrap();
+        assert_eq!("refresh\n", fs::read_to_string(&out).unwrap());
+        sleep(Duration::from_millis(400));
+        executor.execute(&task("INBOX")).unwrap();
+        assert_eq!("refresh\nrefresh\n", fs::read_to_string(&out).unwrap());
+        assert!(executor
+            .execute(&MailUpdaterTask::new(Some("gmail".to_owned()), None))
+            .is_err());
+    }
+    #[test]
+    fn it_should_exclude_accounts_with_failed_token_refreshes_from_syncs_of_all_accounts() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let executor = HookedExecutor::new(Excluding::default())
+            .with_token_refresh("gmail", "false", Duration::from_secs(3600))
+            .with_token_refresh(
+                "office",
+                &format!("echo refresh >> {}", out.display()),
+                Duration::from_secs(3600),
+            )
+            .with_post_sync(
+                None,
+                &format!("echo $MAILWATCH_ACCOUNT >> {}", out.display()),
+            )
+            .with_post_sync(Some("gmail"), &format!("echo gmail >> {}", out.display()));
+        executor.execute(&MailUpdaterTask::new(None, None)).unwrap();
+        assert_eq!(
+            vec![vec!["gmail".to_owned()]],
+            *executor.executor.0.lock().unwrap()
+        );
+        //the post sync hooks of the excluded account are not run
+        assert_eq!("refresh\n\n", fs::read_to_string(&out).unwrap());
+    }
+}
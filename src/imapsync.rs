@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    process::{Command, ExitStatus},
+};
+
+use crate::{
+    executor::{
+        execute_accounts, CommandRunner, ExecError, ExecReport, Executor, FailureKind,
+        RunnerOptions,
+    },
+    updater::MailUpdaterTask,
+};
+
+//a one-way mirror per account, its hosts and credentials are given by the arguments
+pub struct ImapSyncExecutor {
+    command: String,
+    args: Vec<String>,
+    accounts: HashMap<String, Vec<String>>,
+    runner: CommandRunner,
+}
+
+//the exit codes of imapsync
+fn classify_failure(status: ExitStatus) -> FailureKind {
+    match status.code() {
+        None | Some(6) => FailureKind::Interrupted,
+        Some(10) | Some(12) => FailureKind::Network,
+        Some(16) | Some(21) => FailureKind::Config,
+        Some(_) => FailureKind::Other,
+    }
+}
+
+impl ImapSyncExecutor {
+    pub fn new(command: &str, args: &[String]) -> Self {
+        Self {
+            command: command.to_owned(),
+            args: args.to_vec(),
+            accounts: HashMap::new(),
+            runner: CommandRunner::default(),
+        }
+    }
+
+    //e.g. --host1, --user1 and --passfile1 of the account
+    pub fn with_account(mut self, account: &str, args: &[String]) -> Self {
+        self.accounts.insert(account.to_owned(), args.to_vec());
+        self
+    }
+
+    fn task_args(&self, account: &str, mailbox: Option<&str>) -> Result<Vec<String>, ExecError> {
+        let account_args = self.accounts.get(account).ok_or_else(|| {
+            ExecError::Other(format!("no imapsync arguments for account {}", account))
+        })?;
+        let mut args = self.args.clone();
+        args.extend(account_args.iter().cloned());
+        if let Some(mailbox) = mailbox {
+            args.extend(["--folder".to_owned(), mailbox.to_owned()]);
+        }
+        Ok(args)
+    }
+
+    fn sync_account(&self, account: &str, mailbox: Option<&str>) -> Result<ExecReport, ExecError> {
+        let args = self.task_args(account, mailbox)?;
+        log::info!("execute imapsync for {} {:?}", account, mailbox);
+        let mut command = Command::new(&self.command);
+        command.args(args);
+        let label = match mailbox {
+            Some(mailbox) => format!("{}:{}", account, mailbox),
+            None => account.to_owned(),
+        };
+        self.runner.prepare(&mut command, Some(account));
+        let output = self.runner.run(command, &label)?;
+        if !output.status.success() {
+            return Err(ExecError::Failed(
+                output.status,
+                classify_failure(output.status),
+            ));
+        }
+        Ok(ExecReport::with_status(output.status))
+    }
+
+    fn execute_command(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        match &task.specific_account {
+            Some(account) => self.sync_account(account, task.specific_mailbox.as_deref()),
+            //every configured account one after another
+            None => {
+                let mut accounts = self.accounts.keys().collect::<Vec<_>>();
+                accounts.sort_unstable();
+                execute_accounts(accounts, |account| self.sync_account(account, None))
+            }
+        }
+    }
+}
+
+impl RunnerOptions for ImapSyncExecutor {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl Executor for ImapSyncExecutor {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.runner.suppressed(task, || self.execute_command(task))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    use super::{classify_failure, ImapSyncExecutor};
+    use crate::executor::FailureKind;
+
+    #[test]
+    fn it_should_build_the_arguments_of_the_account() {
+        let executor = ImapSyncExecutor::new("imapsync", &["--nolog".to_owned()]).with_account(
+            "work",
+            &["--host1".to_owned(), "imap.example.org".to_owned()],
+        );
+        assert_eq!(
+            vec![
+                "--nolog",
+                "--host1",
+                "imap.example.org",
+                "--folder",
+                "INBOX"
+            ],
+            executor.task_args("work", Some("INBOX")).unwrap()
+        );
+        assert!(executor.task_args("private", None).is_err());
+        let exit = |code: i32| ExitStatus::from_raw(code << 8);
+        assert_eq!(FailureKind::Network, classify_failure(exit(10)));
+        assert_eq!(FailureKind::Config, classify_failure(exit(16)));
+        assert_eq!(FailureKind::Other, classify_failure(exit(111)));
+    }
+}
@@ -1,5 +1,24 @@
-pub mod watcher;
-pub mod updater;
+pub mod accounts;
+pub mod connectivity;
+pub mod disabled;
+pub mod executor;
+#[cfg(target_os = "linux")]
+pub mod fanotify;
+pub mod hooks;
+pub mod imapsync;
+pub mod logwatcher;
+pub mod maintenance;
 pub mod mbsync;
+pub mod mbsyncrc;
+pub mod offlineimap;
+pub mod power;
+pub mod pull;
+pub mod push;
+pub mod shell;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod suppressor;
 pub mod timer;
-
+pub mod updater;
+pub mod user;
+pub mod watcher;
@@ -0,0 +1,305 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::watcher::{wait_for_event, wait_for_events, FileWatcherEvent, FileWatcherEventKind};
+
+#[derive(Debug, Error)]
+pub enum LogWatcherError {
+    #[error("IO-Error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid line pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("line pattern {0} has no account group")]
+    MissingAccountGroup(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum LogSource {
+    File(PathBuf),
+    //the unit of the dovecot service
+    Journald(String),
+}
+
+//lines of the mail_log plugin, the delivery of lda/lmtp and the delivery by sieve.
+//the mailbox names are logged utf8 decoded.
+static LINE_REGEXES: Lazy<Vec<Regex>> = Lazy::new(|| {
+    vec![
+        Regex::new(r"\w+\((?P<account>[^)]+)\)(?:<[^>]*>)*: (?P<event>[a-z_]+): (?:.*, )?box=(?P<mailbox>[^,]+)").unwrap(),
+        Regex::new(r"(?:lmtp|lda)\((?P<account>[^)]+)\).*: saved mail to (?P<mailbox>.+)$").unwrap(),
+        Regex::new(r"(?:lmtp|lda)\((?P<account>[^)]+)\).*: stored mail into mailbox '(?P<mailbox>[^']+)'").unwrap(),
+    ]
+});
+
+const TAIL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct LogWatcherOptions {
+    line_patterns: Vec<Regex>,
+}
+
+impl Default for LogWatcherOptions {
+    fn default() -> Self {
+        Self {
+            line_patterns: LINE_REGEXES.clone(),
+        }
+    }
+}
+
+impl LogWatcherOptions {
+    //replaces the default patterns, the named groups event and mailbox are optional
+    pub fn with_line_pattern(mut self, pattern: &str) -> Result<Self, LogWatcherError> {
+        let regex = Regex::new(pattern)?;
+        if !regex.capture_names().any(|name| name == Some("account")) {
+            return Err(LogWatcherError::MissingAccountGroup(pattern.to_string()));
+        }
+        self.line_patterns = vec![regex];
+        Ok(self)
+    }
+}
+
+pub struct LogWatcher {
+    events: Receiver<FileWatcherEvent>,
+    stop: Arc<AtomicBool>,
+    child: Option<Child>,
+}
+
+impl LogWatcher {
+    pub fn new(source: LogSource) -> Result<Self, LogWatcherError> {
+        Self::with_options(source, LogWatcherOptions::default())
+    }
+
+    pub fn with_options(
+        source: LogSource,
+        options: LogWatcherOptions,
+    ) -> Result<Self, LogWatcherError> {
+        let (events_tx, events_rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut child = None;
+        match source {
+            LogSource::File(path) => {
+                //only new lines are of interest
+                let mut file = File::open(&path)?;
+                let position = file.seek(SeekFrom::End(0))?;
+                let thread_stop = stop.clone();
+                thread::spawn(move || {
+                    if let Err(err) =
+                        Self::tail_file(&path, file, position, &options, &thread_stop, &events_tx)
+                    {
+                        log::error!("could not read log {:?}: {}", path, err);
+                    }
+                });
+            }
+            LogSource::Journald(unit) => {
+                let mut journalctl = Command::new("journalctl")
+                    .args(["--follow", "--lines=0", "--output=cat", "--unit", &unit])
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+                let stdout = journalctl.stdout.take().unwrap();
+                child = Some(journalctl);
+                thread::spawn(move || {
+                    for line in BufReader::new(stdout).lines() {
+                        match line {
+                            Ok(line) => {
+                                if !Self::send_line(&line, Path::new(""), &options, &events_tx) {
+                                    return;
+                                }
+                            }
+                            Err(err) => {
+                                log::error!("could not read journal of {}: {}", unit, err);
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        }
+        Ok(Self {
+            events: events_rx,
+            stop,
+            child,
+        })
+    }
+
+    pub fn wait_for_event(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<FileWatcherEvent, RecvTimeoutError> {
+        wait_for_event(&self.events, timeout)
+    }
+
+    //waits like wait_for_event and then drains the pending events, up to max
+    pub fn wait_for_events(
+        &self,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<FileWatcherEvent>, RecvTimeoutError> {
+        wait_for_events(&self.events, max, timeout)
+    }
+
+    pub fn parse_line(
+        line: &str,
+        path: &Path,
+        options: &LogWatcherOptions,
+    ) -> Option<FileWatcherEvent> {
+        options.line_patterns.iter().find_map(|regex| {
+            let captures = regex.captures(line)?;
+            let kind = match captures.name("event").map(|event| event.as_str()) {
+                Some("expunge") | Some("delete") => FileWatcherEventKind::Remove,
+                Some("save") | Some("copy") | Some("append") | None => FileWatcherEventKind::Create,
+                Some(_) => FileWatcherEventKind::Modify,
+            };
+            Some(FileWatcherEvent {
+                account: captures["account"].to_string(),
+                mailbox: captures
+                    .name("mailbox")
+                    .map(|mailbox| mailbox.as_str().trim().to_string()),
+                kind,
+                path: path.to_path_buf(),
+                renamed_from: None,
+                process: None,
+            })
+        })
+    }
+
+    //false if nobody listens anymore
+    fn send_line(
+        line: &str,
+        path: &Path,
+        options: &LogWatcherOptions,
+        events_tx: &Sender<FileWatcherEvent>,
+    ) -> bool {
+        match Self::parse_line(line, path, options) {
+            Some(event) => events_tx.send(event).is_ok(),
+            None => true,
+        }
+    }
+
+    fn tail_file(
+        path: &Path,
+        file: File,
+        mut position: u64,
+        options: &LogWatcherOptions,
+        stop: &AtomicBool,
+        events_tx: &Sender<FileWatcherEvent>,
+    ) -> io::Result<()> {
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        while !stop.load(Ordering::Relaxed) {
+            position += reader.read_line(&mut line)? as u64;
+            //partially written lines are completed by the next read
+            if line.ends_with('\n') {
+                if !Self::send_line(line.trim_end(), path, options, events_tx) {
+                    return Ok(());
+                }
+                line.clear();
+                continue;
+            }
+            sleep(TAIL_INTERVAL);
+            //start from the beginning of a rotated or truncated log
+            let rotated = match (fs::metadata(path), reader.get_ref().metadata()) {
+                (Ok(current), Ok(opened)) => {
+                    current.len() < position || !Self::same_file(&current, &opened)
+                }
+                _ => false,
+            };
+            if rotated {
+                log::info!("log {:?} rotated", path);
+                reader = BufReader::new(File::open(path)?);
+                position = 0;
+                line.clear();
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn same_file(current: &fs::Metadata, opened: &fs::Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        current.dev() == opened.dev() && current.ino() == opened.ino()
+    }
+
+    #[cfg(not(unix))]
+    fn same_file(_current: &fs::Metadata, _opened: &fs::Metadata) -> bool {
+        true
+    }
+}
+
+impl Drop for LogWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, fs::OpenOptions, io::Write};
+
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[rstest]
+    #[case("Oct 14 10:00:00 host dovecot: imap(frank)<4711><abc>: expunge: box=INBOX, uid=5, msgid=<1@x>, size=12", "frank", "INBOX", FileWatcherEventKind::Remove)]
+    #[case("Oct 14 10:00:00 host dovecot: imap(frank)<4711><abc>: flag_change: box=Sent, uid=5, msgid=<1@x>", "frank", "Sent", FileWatcherEventKind::Modify)]
+    #[case(
+        "dovecot: lmtp(frank)<4711><abc>: msgid=<1@x>: saved mail to Spam",
+        "frank",
+        "Spam",
+        FileWatcherEventKind::Create
+    )]
+    #[case(
+        "dovecot: lmtp(frank)<4711><abc>: sieve: msgid=<1@x>: stored mail into mailbox 'Späßchen'",
+        "frank",
+        "Späßchen",
+        FileWatcherEventKind::Create
+    )]
+    pub fn it_should_parse_log_lines(
+        #[case] line: &str,
+        #[case] account: &str,
+        #[case] mailbox: &str,
+        #[case] kind: FileWatcherEventKind,
+    ) {
+        let event =
+            LogWatcher::parse_line(line, Path::new("mail.log"), &LogWatcherOptions::default())
+                .unwrap();
+        assert_eq!(account, event.account);
+        assert_eq!(Some(mailbox), event.mailbox.as_deref());
+        assert_eq!(kind, event.kind);
+    }
+
+    #[rstest]
+    pub fn it_should_report_appended_log_lines() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let path = dir.path().join("mail.log");
+        fs::write(&path, "imap(old)<1><a>: expunge: box=INBOX, uid=1\n")?;
+        let watcher = LogWatcher::new(LogSource::File(path.clone()))?;
+        let mut file = OpenOptions::new().append(true).open(&path)?;
+        writeln!(file, "auth: some unrelated line")?;
+        writeln!(file, "imap(frank)<1><a>: expunge: box=INBOX, uid=1")?;
+        let event = watcher.wait_for_event(Some(Duration::from_secs(2)))?;
+        assert_eq!("frank", event.account);
+        assert_eq!(Some("INBOX"), event.mailbox.as_deref());
+        assert_eq!(path, event.path);
+        Ok(())
+    }
+}
@@ -1,41 +1,924 @@
 use std::{
+    collections::HashMap,
+    env,
+    error::Error,
     fs::{self, File},
     io::{self, Read},
     path::{Path, PathBuf},
+    process,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, RwLock,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant, SystemTime},
 };
 
+use chrono::Local;
+use clap::{Parser, Subcommand};
 use env_logger::Builder;
 use mailwatch::{
-    mbsync::MbSyncExecutor,
-    timer::run_timer,
-    updater::{MailUpdater, MailUpdaterTask},
-    watcher::{FileWatcher, FileWatcherError},
+    accounts::{AccountLayout, Accounts, MailboxFilter},
+    connectivity::ConnectivityCheck,
+    disabled::DisabledAccountsExecutor,
+    executor::{ExecError, Executor, IoPriority, Priority, RunnerOptions},
+    hooks::HookedExecutor,
+    imapsync::ImapSyncExecutor,
+    logwatcher::{LogSource, LogWatcher, LogWatcherOptions},
+    maintenance::MaintenanceJob,
+    mbsync::{LockMode, MbSyncExecutor},
+    mbsyncrc::MbSyncRc,
+    offlineimap::OfflineImapExecutor,
+    pull::{PullExecutor, PullTool},
+    push::run_push_receiver,
+    shell::ShellExecutor,
+    suppressor::SyncSuppressor,
+    timer::{run_timer, PowerPolicy, QuietHours, Schedule, StartupSync, TimerHandle, TimerOptions},
+    updater::{
+        MailUpdater, MailUpdaterOptions, MailUpdaterTask, TaskEvent, TaskPriority, TaskSource,
+    },
+    user::LocalUser,
+    watcher::{
+        FileWatcher, FileWatcherBackend, FileWatcherError, FileWatcherEvent, FileWatcherEventKind,
+        FileWatcherOptions, MailboxDecoder,
+    },
 };
 use serde::Deserialize;
 use thiserror::Error;
 
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum MailRootConfig {
+    Path(PathBuf),
+    Detailed {
+        path: PathBuf,
+        path_pattern: Option<String>,
+    },
+}
+
+impl MailRootConfig {
+    fn path(&self) -> &Path {
+        match self {
+            MailRootConfig::Path(path) => path,
+            MailRootConfig::Detailed { path, .. } => path,
+        }
+    }
+
+    fn path_pattern(&self) -> Option<&str> {
+        match self {
+            MailRootConfig::Path(_) => None,
+            MailRootConfig::Detailed { path_pattern, .. } => path_pattern.as_deref(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum MailRootsConfig {
+    Single(MailRootConfig),
+    Multiple(Vec<MailRootConfig>),
+}
+
 #[derive(Deserialize, Debug)]
 struct DovecotConfig {
-    dir: PathBuf,
+    dir: MailRootsConfig,
+    #[serde(default = "default_account_depth")]
+    account_depth: usize,
+    account_format: Option<String>,
+}
+
+impl DovecotConfig {
+    fn roots(&self) -> Vec<&MailRootConfig> {
+        match &self.dir {
+            MailRootsConfig::Single(root) => vec![root],
+            MailRootsConfig::Multiple(roots) => roots.iter().collect(),
+        }
+    }
+
+    fn account_layout(&self) -> AccountLayout {
+        AccountLayout::new(self.account_depth, self.account_format.as_deref())
+    }
+}
+
+fn default_account_depth() -> usize {
+    1
 }
 
 #[derive(Deserialize, Debug)]
 struct MbSyncConfig {
     command: String,
     args: Vec<String>,
+    //seconds until a hanging sync is killed
+    timeout: Option<u64>,
+    //the output of mbsync is not logged, but written to stdout and stderr
+    #[serde(default)]
+    passthrough: bool,
+    //the output is only logged of failed syncs
+    #[serde(default)]
+    quiet: bool,
+    #[serde(default)]
+    accounts: HashMap<String, MbSyncAccountConfig>,
+    //the values of placeholders like {config} in the arguments
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    //the mbsync channels of the account directories, if named otherwise
+    #[serde(default)]
+    channel_map: HashMap<String, String>,
+    //the mbsync groups of accounts, e.g. to sync several accounts with one group
+    #[serde(default)]
+    group_map: HashMap<String, String>,
+    //the config of mbsync, by default the one of -c in the arguments or ~/.mbsyncrc
+    mbsyncrc: Option<PathBuf>,
+    //options by the trigger of the sync, not used for the syncs of all accounts
+    #[serde(default)]
+    source_args: HashMap<TriggerConfig, Vec<String>>,
+    //wait for or defer syncs of channels locked by another mbsync
+    lock: Option<LockConfig>,
+    //by default in the runtime directory
+    lock_dir: Option<PathBuf>,
+    //runs mbsync in a transient scope of the systemd user instance
+    systemd_scope: Option<SystemdScopeConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SystemdScopeConfig {
+    //e.g. 512M
+    memory_max: Option<String>,
+    //e.g. 50%
+    cpu_quota: Option<String>,
+    //further properties like IOWeight=10
+    #[serde(default)]
+    properties: Vec<String>,
+}
+
+impl SystemdScopeConfig {
+    fn properties(&self) -> Vec<String> {
+        self.memory_max
+            .iter()
+            .map(|memory_max| format!("MemoryMax={}", memory_max))
+            .chain(
+                self.cpu_quota
+                    .iter()
+                    .map(|cpu_quota| format!("CPUQuota={}", cpu_quota)),
+            )
+            .chain(self.properties.iter().cloned())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum LockConfig {
+    Wait,
+    Defer,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+enum TriggerConfig {
+    Timer,
+    Watcher,
+    Logwatcher,
+    Push,
+    Manual,
+    Startup,
+}
+
+impl TriggerConfig {
+    fn source(self) -> TaskSource {
+        match self {
+            Self::Timer => TaskSource::Timer,
+            Self::Watcher => TaskSource::FileWatch,
+            Self::Logwatcher => TaskSource::LogWatch,
+            Self::Push => TaskSource::Push,
+            Self::Manual => TaskSource::Manual,
+            Self::Startup => TaskSource::Startup,
+        }
+    }
+}
+
+impl MbSyncConfig {
+    fn lock_dir(&self) -> PathBuf {
+        self.lock_dir.clone().unwrap_or_else(|| {
+            dirs::runtime_dir()
+                .unwrap_or_else(env::temp_dir)
+                .join("mailwatch")
+                .join("locks")
+        })
+    }
+
+    fn mbsyncrc(&self) -> Option<PathBuf> {
+        if let Some(mbsyncrc) = &self.mbsyncrc {
+            return Some(mbsyncrc.clone());
+        }
+        let configured = self
+            .args
+            .windows(2)
+            .find(|args| args[0] == "-c" || args[0] == "--config")
+            .map(|args| PathBuf::from(&args[1]));
+        match configured {
+            //a placeholder differs between the accounts
+            Some(path) if path.to_string_lossy().contains('{') => None,
+            Some(path) => Some(path),
+            None => [
+                dirs::home_dir().map(|home| home.join(".mbsyncrc")),
+                dirs::config_dir().map(|config| config.join("isyncrc")),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|path| path.exists()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExecutorKind {
+    #[default]
+    Mbsync,
+    Offlineimap,
+    Imapsync,
+    Getmail,
+    Fetchmail,
+    Shell,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+struct OfflineImapConfig {
+    command: String,
+    args: Vec<String>,
+    timeout: Option<u64>,
+    passthrough: bool,
+    quiet: bool,
+}
+
+impl Default for OfflineImapConfig {
+    fn default() -> Self {
+        Self {
+            command: "offlineimap".to_owned(),
+            args: Vec::new(),
+            timeout: None,
+            passthrough: false,
+            quiet: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ImapSyncConfig {
+    #[serde(default = "default_imapsync_command")]
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    //the hosts and credentials of the accounts, only those are synced
+    accounts: HashMap<String, ImapSyncAccountConfig>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(default)]
+    quiet: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImapSyncAccountConfig {
+    args: Vec<String>,
+}
+
+fn default_imapsync_command() -> String {
+    "imapsync".to_owned()
+}
+
+//getmail or fetchmail, run with the rc file of the account
+#[derive(Deserialize, Debug)]
+struct PullConfig {
+    command: Option<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    rc_files: HashMap<String, PathBuf>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(default)]
+    quiet: bool,
+}
+
+//command lines with the placeholders {account} and {mailbox}
+#[derive(Deserialize, Debug)]
+struct ShellConfig {
+    command: String,
+    account_command: Option<String>,
+    all_command: Option<String>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    passthrough: bool,
+    #[serde(default)]
+    quiet: bool,
+}
+
+//overrides of the command for an account, the others are used by default
+#[derive(Deserialize, Debug)]
+struct MbSyncAccountConfig {
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    //the mbsync names of the mailboxes of dovecot, e.g. Sent = "Sent Messages"
+    #[serde(default)]
+    mailboxes: HashMap<String, String>,
 }
 
 #[derive(Deserialize, Debug)]
 struct TimerConfig {
-    inboxes: u64,
-    all: u64,
+    inboxes: ScheduleConfig,
+    all: ScheduleConfig,
+    //the all interval runs at its multiples in local time, e.g. on the hour
+    #[serde(default)]
+    align_all: bool,
+    #[serde(default)]
+    groups: HashMap<String, MailboxGroupConfig>,
+    quiet_hours: Option<QuietHoursConfig>,
+    #[serde(default)]
+    jitter: u64,
+    //bounds of the inbox interval adapted to the activity of the accounts
+    adaptive_inboxes: Option<(u64, u64)>,
+    connectivity_check: Option<ConnectivityCheckConfig>,
+    power: Option<PowerConfig>,
+    #[serde(default)]
+    startup: StartupConfig,
+    //seconds between the startup syncs of the accounts
+    startup_stagger: Option<u64>,
+    #[serde(default)]
+    maintenance: HashMap<String, MaintenanceConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MaintenanceConfig {
+    schedule: ScheduleConfig,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    //seconds until a hanging command is killed
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum StartupConfig {
+    #[default]
+    Full,
+    Inboxes,
+    Nothing,
+    //only the mailboxes changed since the last shutdown
+    CatchUp,
+}
+
+impl StartupConfig {
+    fn startup_sync(&self) -> StartupSync {
+        match self {
+            Self::Full => StartupSync::All,
+            Self::Inboxes => StartupSync::Inboxes,
+            Self::Nothing | Self::CatchUp => StartupSync::Nothing,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PowerConfig {
+    //the factor of the intervals on battery or a metered connection
+    #[serde(default = "default_power_slowdown")]
+    slowdown: u32,
+    #[serde(default)]
+    skip_all: bool,
+    min_battery: Option<u8>,
+    #[serde(default)]
+    metered: bool,
+}
+
+fn default_power_slowdown() -> u32 {
+    2
+}
+
+impl PowerConfig {
+    fn policy(&self) -> PowerPolicy {
+        let mut policy = PowerPolicy::new(self.slowdown);
+        if self.skip_all {
+            policy = policy.with_skipped_all();
+        }
+        if let Some(min_battery) = self.min_battery {
+            policy = policy.with_min_capacity(min_battery);
+        }
+        if self.metered {
+            policy = policy.with_metered_check();
+        }
+        policy
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum ConnectivityCheckConfig {
+    Tcp(String),
+    NetworkManager,
+}
+
+impl ConnectivityCheckConfig {
+    fn check(&self) -> ConnectivityCheck {
+        match self {
+            Self::Tcp(address) => ConnectivityCheck::Tcp(address.clone()),
+            Self::NetworkManager => ConnectivityCheck::NetworkManager,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct QuietHoursConfig {
+    start: String,
+    end: String,
+    //no changes are synced during the quiet hours, they are caught up afterwards
+    #[serde(default)]
+    pause_watcher: bool,
+}
+
+impl QuietHoursConfig {
+    fn quiet_hours(&self) -> Result<QuietHours, chrono::ParseError> {
+        QuietHours::parse(&self.start, &self.end)
+    }
+}
+
+//the quiet hours pausing the watchers
+fn watcher_pause(config: &Config) -> Result<Option<QuietHours>, chrono::ParseError> {
+    match &config.timer.quiet_hours {
+        Some(quiet_hours) if quiet_hours.pause_watcher => quiet_hours.quiet_hours().map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct MailboxGroupConfig {
+    mailboxes: Vec<String>,
+    schedule: ScheduleConfig,
+    //keeps its schedule when all is synced, e.g. for archives
+    #[serde(default)]
+    slow: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ScheduleConfig {
+    Seconds(u64),
+    Cron(String),
+}
+
+impl ScheduleConfig {
+    //seconds may be a string as well, e.g. of MAILWATCH_TIMER__INBOXES=60
+    fn schedule(&self) -> Result<Schedule, cron::error::Error> {
+        match self {
+            Self::Seconds(secs) => Ok(Schedule::Interval(Duration::from_secs(*secs))),
+            Self::Cron(expression) => match expression.parse() {
+                Ok(secs) => Ok(Schedule::Interval(Duration::from_secs(secs))),
+                Err(_) => Schedule::cron(expression),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum WatcherBackendConfig {
+    #[default]
+    #[serde(alias = "native")]
+    Recommended,
+    Inotify,
+    Fanotify,
+    FsEvents,
+    Kqueue,
+    ReadDirectoryChanges,
+    Poll,
+}
+
+fn default_watcher_enabled() -> bool {
+    true
+}
+
+fn default_account_enabled() -> bool {
+    true
+}
+
+fn default_poll_interval() -> u64 {
+    10
+}
+
+fn default_root_check_interval() -> u64 {
+    10
+}
+
+fn default_access_cooldown() -> u64 {
+    60
+}
+
+fn default_settle_ms() -> u64 {
+    1000
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum MailboxEncodingConfig {
+    #[default]
+    Mutf7,
+    Utf8,
+    #[serde(rename = "none")]
+    Raw,
+}
+
+#[derive(Deserialize, Debug)]
+struct WatcherConfig {
+    #[serde(default = "default_watcher_enabled")]
+    enabled: bool,
+    path_pattern: Option<String>,
+    ignore: Option<Vec<String>>,
+    #[serde(default)]
+    ignore_dirs: Vec<String>,
+    #[serde(default)]
+    mailbox_encoding: MailboxEncodingConfig,
+    #[serde(default)]
+    backend: WatcherBackendConfig,
+    #[serde(default = "default_poll_interval")]
+    poll_interval: u64,
+    #[serde(default)]
+    debounce_ms: u64,
+    //off by default, the changes of the user while a covering sync runs are dropped as well
+    #[serde(default)]
+    suppress_own_changes: bool,
+    #[serde(default = "default_settle_ms")]
+    settle_ms: u64,
+    #[serde(default)]
+    ignore_processes: Vec<String>,
+    #[serde(default = "default_root_check_interval")]
+    root_check_interval: u64,
+    #[serde(default)]
+    sync_on_access: bool,
+    #[serde(default = "default_access_cooldown")]
+    access_cooldown: u64,
+}
+
+impl Default for WatcherConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_watcher_enabled(),
+            path_pattern: None,
+            ignore: None,
+            ignore_dirs: Vec::new(),
+            mailbox_encoding: MailboxEncodingConfig::default(),
+            backend: WatcherBackendConfig::default(),
+            poll_interval: default_poll_interval(),
+            debounce_ms: 0,
+            suppress_own_changes: false,
+            settle_ms: default_settle_ms(),
+            ignore_processes: Vec::new(),
+            root_check_interval: default_root_check_interval(),
+            sync_on_access: false,
+            access_cooldown: default_access_cooldown(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LogWatcherConfig {
+    file: Option<PathBuf>,
+    journald_unit: Option<String>,
+    line_pattern: Option<String>,
+}
+
+fn default_retry_delay() -> u64 {
+    30
+}
+
+fn default_circuit_cooldown() -> u64 {
+    300
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_persist_queue() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct UpdaterConfig {
+    #[serde(default)]
+    retries: u32,
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
+    max_concurrent: Option<usize>,
+    coalesce_threshold: Option<usize>,
+    max_queue_len: Option<usize>,
+    max_age: Option<u64>,
+    min_interval: Option<u64>,
+    circuit_failures: Option<u32>,
+    #[serde(default = "default_circuit_cooldown")]
+    circuit_cooldown: u64,
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+    #[serde(default = "default_persist_queue")]
+    persist_queue: bool,
+    state_file: Option<PathBuf>,
+    //seconds between the logs of the sync statistics
+    statistics_interval: Option<u64>,
+}
+
+impl UpdaterConfig {
+    //defaults to the state dir of the user
+    fn state_file(&self) -> Option<PathBuf> {
+        if !self.persist_queue {
+            return None;
+        }
+        self.state_file.clone().or_else(|| {
+            dirs::state_dir().map(|state_dir| state_dir.join("mailwatch").join("queue.json"))
+        })
+    }
+}
+
+impl Default for UpdaterConfig {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            retry_delay: default_retry_delay(),
+            max_concurrent: None,
+            coalesce_threshold: None,
+            max_queue_len: None,
+            max_age: None,
+            min_interval: None,
+            circuit_failures: None,
+            circuit_cooldown: default_circuit_cooldown(),
+            shutdown_timeout: default_shutdown_timeout(),
+            persist_queue: default_persist_queue(),
+            state_file: None,
+            statistics_interval: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AccountConfig {
+    //a disabled account is neither watched nor synced, e.g. an archived one
+    #[serde(default = "default_account_enabled")]
+    enabled: bool,
+    #[serde(default)]
+    ignore_mailboxes: Vec<String>,
+    //shell commands before a sync of the account, a failure defers it
+    #[serde(default)]
+    pre_sync: Vec<String>,
+    //shell commands after a successful sync of the account
+    #[serde(default)]
+    post_sync: Vec<String>,
+    //e.g. the refresh of the xoauth2 token used by the password command of mbsync
+    token_refresh: Option<TokenRefreshConfig>,
+    //e.g. tcp = "imap.example.org:993", syncs are deferred while it fails
+    connectivity_check: Option<ConnectivityCheckConfig>,
+    //the user the syncs run as, with its config and credentials
+    run_as: Option<String>,
+    //e.g. XDG_RUNTIME_DIR for gpg or https_proxy
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(flatten)]
+    priority: PriorityConfig,
+}
+
+//e.g. nice = 19 and ionice = "idle" for huge archives
+#[derive(Deserialize, Debug, Default)]
+struct PriorityConfig {
+    nice: Option<i32>,
+    ionice: Option<IoPriorityConfig>,
+}
+
+//the class idle or the level of the class best effort
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+enum IoPriorityConfig {
+    Idle(IdleConfig),
+    BestEffort(u8),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum IdleConfig {
+    Idle,
+}
+
+impl PriorityConfig {
+    fn priority(&self) -> Option<Priority> {
+        if self.nice.is_none() && self.ionice.is_none() {
+            return None;
+        }
+        Some(Priority {
+            nice: self.nice,
+            io: self.ionice.map(|ionice| match ionice {
+                IoPriorityConfig::Idle(IdleConfig::Idle) => IoPriority::Idle,
+                IoPriorityConfig::BestEffort(level) => IoPriority::BestEffort(level),
+            }),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenRefreshConfig {
+    command: String,
+    //seconds until the token has to be refreshed again
+    #[serde(default = "default_token_lifetime")]
+    lifetime: u64,
+}
+
+fn default_token_lifetime() -> u64 {
+    3000
+}
+
+//shell commands around the syncs, with MAILWATCH_ACCOUNT and MAILWATCH_MAILBOX in their environment
+#[derive(Deserialize, Debug, Default)]
+struct HooksConfig {
+    //checked before every sync
+    connectivity_check: Option<ConnectivityCheckConfig>,
+    #[serde(default)]
+    pre_sync: Vec<String>,
+    #[serde(default)]
+    post_sync: Vec<String>,
+    //in seconds, a hanging hook is killed, by default after the timeout of the syncs
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PushConfig {
+    listen: String,
 }
 
 #[derive(Deserialize, Debug)]
 struct Config {
     dovecot: DovecotConfig,
-    mbsync: MbSyncConfig,
+    #[serde(default)]
+    executor: ExecutorKind,
+    mbsync: Option<MbSyncConfig>,
+    offlineimap: Option<OfflineImapConfig>,
+    imapsync: Option<ImapSyncConfig>,
+    getmail: Option<PullConfig>,
+    fetchmail: Option<PullConfig>,
+    shell: Option<ShellConfig>,
     timer: TimerConfig,
+    #[serde(default)]
+    watcher: WatcherConfig,
+    #[serde(default)]
+    updater: UpdaterConfig,
+    logwatcher: Option<LogWatcherConfig>,
+    push: Option<PushConfig>,
+    #[serde(default)]
+    accounts: HashMap<String, AccountConfig>,
+    #[serde(default)]
+    hooks: HooksConfig,
+    #[serde(default)]
+    priority: PriorityConfig,
+    #[serde(default)]
+    logging: LoggingConfig,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum LogStyleConfig {
+    //colored on a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct LoggingConfig {
+    //info by default, --log-level takes precedence
+    level: Option<log::LevelFilter>,
+    //e.g. "mailwatch::watcher" = "debug"
+    #[serde(default)]
+    modules: HashMap<String, log::LevelFilter>,
+    #[serde(default)]
+    style: LogStyleConfig,
+}
+
+//the logger of the config, replaced on a reload
+static LOGGER: RwLock<Option<env_logger::Logger>> = RwLock::new(None);
+
+struct ReloadableLogger;
+
+impl log::Log for ReloadableLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        LOGGER
+            .read()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = LOGGER.read().unwrap().as_ref() {
+            logger.flush();
+        }
+    }
+}
+
+fn apply_logging(level: Option<log::LevelFilter>, config: &LoggingConfig) {
+    let mut builder = Builder::new();
+    builder.filter(
+        None,
+        level.or(config.level).unwrap_or(log::LevelFilter::Info),
+    );
+    for (module, level) in &config.modules {
+        builder.filter(Some(module), *level);
+    }
+    builder.write_style(match config.style {
+        LogStyleConfig::Auto => env_logger::WriteStyle::Auto,
+        LogStyleConfig::Always => env_logger::WriteStyle::Always,
+        LogStyleConfig::Never => env_logger::WriteStyle::Never,
+    });
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    *LOGGER.write().unwrap() = Some(logger);
+}
+
+impl Config {
+    fn mailbox_filter(&self) -> Result<MailboxFilter, glob::PatternError> {
+        self.accounts
+            .iter()
+            .try_fold(MailboxFilter::default(), |filter, (account, config)| {
+                let filter = filter.with_ignored_mailboxes(account, &config.ignore_mailboxes)?;
+                Ok(match config.enabled {
+                    true => filter,
+                    false => filter.with_disabled_account(account),
+                })
+            })
+    }
+
+    fn enabled_accounts(&self) -> impl Iterator<Item = (&String, &AccountConfig)> {
+        self.accounts
+            .iter()
+            .filter(|(_, account_config)| account_config.enabled)
+    }
+
+    //the timeout of the syncs of the executor
+    fn sync_timeout(&self) -> Option<u64> {
+        match self.executor {
+            ExecutorKind::Mbsync => self.mbsync.as_ref().and_then(|config| config.timeout),
+            ExecutorKind::Offlineimap => {
+                self.offlineimap.as_ref().and_then(|config| config.timeout)
+            }
+            ExecutorKind::Imapsync => self.imapsync.as_ref().and_then(|config| config.timeout),
+            ExecutorKind::Getmail => self.getmail.as_ref().and_then(|config| config.timeout),
+            ExecutorKind::Fetchmail => self.fetchmail.as_ref().and_then(|config| config.timeout),
+            ExecutorKind::Shell => self.shell.as_ref().and_then(|config| config.timeout),
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "syncs the mailboxes of dovecot on their changes")]
+struct Cli {
+    #[arg(
+        short,
+        long,
+        help = "the toml, json or yaml config file, by default mailwatch.toml in the mail dir of the config dir"
+    )]
+    config: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "e.g. debug, info or warn, by default that of the config or info"
+    )]
+    log_level: Option<log::LevelFilter>,
+    #[arg(long, help = "only log the syncs instead of running them")]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum CliCommand {
+    #[command(about = "watch the mailboxes and run the syncs, the default")]
+    Run,
+    #[command(about = "validate the config without starting the daemon")]
+    Check,
+    #[command(about = "sync all accounts once and exit with the result")]
+    Once {
+        #[arg(long, help = "only sync the inboxes of the accounts")]
+        inboxes: bool,
+    },
+    //runs the executor itself, the daemon has no control channel
+    #[command(about = "sync accounts or mailboxes once and exit with the result")]
+    Sync {
+        #[arg(required = true, value_name = "ACCOUNT[:MAILBOX]")]
+        targets: Vec<String>,
+    },
 }
 
 #[derive(Debug, Error)]
@@ -44,75 +927,1586 @@ enum ConfigError {
     IoError(#[from] io::Error),
     #[error("config parse error: {0}")]
     TomlError(#[from] toml::de::Error),
+    #[error("config parse error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("config parse error: {0}")]
+    YamlError(#[from] serde_yaml_ng::Error),
+    #[error("invalid include pattern: {0}")]
+    IncludeError(#[from] glob::PatternError),
+    #[error("logwatcher needs either file or journald_unit")]
+    MissingLogSource,
+    #[error("executor {0} needs its config section")]
+    MissingExecutorConfig(&'static str),
 }
 
-fn read_config() -> Result<Config, ConfigError> {
-    let config_file = match dirs::config_dir() {
-        Some(config_dir) => config_dir.join("mail"),
-        None => PathBuf::from(","),
-    }
-    .join("mailwatch.toml");
+fn read_config(config_file: Option<&Path>) -> Result<Config, ConfigError> {
+    let config_file = match config_file {
+        Some(config_file) => config_file.to_path_buf(),
+        None => match dirs::config_dir() {
+            Some(config_dir) => config_dir.join("mail"),
+            None => PathBuf::from("."),
+        }
+        .join("mailwatch.toml"),
+    };
     log::info!("try to load {:?}", config_file);
+    let mut config = read_config_includes(&config_file)?;
+    let ambiguous = override_by_env(&mut config, env::vars());
+    typed_config(config, &ambiguous)
+}
+
+//the config with the fragments of its includes merged in the order of their names
+fn read_config_includes(config_file: &Path) -> Result<toml::Table, ConfigError> {
+    let mut config = read_config_file(config_file)?;
+    //e.g. include = ["accounts.d/*.toml"], relative to the dir of the config
+    if let Some(include) = config.remove("include") {
+        let dir = config_file.parent().unwrap_or(Path::new("."));
+        for pattern in include.try_into::<Vec<String>>()? {
+            for fragment in glob::glob(&dir.join(pattern).to_string_lossy())? {
+                let fragment = fragment.map_err(glob::GlobError::into_error)?;
+                log::info!("include {:?}", fragment);
+                merge_config(&mut config, read_config_file(&fragment)?);
+            }
+        }
+    }
+    Ok(config)
+}
+
+fn read_config_file(config_file: &Path) -> Result<toml::Table, ConfigError> {
     let mut file = File::open(config_file)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    Ok(toml::from_str(&contents)?)
+    //the format is chosen by the extension, e.g. for configs generated as json
+    Ok(
+        match config_file
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => serde_json::from_str(&contents)?,
+            Some("yaml" | "yml") => serde_yaml_ng::from_str(&contents)?,
+            _ => toml::from_str(&contents)?,
+        },
+    )
+}
+
+//the tables of the fragment are merged, its other values replace those of the config
+fn merge_config(config: &mut toml::Table, fragment: toml::Table) {
+    for (key, value) in fragment {
+        match (config.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(fragment)) => {
+                merge_config(table, fragment)
+            }
+            (_, value) => {
+                config.insert(key, value);
+            }
+        }
+    }
+}
+
+const ENV_PREFIX: &str = "MAILWATCH_";
+
+//the ambiguous overrides tried in all combinations, any further ones stay strings
+const MAX_AMBIGUOUS_OVERRIDES: usize = 8;
+
+//an override with text which is another toml value than the one of the config, e.g. 2024
+type AmbiguousOverride = (Vec<String>, toml::Value);
+
+//e.g. MAILWATCH_DOVECOT__DIR=/srv/mail or MAILWATCH_TIMER__INBOXES=60, the sections are separated
+//by two underscores. a value is toml if the value of the config has its type or it is an array or
+//table, otherwise a string. the uppercase keys are lowercased unless the config has them, e.g.
+//MAILWATCH_ACCOUNTS__Work__ENABLED=false of the account Work.
+fn override_by_env<I>(config: &mut toml::Table, vars: I) -> Vec<AmbiguousOverride>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut ambiguous = Vec::new();
+    for (name, value) in vars {
+        //e.g. MAILWATCH_ACCOUNT of the hooks is no override
+        let Some(path) = name
+            .strip_prefix(ENV_PREFIX)
+            .filter(|path| path.contains("__"))
+        else {
+            continue;
+        };
+        let mut keys = path.split("__").collect::<Vec<_>>();
+        let Some(last) = keys.pop() else {
+            continue;
+        };
+        let mut resolved = Vec::new();
+        let table = keys.into_iter().fold(&mut *config, |table, key| {
+            let key = env_key(table, key);
+            resolved.push(key.clone());
+            let value = table
+                .entry(key)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if !value.is_table() {
+                *value = toml::Value::Table(toml::Table::new());
+            }
+            value.as_table_mut().unwrap()
+        });
+        let key = env_key(table, last);
+        let typed = toml::from_str::<toml::Table>(&format!("value = {}", value))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"));
+        let value = match (typed, table.get(&key)) {
+            (Some(typed), _) if typed.is_array() || typed.is_table() => typed,
+            (Some(typed), Some(existing)) if typed.same_type(existing) => typed,
+            //e.g. 60 of a schedule of seconds or cron expressions
+            (Some(typed), _) if !typed.is_str() => {
+                resolved.push(key.clone());
+                ambiguous.push((resolved, typed));
+                toml::Value::String(value)
+            }
+            _ => toml::Value::String(value),
+        };
+        log::info!("{} overrides the config", name);
+        table.insert(key, value);
+    }
+    ambiguous
+}
+
+//the ambiguous overrides are strings, unless the config is only valid with some of them typed,
+//e.g. MAILWATCH_ACCOUNTS__Private__ENABLED=false of an account missing in the config
+fn typed_config(
+    config: toml::Table,
+    ambiguous: &[AmbiguousOverride],
+) -> Result<Config, ConfigError> {
+    let ambiguous = &ambiguous[..ambiguous.len().min(MAX_AMBIGUOUS_OVERRIDES)];
+    let mut combinations = (0..1u32 << ambiguous.len()).collect::<Vec<_>>();
+    combinations.sort_by_key(|typed| typed.count_ones());
+    let mut error = None;
+    for typed in combinations {
+        let mut config = config.clone();
+        for (index, (path, value)) in ambiguous.iter().enumerate() {
+            if typed & 1 << index == 0 {
+                continue;
+            }
+            let (key, keys) = path.split_last().unwrap();
+            let table = keys.iter().fold(&mut config, |table, key| {
+                table
+                    .get_mut(key)
+                    .and_then(toml::Value::as_table_mut)
+                    .unwrap()
+            });
+            table.insert(key.clone(), value.clone());
+        }
+        match toml::Value::Table(config).try_into() {
+            Ok(config) => return Ok(config),
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+    Err(error.unwrap().into())
+}
+
+fn env_key(table: &toml::Table, key: &str) -> String {
+    if let Some(existing) = table
+        .keys()
+        .find(|existing| existing.eq_ignore_ascii_case(key))
+    {
+        return existing.clone();
+    }
+    match key.chars().any(char::is_lowercase) {
+        true => key.to_owned(),
+        false => key.to_lowercase(),
+    }
+}
+
+fn watcher_backend(config: &WatcherConfig) -> Result<FileWatcherBackend, FileWatcherError> {
+    match config.backend {
+        WatcherBackendConfig::Recommended => Ok(FileWatcherBackend::Recommended),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        WatcherBackendConfig::Inotify => Ok(FileWatcherBackend::Inotify),
+        #[cfg(target_os = "linux")]
+        WatcherBackendConfig::Fanotify => Ok(FileWatcherBackend::Fanotify),
+        #[cfg(target_os = "macos")]
+        WatcherBackendConfig::FsEvents => Ok(FileWatcherBackend::FsEvents),
+        #[cfg(any(
+            target_os = "freebsd",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "dragonfly",
+            target_os = "ios"
+        ))]
+        WatcherBackendConfig::Kqueue => Ok(FileWatcherBackend::Kqueue),
+        #[cfg(target_os = "windows")]
+        WatcherBackendConfig::ReadDirectoryChanges => Ok(FileWatcherBackend::ReadDirectoryChanges),
+        WatcherBackendConfig::Poll => Ok(FileWatcherBackend::Poll(Duration::from_secs(
+            config.poll_interval,
+        ))),
+        #[allow(unreachable_patterns)]
+        other => Err(FileWatcherError::UnsupportedBackend(
+            format!("{:?}", other).to_lowercase(),
+        )),
+    }
+}
+
+fn watcher_options(
+    config: &WatcherConfig,
+    layout: AccountLayout,
+    mailbox_filter: MailboxFilter,
+    root: &MailRootConfig,
+    suppressor: &Option<SyncSuppressor>,
+) -> Result<FileWatcherOptions, FileWatcherError> {
+    let mut options = FileWatcherOptions::default()
+        .with_backend(watcher_backend(config)?)
+        .with_fallback_poll_interval(Duration::from_secs(config.poll_interval))
+        .with_debounce(Duration::from_millis(config.debounce_ms))
+        .with_ignored_processes(&config.ignore_processes)
+        .with_ignored_dirs(&config.ignore_dirs)
+        .with_account_layout(layout)
+        .with_mailbox_filter(mailbox_filter)
+        .with_mailbox_decoder(match config.mailbox_encoding {
+            MailboxEncodingConfig::Mutf7 => MailboxDecoder::Mutf7,
+            //utf8 names are stored as they are, so no decoding is needed as well
+            MailboxEncodingConfig::Utf8 | MailboxEncodingConfig::Raw => MailboxDecoder::Raw,
+        });
+    if let Some(suppressor) = suppressor {
+        options = options.with_suppressor(suppressor.clone());
+    }
+    if config.sync_on_access {
+        options = options.with_sync_on_access(Duration::from_secs(config.access_cooldown));
+    }
+    if let Some(ignore) = &config.ignore {
+        options = options.with_ignore_patterns(ignore)?;
+    }
+    //the pattern of the root wins over the global one
+    match root.path_pattern().or(config.path_pattern.as_deref()) {
+        Some(pattern) => options.with_path_pattern(pattern),
+        None => Ok(options),
+    }
+}
+
+const EVENT_BATCH_SIZE: usize = 100;
+
+fn event_task(event: FileWatcherEvent, source: TaskSource) -> MailUpdaterTask {
+    let task = match event.kind {
+        //the channel of the old name is gone, so sync the whole account
+        FileWatcherEventKind::Rename => {
+            log::info!(
+                "mailbox {:?} of {} renamed to {:?}",
+                event.renamed_from,
+                event.account,
+                event.mailbox
+            );
+            MailUpdaterTask::new(Some(event.account), None)
+        }
+        _ => MailUpdaterTask::new(Some(event.account), event.mailbox),
+    };
+    task.with_source(source)
+}
+
+fn add_event_account(accounts: &Accounts, event: &FileWatcherEvent) {
+    match &event.mailbox {
+        Some(mailbox) => accounts.add_mailbox(&event.account, mailbox),
+        None => accounts.add(&event.account),
+    }
+    if event.kind != FileWatcherEventKind::Access {
+        accounts.record_activity(&event.account);
+    }
+}
+
+fn establish_file_watcher(
+    dir_to_watch: &Path,
+    options: &FileWatcherOptions,
+    check_interval: Duration,
+) -> FileWatcher {
+    loop {
+        if dir_to_watch.is_dir() {
+            match FileWatcher::with_options(dir_to_watch, options.clone()) {
+                Ok(file_watcher) => return file_watcher,
+                Err(err) => log::error!("could not watch {:?}: {}", dir_to_watch, err),
+            }
+        }
+        sleep(check_interval);
+    }
 }
 
 fn queue_filewatch_tasks(
     dir_to_watch: &Path,
+    mut options: FileWatcherOptions,
+    reload: Receiver<WatcherReload>,
+    check_interval: Duration,
+    mut pause: Option<QuietHours>,
+    accounts: &Accounts,
     updater: &MailUpdater,
-) -> Result<(), FileWatcherError> {
-    let file_watcher = FileWatcher::new(dir_to_watch)?;
-    while let Ok(event) = file_watcher.wait_for_event(None) {
-        updater.queue_task(MailUpdaterTask::new(
-            Some(event.account),
-            Some(event.mailbox),
-        ));
+) {
+    let mut file_watcher = establish_file_watcher(dir_to_watch, &options, check_interval);
+    let mut paused = false;
+    loop {
+        //the old watcher is dropped once the new one watches
+        if let Some((reloaded, reloaded_pause)) = reload.try_iter().last() {
+            log::info!(
+                "restart the watcher of {:?} with the reloaded config",
+                dir_to_watch
+            );
+            options = reloaded;
+            pause = reloaded_pause;
+            file_watcher = establish_file_watcher(dir_to_watch, &options, check_interval);
+        }
+        let quiet = pause.is_some_and(|pause| pause.contains(Local::now().time()));
+        if paused && !quiet {
+            log::info!(
+                "quiet hours are over, catch up the changes of {:?}",
+                dir_to_watch
+            );
+            queue_root_accounts(dir_to_watch, accounts, updater);
+        }
+        paused = quiet;
+        match file_watcher.wait_for_events(EVENT_BATCH_SIZE, Some(check_interval)) {
+            //the changes are caught up after the quiet hours
+            Ok(_) if paused => {}
+            Ok(events) => {
+                for event in &events {
+                    add_event_account(accounts, event);
+                }
+                //an opened mailbox is only refreshed after the real changes
+                let (accessed, changed): (Vec<_>, Vec<_>) = events
+                    .into_iter()
+                    .partition(|event| event.kind == FileWatcherEventKind::Access);
+                updater.queue_tasks(
+                    changed
+                        .into_iter()
+                        .map(|event| event_task(event, TaskSource::FileWatch)),
+                );
+                updater.queue_tasks_with_priority(
+                    accessed
+                        .into_iter()
+                        .map(|event| event_task(event, TaskSource::FileWatch)),
+                    TaskPriority::Low,
+                );
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            //the event thread of the watcher died, e.g. by a panic
+            Err(RecvTimeoutError::Disconnected) => {
+                log::error!("watcher of {:?} stopped, restarting it", dir_to_watch);
+                drop(file_watcher);
+                sleep(check_interval);
+                file_watcher = establish_file_watcher(dir_to_watch, &options, check_interval);
+                log::info!("watcher of {:?} restarted", dir_to_watch);
+                queue_root_accounts(dir_to_watch, accounts, updater);
+                continue;
+            }
+        }
+        if !file_watcher.is_root_available() {
+            log::warn!("mail root {:?} disappeared, waiting for it", dir_to_watch);
+            drop(file_watcher);
+            file_watcher = establish_file_watcher(dir_to_watch, &options, check_interval);
+            log::info!("mail root {:?} is available again", dir_to_watch);
+            queue_root_accounts(dir_to_watch, accounts, updater);
+        }
+    }
+}
+
+//catch up the changes done while the root was not watched
+fn queue_root_accounts(dir_to_watch: &Path, accounts: &Accounts, updater: &MailUpdater) {
+    match accounts.scan(dir_to_watch) {
+        Ok(root_accounts) => {
+            updater.queue_tasks(root_accounts.into_iter().map(|account| {
+                MailUpdaterTask::new(Some(account), None).with_source(TaskSource::FileWatch)
+            }));
+        }
+        Err(err) => log::error!("could not read mail root {:?}: {}", dir_to_watch, err),
+    }
+}
+
+//the options and the quiet hours of a watcher of the reloaded config
+type WatcherReload = (FileWatcherOptions, Option<QuietHours>);
+
+//the thread watching a mail root, its options are replaced on a reload of the config
+struct RootWatcher {
+    path: PathBuf,
+    thread: JoinHandle<()>,
+    reload: Sender<WatcherReload>,
+}
+
+fn watch_roots(
+    config: &Config,
+    mailbox_filter: &MailboxFilter,
+    suppressor: &Option<SyncSuppressor>,
+    accounts: &Accounts,
+    updater: &Arc<MailUpdater>,
+) -> Result<Vec<RootWatcher>, Box<dyn Error>> {
+    let pause = watcher_pause(config)?;
+    let mut watchers = Vec::new();
+    for root in config.dovecot.roots() {
+        let options = watcher_options(
+            &config.watcher,
+            config.dovecot.account_layout(),
+            mailbox_filter.clone(),
+            root,
+            suppressor,
+        )?;
+        let check_interval = Duration::from_secs(config.watcher.root_check_interval);
+        let path = root.path().to_path_buf();
+        let (reload, reloaded) = mpsc::channel();
+        let accounts = accounts.clone();
+        let updater = updater.clone();
+        let thread = thread::spawn(move || {
+            queue_filewatch_tasks(
+                &path,
+                options,
+                reloaded,
+                check_interval,
+                pause,
+                &accounts,
+                &updater,
+            )
+        });
+        watchers.push(RootWatcher {
+            path: root.path().to_path_buf(),
+            thread,
+            reload,
+        });
     }
+    Ok(watchers)
+}
+
+fn queue_changed_mailboxes(
+    config: &Config,
+    mailbox_filter: &MailboxFilter,
+    since: SystemTime,
+    accounts: &Accounts,
+    updater: &MailUpdater,
+) {
+    for root in config.dovecot.roots() {
+        let events = watcher_options(
+            &config.watcher,
+            config.dovecot.account_layout(),
+            mailbox_filter.clone(),
+            root,
+            &None,
+        )
+        .and_then(|options| FileWatcher::changed_since(root.path(), &options, since));
+        match events {
+            Ok(events) => {
+                log::info!(
+                    "{} mailboxes in {:?} changed since the last shutdown",
+                    events.len(),
+                    root.path()
+                );
+                for event in &events {
+                    add_event_account(accounts, event);
+                }
+                updater.queue_tasks(
+                    events
+                        .into_iter()
+                        .map(|event| event_task(event, TaskSource::Startup)),
+                );
+            }
+            Err(err) => log::warn!("could not scan {:?} for changes: {}", root.path(), err),
+        }
+    }
+}
+
+fn log_source(config: &LogWatcherConfig) -> Result<LogSource, ConfigError> {
+    match (&config.file, &config.journald_unit) {
+        (Some(file), _) => Ok(LogSource::File(file.clone())),
+        (None, Some(unit)) => Ok(LogSource::Journald(unit.clone())),
+        (None, None) => Err(ConfigError::MissingLogSource),
+    }
+}
+
+fn log_watcher(config: &LogWatcherConfig) -> Result<LogWatcher, Box<dyn Error>> {
+    let mut options = LogWatcherOptions::default();
+    if let Some(pattern) = &config.line_pattern {
+        options = options.with_line_pattern(pattern)?;
+    }
+    Ok(LogWatcher::with_options(log_source(config)?, options)?)
+}
+
+//the users of the log are mapped to the accounts of the mail dirs, e.g. frank@example.org to frank
+fn queue_logwatch_tasks(log_watcher: LogWatcher, accounts: &Accounts, updater: &MailUpdater) {
+    while let Ok(events) = log_watcher.wait_for_events(EVENT_BATCH_SIZE, None) {
+        let mut refreshed = false;
+        let events = events
+            .into_iter()
+            .filter_map(|mut event| {
+                let mut account = accounts.account_of_user(&event.account);
+                //the dir of a new account may be created by the delivery
+                if account.is_none() && !refreshed {
+                    refreshed = true;
+                    if let Err(err) = accounts.refresh() {
+                        log::warn!("could not refresh the accounts: {}", err);
+                    }
+                    account = accounts.account_of_user(&event.account);
+                }
+                match account {
+                    Some(account) => event.account = account,
+                    None => {
+                        log::debug!("ignore log event of the unknown user {}", event.account);
+                        return None;
+                    }
+                }
+                Some(event)
+            })
+            .collect::<Vec<_>>();
+        for event in &events {
+            add_event_account(accounts, event);
+        }
+        updater.queue_tasks(
+            events
+                .into_iter()
+                .map(|event| event_task(event, TaskSource::LogWatch)),
+        );
+    }
+    log::error!("log watcher stopped");
+}
+
+#[cfg(unix)]
+fn handle_termination(updater: Arc<MailUpdater>, timeout: Duration) -> io::Result<()> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            log::info!("received signal {}, waiting for running syncs", signal);
+            let pending = updater.shutdown(Some(timeout));
+            if !pending.is_empty() {
+                log::warn!("pending tasks at shutdown {:?}", pending);
+            }
+            process::exit(0);
+        }
+    });
     Ok(())
 }
 
-fn get_inboxes(dir: &Path) -> Result<Vec<String>, io::Error> {
-    let mut result = Vec::new();
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        if entry.file_type()?.is_dir() {
-            result.push(
-                entry
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_owned(),
+//a full sync on demand, e.g. by kill -USR1
+#[cfg(unix)]
+fn handle_sync_signal(updater: Arc<MailUpdater>) -> io::Result<()> {
+    use signal_hook::{consts::SIGUSR1, iterator::Signals};
+    let mut signals = Signals::new([SIGUSR1])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            log::info!("received SIGUSR1, sync all");
+            updater.queue_task(MailUpdaterTask::new(None, None).with_source(TaskSource::Manual));
+        }
+    });
+    Ok(())
+}
+
+//the state of the daemon rebuilt by a reload of the config
+#[cfg(unix)]
+struct Reloader {
+    config_file: Option<PathBuf>,
+    log_level: Option<log::LevelFilter>,
+    dry_run: bool,
+    suppressor: Option<SyncSuppressor>,
+    accounts: Accounts,
+    updater: Arc<MailUpdater>,
+    timer: TimerHandle,
+    watchers: HashMap<PathBuf, Sender<WatcherReload>>,
+    //the options of the running updater
+    updater_config: UpdaterConfig,
+}
+
+#[cfg(unix)]
+impl Reloader {
+    //the queue is kept, e.g. the mail roots and the updater options need a restart
+    fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let config = read_config(self.config_file.as_deref())?;
+        let mailbox_filter = config.mailbox_filter()?;
+        let accounts = self
+            .accounts
+            .clone()
+            .with_mailbox_filter(mailbox_filter.clone());
+        let executor = wrapped_executor(&config, &self.suppressor, &accounts, self.dry_run)?;
+        let timer_options = timer_options(&config)?;
+        let pause = watcher_pause(&config)?;
+        if config.updater != self.updater_config {
+            log::warn!("the changed updater options are only applied after a restart");
+        }
+        let mut watchers = Vec::new();
+        for root in config.dovecot.roots() {
+            match self.watchers.get(root.path()) {
+                Some(reload) if config.watcher.enabled => watchers.push((
+                    reload,
+                    watcher_options(
+                        &config.watcher,
+                        config.dovecot.account_layout(),
+                        mailbox_filter.clone(),
+                        root,
+                        &self.suppressor,
+                    )?,
+                )),
+                Some(_) => log::warn!("the watcher is only disabled by a restart"),
+                None => log::warn!(
+                    "mail root {:?} is only watched after a restart",
+                    root.path()
+                ),
+            }
+        }
+        //nothing is replaced by an invalid config
+        apply_logging(self.log_level, &config.logging);
+        self.updater.set_executor(executor);
+        self.timer.restart(timer_options, accounts);
+        for (reload, options) in watchers {
+            let _ = reload.send((options, pause));
+        }
+        Ok(())
+    }
+}
+
+//rereads the config on kill -HUP
+#[cfg(unix)]
+fn handle_reload_signal(reloader: Reloader) -> io::Result<()> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            log::info!("received SIGHUP, reload the config");
+            match reloader.reload() {
+                Ok(()) => log::info!("config reloaded"),
+                Err(err) => log::error!("could not reload the config, keep the old one: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+//new mails pulled by a sync are activity of the account like the changes seen by the watcher, the
+//counts of a sync of all accounts can not be assigned to one of them
+fn record_pulled_activity(updater: &MailUpdater, accounts: Accounts) {
+    let events = updater.subscribe();
+    thread::spawn(move || {
+        for event in events {
+            if let TaskEvent::Finished {
+                task,
+                result: Ok(report),
+                ..
+            } = event
+            {
+                if let (Some(account), Some(counts)) = (&task.specific_account, report.counts) {
+                    if counts.pulled > 0 {
+                        accounts.record_activity(account);
+                    }
+                }
+            }
+        }
+    });
+}
+
+//the slowest first
+fn log_statistics(updater: Arc<MailUpdater>, interval: Duration) {
+    thread::spawn(move || loop {
+        sleep(interval);
+        let mut statistics = updater.statistics().into_iter().collect::<Vec<_>>();
+        statistics.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.avg()));
+        for ((account, mailbox), stats) in statistics {
+            log::info!(
+                "statistics of {}{}: {} syncs, min {:?}, avg {:?}, max {:?}, {:.0}% failed",
+                account.as_deref().unwrap_or("all"),
+                mailbox
+                    .map(|mailbox| format!(":{}", mailbox))
+                    .unwrap_or_default(),
+                stats.runs(),
+                stats.min().unwrap_or_default(),
+                stats.avg().unwrap_or_default(),
+                stats.max().unwrap_or_default(),
+                stats.failure_rate() * 100.0
             );
         }
+        let now = Instant::now();
+        for (account, circuit) in updater.circuits() {
+            if let Some(open_until) = circuit.open_until.filter(|_| circuit.is_open(now)) {
+                log::warn!(
+                    "syncs of {} paused for {:?} after {} failures",
+                    account,
+                    open_until - now,
+                    circuit.failures
+                );
+            }
+        }
+    });
+}
+
+fn executor(
+    config: &Config,
+    suppressor: &Option<SyncSuppressor>,
+    accounts: &Accounts,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    Ok(match config.executor {
+        ExecutorKind::Mbsync => Box::new(with_account_options(
+            config,
+            mbsync_executor(
+                config
+                    .mbsync
+                    .as_ref()
+                    .ok_or(ConfigError::MissingExecutorConfig("mbsync"))?,
+                suppressor,
+                accounts,
+                &config.accounts,
+            )?,
+        )?),
+        ExecutorKind::Offlineimap => Box::new(with_account_options(
+            config,
+            offlineimap_executor(
+                config.offlineimap.as_ref().unwrap_or(&Default::default()),
+                suppressor,
+            ),
+        )?),
+        ExecutorKind::Imapsync => Box::new(with_account_options(
+            config,
+            imapsync_executor(
+                config
+                    .imapsync
+                    .as_ref()
+                    .ok_or(ConfigError::MissingExecutorConfig("imapsync"))?,
+                suppressor,
+            ),
+        )?),
+        ExecutorKind::Getmail => Box::new(with_account_options(
+            config,
+            pull_executor(
+                PullTool::Getmail,
+                config
+                    .getmail
+                    .as_ref()
+                    .ok_or(ConfigError::MissingExecutorConfig("getmail"))?,
+                suppressor,
+            ),
+        )?),
+        ExecutorKind::Fetchmail => Box::new(with_account_options(
+            config,
+            pull_executor(
+                PullTool::Fetchmail,
+                config
+                    .fetchmail
+                    .as_ref()
+                    .ok_or(ConfigError::MissingExecutorConfig("fetchmail"))?,
+                suppressor,
+            ),
+        )?),
+        ExecutorKind::Shell => Box::new(with_account_options(
+            config,
+            shell_executor(
+                config
+                    .shell
+                    .as_ref()
+                    .ok_or(ConfigError::MissingExecutorConfig("shell"))?,
+                suppressor,
+            ),
+        )?),
+    })
+}
+
+//the general priority and the priorities, environments and users of the accounts, of the syncs
+//and of the hooks
+fn with_account_options<E: RunnerOptions>(config: &Config, executor: E) -> Result<E, ConfigError> {
+    let mut executor = match config.priority.priority() {
+        Some(priority) => executor.with_priority(None, priority),
+        None => executor,
+    };
+    for (account, account_config) in config.enabled_accounts() {
+        if let Some(priority) = account_config.priority.priority() {
+            executor = executor.with_priority(Some(account), priority);
+        }
+        for (name, value) in &account_config.env {
+            executor = executor.with_env(account, name, value);
+        }
+        if let Some(user) = &account_config.run_as {
+            executor = executor.with_user(account, LocalUser::lookup(user)?);
+        }
+    }
+    Ok(executor)
+}
+
+//the options of the commands of the executor kinds
+fn with_command_options<E: RunnerOptions>(
+    mut executor: E,
+    passthrough: bool,
+    quiet: bool,
+    timeout: Option<u64>,
+    suppressor: &Option<SyncSuppressor>,
+) -> E {
+    if passthrough {
+        executor = executor.with_passthrough();
+    }
+    if quiet {
+        executor = executor.with_quiet();
+    }
+    if let Some(timeout) = timeout {
+        executor = executor.with_timeout(Duration::from_secs(timeout));
+    }
+    if let Some(suppressor) = suppressor {
+        executor = executor.with_suppressor(suppressor.clone());
+    }
+    executor
+}
+
+//the executor of the config with its hooks, or only logging the syncs on a dry run
+fn wrapped_executor(
+    config: &Config,
+    suppressor: &Option<SyncSuppressor>,
+    accounts: &Accounts,
+    dry_run: bool,
+) -> Result<Box<dyn Executor>, ConfigError> {
+    //the hooks exclude accounts from a sync of all accounts even if the executor can not
+    let executor: Box<dyn Executor> = Box::new(DisabledAccountsExecutor::new(
+        executor(config, suppressor, accounts)?,
+        accounts.clone(),
+    ));
+    let executor = with_account_options(config, hooked_executor(config, executor))?;
+    //the executor is still set up to validate its config
+    Ok(match dry_run {
+        true => Box::new(|task: &MailUpdaterTask| {
+            log::info!("dry run, skip sync of {:?}", task);
+            Ok::<_, String>(())
+        }),
+        false => Box::new(executor),
+    })
+}
+
+fn timer_options(config: &Config) -> Result<TimerOptions, Box<dyn Error>> {
+    let mut timer_options = TimerOptions::new(
+        config.timer.inboxes.schedule()?,
+        match (config.timer.all.schedule()?, config.timer.align_all) {
+            (Schedule::Interval(interval), true) => Schedule::Aligned(interval),
+            (schedule, _) => schedule,
+        },
+    );
+    for (name, group) in &config.timer.groups {
+        let schedule = group.schedule.schedule()?;
+        timer_options = match group.slow {
+            true => timer_options.with_slow_group(name, schedule, &group.mailboxes),
+            false => timer_options.with_group(name, schedule, &group.mailboxes),
+        }?;
+    }
+    for (name, maintenance) in &config.timer.maintenance {
+        let mut job = MaintenanceJob::new(name, &maintenance.command, &maintenance.args);
+        if let Some(timeout) = maintenance.timeout {
+            job = job.with_timeout(Duration::from_secs(timeout));
+        }
+        timer_options = timer_options.with_maintenance(maintenance.schedule.schedule()?, job);
+    }
+    timer_options = timer_options.with_jitter(Duration::from_secs(config.timer.jitter));
+    if let Some(power) = &config.timer.power {
+        timer_options = timer_options.with_power_policy(power.policy());
     }
-    Ok(result)
+    if let Some(check) = &config.timer.connectivity_check {
+        timer_options = timer_options.with_connectivity_check(check.check());
+    }
+    if let Some((min, max)) = config.timer.adaptive_inboxes {
+        timer_options =
+            timer_options.with_adaptive_inboxes(Duration::from_secs(min), Duration::from_secs(max));
+    }
+    if let Some(quiet_hours) = &config.timer.quiet_hours {
+        timer_options = timer_options.with_quiet_hours(quiet_hours.quiet_hours()?);
+    }
+    Ok(timer_options)
+}
+
+fn hooked_executor(
+    config: &Config,
+    executor: Box<dyn Executor>,
+) -> HookedExecutor<Box<dyn Executor>> {
+    let mut executor = HookedExecutor::new(executor);
+    if let Some(timeout) = config.hooks.timeout.or(config.sync_timeout()) {
+        executor = executor.with_timeout(Duration::from_secs(timeout));
+    }
+    if let Some(check) = &config.hooks.connectivity_check {
+        executor = executor.with_connectivity_check(None, check.check());
+    }
+    for hook in &config.hooks.pre_sync {
+        executor = executor.with_pre_sync(None, hook);
+    }
+    for hook in &config.hooks.post_sync {
+        executor = executor.with_post_sync(None, hook);
+    }
+    for (account, account_config) in config.enabled_accounts() {
+        if let Some(refresh) = &account_config.token_refresh {
+            executor = executor.with_token_refresh(
+                account,
+                &refresh.command,
+                Duration::from_secs(refresh.lifetime),
+            );
+        }
+        if let Some(check) = &account_config.connectivity_check {
+            executor = executor.with_connectivity_check(Some(account), check.check());
+        }
+        for hook in &account_config.pre_sync {
+            executor = executor.with_pre_sync(Some(account), hook);
+        }
+        for hook in &account_config.post_sync {
+            executor = executor.with_post_sync(Some(account), hook);
+        }
+    }
+    executor
+}
+
+fn mbsync_executor(
+    config: &MbSyncConfig,
+    suppressor: &Option<SyncSuppressor>,
+    accounts: &Accounts,
+    account_configs: &HashMap<String, AccountConfig>,
+) -> Result<MbSyncExecutor, ConfigError> {
+    let mut executor =
+        MbSyncExecutor::new(&config.command, &config.args).with_accounts(accounts.clone());
+    if let Some(mbsyncrc) = config.mbsyncrc() {
+        log::info!("validate the tasks with {:?}", mbsyncrc);
+        executor = executor.with_mbsyncrc(MbSyncRc::read(&mbsyncrc)?);
+    }
+    for (name, value) in &config.variables {
+        executor = executor.with_variable(None, name, value);
+    }
+    for (account, channel) in &config.channel_map {
+        executor = executor.with_channel(account, channel);
+    }
+    for (account, group) in &config.group_map {
+        executor = executor.with_group(account, group);
+    }
+    for (trigger, args) in &config.source_args {
+        executor = executor.with_source_args(trigger.source(), args);
+    }
+    if let Some(scope) = &config.systemd_scope {
+        executor = executor.with_systemd_scope(&scope.properties());
+    }
+    if let Some(lock) = config.lock {
+        let mode = match lock {
+            LockConfig::Wait => LockMode::Wait,
+            LockConfig::Defer => LockMode::Defer,
+        };
+        executor = executor.with_lock_dir(&config.lock_dir(), mode);
+    }
+    for (account, account_config) in &config.accounts {
+        for (name, value) in &account_config.variables {
+            executor = executor.with_variable(Some(account), name, value);
+        }
+        for (mailbox, name) in &account_config.mailboxes {
+            executor = executor.with_mailbox_name(account, mailbox, name);
+        }
+        //only variables keep the account part of --all
+        if account_config.command.is_some() || account_config.args.is_some() {
+            executor = executor.with_account_command(
+                account,
+                account_config.command.as_ref().unwrap_or(&config.command),
+                account_config.args.as_ref().unwrap_or(&config.args),
+            );
+        }
+    }
+    executor = with_command_options(
+        executor,
+        config.passthrough,
+        config.quiet,
+        config.timeout,
+        suppressor,
+    );
+    //the accounts of other users are not part of the --all of mailwatch's user
+    for (account, _) in account_configs.iter().filter(|(account, account_config)| {
+        account_config.run_as.is_some() && !config.accounts.contains_key(*account)
+    }) {
+        executor = executor.with_account_command(account, &config.command, &config.args);
+    }
+    for account in executor.unknown_accounts(&accounts.list()) {
+        log::warn!("account {} has no channel in the config of mbsync", account);
+    }
+    Ok(executor)
+}
+
+fn imapsync_executor(
+    config: &ImapSyncConfig,
+    suppressor: &Option<SyncSuppressor>,
+) -> ImapSyncExecutor {
+    let mut executor = ImapSyncExecutor::new(&config.command, &config.args);
+    for (account, account_config) in &config.accounts {
+        executor = executor.with_account(account, &account_config.args);
+    }
+    with_command_options(
+        executor,
+        config.passthrough,
+        config.quiet,
+        config.timeout,
+        suppressor,
+    )
+}
+
+fn pull_executor(
+    tool: PullTool,
+    config: &PullConfig,
+    suppressor: &Option<SyncSuppressor>,
+) -> PullExecutor {
+    let mut executor = PullExecutor::new(tool, &config.args);
+    if let Some(command) = &config.command {
+        executor = executor.with_command(command);
+    }
+    for (account, rc_file) in &config.rc_files {
+        executor = executor.with_rc_file(account, rc_file);
+    }
+    with_command_options(
+        executor,
+        config.passthrough,
+        config.quiet,
+        config.timeout,
+        suppressor,
+    )
+}
+
+fn shell_executor(config: &ShellConfig, suppressor: &Option<SyncSuppressor>) -> ShellExecutor {
+    let mut executor = ShellExecutor::new(&config.command);
+    if let Some(template) = &config.account_command {
+        executor = executor.with_account_template(template);
+    }
+    if let Some(template) = &config.all_command {
+        executor = executor.with_all_template(template);
+    }
+    with_command_options(
+        executor,
+        config.passthrough,
+        config.quiet,
+        config.timeout,
+        suppressor,
+    )
+}
+
+fn offlineimap_executor(
+    config: &OfflineImapConfig,
+    suppressor: &Option<SyncSuppressor>,
+) -> OfflineImapExecutor {
+    with_command_options(
+        OfflineImapExecutor::new(&config.command, &config.args),
+        config.passthrough,
+        config.quiet,
+        config.timeout,
+        suppressor,
+    )
 }
 
 fn main() {
-    Builder::new()
-        .filter(None, log::LevelFilter::Info)
-        // .filter(Some("localpackage"), log::LevelFilter::Debug)
-        .write_style(env_logger::WriteStyle::Auto)
-        .init();
-    let config = read_config().unwrap();
+    let cli = Cli::parse();
+    //the logging of the config applies once it is read
+    apply_logging(cli.log_level, &LoggingConfig::default());
+    log::set_logger(&ReloadableLogger).unwrap();
+    match cli.command.unwrap_or(CliCommand::Run) {
+        CliCommand::Run => {
+            let config = read_config(cli.config.as_deref()).unwrap();
+            apply_logging(cli.log_level, &config.logging);
+            run(config, cli.config, cli.log_level, cli.dry_run)
+        }
+        CliCommand::Check => {
+            if !check(cli.config.as_deref()) {
+                process::exit(1);
+            }
+        }
+        CliCommand::Once { inboxes } => {
+            let config = read_config(cli.config.as_deref()).unwrap();
+            apply_logging(cli.log_level, &config.logging);
+            process::exit(sync_once(&config, cli.dry_run, |accounts| match inboxes {
+                true => accounts
+                    .list()
+                    .into_iter()
+                    .map(|account| MailUpdaterTask::new(Some(account), Some("INBOX".to_owned())))
+                    .collect(),
+                false => vec![MailUpdaterTask::new(None, None)],
+            }));
+        }
+        CliCommand::Sync { targets } => {
+            let config = read_config(cli.config.as_deref()).unwrap();
+            apply_logging(cli.log_level, &config.logging);
+            //the account ends at the first colon, the mailbox may contain colons
+            let tasks = targets
+                .iter()
+                .map(|target| match target.split_once(':') {
+                    Some((account, mailbox)) => {
+                        MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+                    }
+                    None => MailUpdaterTask::new(Some(target.clone()), None),
+                })
+                .collect();
+            process::exit(sync_once(&config, cli.dry_run, |_| tasks));
+        }
+    }
+}
+
+//the program run by the syncs of the executor
+fn executor_program(config: &Config) -> Option<&str> {
+    match config.executor {
+        ExecutorKind::Mbsync => config.mbsync.as_ref().map(|mbsync| mbsync.command.as_str()),
+        ExecutorKind::Offlineimap => config
+            .offlineimap
+            .as_ref()
+            .map(|offlineimap| offlineimap.command.as_str()),
+        ExecutorKind::Imapsync => config
+            .imapsync
+            .as_ref()
+            .map(|imapsync| imapsync.command.as_str()),
+        ExecutorKind::Getmail => config.getmail.as_ref().map(|getmail| {
+            getmail
+                .command
+                .as_deref()
+                .unwrap_or(PullTool::Getmail.command())
+        }),
+        ExecutorKind::Fetchmail => config.fetchmail.as_ref().map(|fetchmail| {
+            fetchmail
+                .command
+                .as_deref()
+                .unwrap_or(PullTool::Fetchmail.command())
+        }),
+        ExecutorKind::Shell => Some("sh"),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+//like which, a program with a dir is not searched in PATH
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return is_executable(path).then(|| path.to_path_buf());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+//validates the config without starting the daemon, every problem is printed
+fn check(config_file: Option<&Path>) -> bool {
+    let config = match read_config(config_file) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("error: could not read the config: {}", err);
+            return false;
+        }
+    };
+    let mut problems = Vec::new();
+    for root in config.dovecot.roots() {
+        if let Err(err) = fs::read_dir(root.path()) {
+            problems.push(format!(
+                "mail root {:?} is not readable: {}",
+                root.path(),
+                err
+            ));
+        }
+    }
+    if let Some(program) = executor_program(&config) {
+        match find_program(program) {
+            Some(path) => println!("executor runs {}", path.display()),
+            None => problems.push(format!("{} is no executable in PATH", program)),
+        }
+    }
+    if let Err(err) = timer_options(&config) {
+        problems.push(format!("invalid timer: {}", err));
+    }
+    match config.mailbox_filter() {
+        Ok(mailbox_filter) => {
+            for root in config.dovecot.roots() {
+                if let Err(err) = watcher_options(
+                    &config.watcher,
+                    config.dovecot.account_layout(),
+                    mailbox_filter.clone(),
+                    root,
+                    &None,
+                ) {
+                    problems.push(format!("invalid watcher of {:?}: {}", root.path(), err));
+                }
+            }
+        }
+        Err(err) => problems.push(format!("invalid ignored mailboxes: {}", err)),
+    }
+    //the accounts need readable roots
+    if problems.is_empty() {
+        //without the disabled accounts
+        match accounts(&config, config.mailbox_filter().unwrap_or_default()) {
+            Ok(accounts) => {
+                let mut names = accounts.list();
+                names.sort();
+                println!("{} accounts found: {}", names.len(), names.join(", "));
+                if let Err(err) = executor(&config, &None, &accounts) {
+                    problems.push(format!("invalid executor: {}", err));
+                }
+            }
+            Err(err) => problems.push(format!("could not read the accounts: {}", err)),
+        }
+    }
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    if problems.is_empty() {
+        println!("config is valid");
+    }
+    problems.is_empty()
+}
+
+fn accounts(config: &Config, mailbox_filter: MailboxFilter) -> Result<Accounts, io::Error> {
+    Ok(Accounts::with_layout(
+        config
+            .dovecot
+            .roots()
+            .iter()
+            .map(|root| root.path().to_path_buf())
+            .collect(),
+        config.dovecot.account_layout(),
+    )?
+    .with_mailbox_filter(mailbox_filter))
+}
+
+//the exit status of a failed command, otherwise 1
+fn exit_code(err: &ExecError) -> i32 {
+    match err {
+        ExecError::Failed(status, _) => status.code().unwrap_or(1),
+        _ => 1,
+    }
+}
+
+fn once_executor(
+    config: &Config,
+    dry_run: bool,
+) -> Result<(Accounts, Box<dyn Executor>), Box<dyn Error>> {
+    let accounts = accounts(config, config.mailbox_filter()?)?;
+    let executor = wrapped_executor(config, &None, &accounts, dry_run)?;
+    Ok((accounts, executor))
+}
+
+//runs the syncs once without the daemon, e.g. from cron, the exit code is that of the first
+//failed sync
+fn sync_once<F>(config: &Config, dry_run: bool, tasks: F) -> i32
+where
+    F: FnOnce(&Accounts) -> Vec<MailUpdaterTask>,
+{
+    let (accounts, executor) = match once_executor(config, dry_run) {
+        Ok(executor) => executor,
+        Err(err) => {
+            log::error!("invalid config: {}", err);
+            return 1;
+        }
+    };
+    let mut code = 0;
+    for task in tasks(&accounts) {
+        if let Err(err) = executor.execute(&task) {
+            log::error!("sync of {:?} failed: {}", task, err);
+            if code == 0 {
+                code = exit_code(&err);
+            }
+        }
+    }
+    code
+}
+
+fn run(
+    config: Config,
+    config_file: Option<PathBuf>,
+    log_level: Option<log::LevelFilter>,
+    dry_run: bool,
+) -> ! {
+    let mailbox_filter = config.mailbox_filter().unwrap();
+    let accounts = accounts(&config, mailbox_filter.clone()).unwrap();
+    //setup suppression of the changes done by the syncs itself
+    let suppressor = config
+        .watcher
+        .suppress_own_changes
+        .then(|| SyncSuppressor::new(Duration::from_millis(config.watcher.settle_ms)));
     //setup executor
-    let executor = MbSyncExecutor::new(&config.mbsync.command, &config.mbsync.args);
+    let executor = wrapped_executor(&config, &suppressor, &accounts, dry_run).unwrap();
     //setup updater for task handling
-    let updater = MailUpdater::new(move |task| executor.execute(task));
+    let mut updater_options = MailUpdaterOptions::default().with_retry(
+        config.updater.retries,
+        Duration::from_secs(config.updater.retry_delay),
+    );
+    if let Some(max_concurrent) = config.updater.max_concurrent {
+        updater_options = updater_options.with_max_concurrent(max_concurrent);
+    }
+    if let Some(threshold) = config.updater.coalesce_threshold {
+        updater_options = updater_options.with_coalesce_threshold(threshold);
+    }
+    if let Some(max_queue_len) = config.updater.max_queue_len {
+        updater_options = updater_options.with_max_queue_len(max_queue_len);
+    }
+    if let Some(max_age) = config.updater.max_age {
+        updater_options = updater_options.with_max_age(Duration::from_secs(max_age));
+    }
+    if let Some(min_interval) = config.updater.min_interval {
+        updater_options = updater_options.with_min_interval(Duration::from_secs(min_interval));
+    }
+    if let Some(failures) = config.updater.circuit_failures {
+        updater_options = updater_options.with_circuit_breaker(
+            failures,
+            Duration::from_secs(config.updater.circuit_cooldown),
+        );
+    }
+    if let Some(state_file) = config.updater.state_file() {
+        updater_options = updater_options.with_state_file(&state_file);
+    }
+    //the state file is written while running, so its age tells the time of the last shutdown
+    let last_shutdown = config
+        .updater
+        .state_file()
+        .and_then(|state_file| fs::metadata(state_file).ok())
+        .and_then(|metadata| metadata.modified().ok());
+    let updater = MailUpdater::with_options(executor, updater_options);
+    #[cfg(unix)]
+    handle_termination(
+        updater.clone(),
+        Duration::from_secs(config.updater.shutdown_timeout),
+    )
+    .unwrap();
+    #[cfg(unix)]
+    handle_sync_signal(updater.clone()).unwrap();
+    record_pulled_activity(&updater, accounts.clone());
+    if let Some(interval) = config.updater.statistics_interval {
+        log_statistics(updater.clone(), Duration::from_secs(interval));
+    }
     //setup timer for time based updates
     let timer_updater = updater.clone();
-    run_timer(
-        config.timer.inboxes,
-        config.timer.all,
-        get_inboxes(&config.dovecot.dir).unwrap(),
-        move |task| {
-            timer_updater.queue_task(task);
-        },
-    );
-    //setup filepatcher
-    queue_filewatch_tasks(&config.dovecot.dir, &updater).unwrap();
+    let mut timer_options = timer_options(&config).unwrap();
+    let mut startup_sync = config.timer.startup.startup_sync();
+    if config.timer.startup == StartupConfig::CatchUp {
+        match last_shutdown {
+            Some(since) => {
+                queue_changed_mailboxes(&config, &mailbox_filter, since, &accounts, &updater)
+            }
+            None => {
+                log::warn!("time of the last shutdown is unknown, sync all at startup");
+                startup_sync = StartupSync::All;
+            }
+        }
+    }
+    timer_options = timer_options.with_startup_sync(startup_sync);
+    if let Some(stagger) = config.timer.startup_stagger {
+        timer_options = timer_options.with_startup_stagger(Duration::from_secs(stagger));
+    }
+    let timer = run_timer(timer_options, accounts.clone(), move |task| {
+        timer_updater.queue_task(task);
+    });
+    //setup receiver of dovecot's push notifications
+    if let Some(push_config) = &config.push {
+        let push_accounts = accounts.clone();
+        let push_updater = updater.clone();
+        //anyone reaching the address may send them, so only known accounts are synced. the login
+        //of the user is mapped to the account like the users of the log
+        let address = run_push_receiver(&push_config.listen, move |mut task| {
+            let account_of_user = |user: &String| {
+                push_accounts.account_of_user(user).or_else(|| {
+                    //the dir of a new account may be created by the delivery
+                    if let Err(err) = push_accounts.refresh() {
+                        log::warn!("could not refresh the accounts: {}", err);
+                    }
+                    push_accounts.account_of_user(user)
+                })
+            };
+            let Some(account) = task
+                .specific_account
+                .as_ref()
+                .and_then(account_of_user)
+                .filter(|account| !push_accounts.is_disabled(account))
+            else {
+                log::warn!("reject push notification of {:?}", task);
+                return false;
+            };
+            //like the changes seen by the watcher
+            if let Some(mailbox) = &task.specific_mailbox {
+                if push_accounts.is_mailbox_ignored(&account, mailbox) {
+                    return true;
+                }
+            }
+            task.specific_account = Some(account);
+            push_updater.queue_task(task);
+            true
+        })
+        .unwrap();
+        log::info!("listening for push notifications on {}", address);
+    }
+    //setup logwatcher as additional or alternative source of changes
+    let log_thread = config.logwatcher.as_ref().map(|logwatcher_config| {
+        let log_watcher = log_watcher(logwatcher_config).unwrap();
+        let accounts = accounts.clone();
+        let updater = updater.clone();
+        thread::spawn(move || queue_logwatch_tasks(log_watcher, &accounts, &updater))
+    });
+    //setup filepatcher for every root
+    let watchers = match config.watcher.enabled {
+        true => watch_roots(&config, &mailbox_filter, &suppressor, &accounts, &updater).unwrap(),
+        false => Vec::new(),
+    };
+    let (threads, reloads): (Vec<_>, HashMap<_, _>) = watchers
+        .into_iter()
+        .map(|watcher| (watcher.thread, (watcher.path, watcher.reload)))
+        .unzip();
+    #[cfg(unix)]
+    handle_reload_signal(Reloader {
+        config_file,
+        log_level,
+        dry_run,
+        suppressor,
+        accounts,
+        updater,
+        timer,
+        watchers: reloads,
+        updater_config: config.updater,
+    })
+    .unwrap();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    if let Some(log_thread) = log_thread {
+        log_thread.join().unwrap();
+    }
+    //the timer and the push receiver keep running without any watcher
+    loop {
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, time::Duration};
+
+    use tempfile::TempDir;
+
+    use super::{
+        merge_config, override_by_env, read_config_includes, typed_config, MailRootConfig,
+        MailRootsConfig, Schedule,
+    };
+
+    fn table(content: &str) -> toml::Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn it_should_merge_the_tables_of_fragments() {
+        let mut config = table(
+            "[watcher]\nsettle_ms = 100\nbackend = \"poll\"\n[mbsync]\nargs = [\"-a\", \"-V\"]\n[push]\nlisten = \"127.0.0.1:8080\"\n",
+        );
+        merge_config(
+            &mut config,
+            table("push = false\n[watcher]\nsettle_ms = 500\n[mbsync]\nargs = [\"-q\"]\n"),
+        );
+        assert_eq!(
+            table("push = false\n[watcher]\nsettle_ms = 500\nbackend = \"poll\"\n[mbsync]\nargs = [\"-q\"]\n"),
+            config
+        );
+    }
+
+    #[test]
+    fn it_should_include_the_fragments_in_order() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("accounts.d")).unwrap();
+        fs::write(
+            dir.path().join("mailwatch.toml"),
+            "include = [\"accounts.d/*.toml\", \"local.json\"]\n[accounts.work]\nenabled = true\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("accounts.d/20-work.toml"),
+            "[accounts.work]\npre_sync = [\"vpn-up\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("accounts.d/10-work.toml"),
+            "[accounts.work]\nenabled = false\npre_sync = [\"true\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("local.json"),
+            r#"{"accounts": {"private": {"enabled": false}}}"#,
+        )
+        .unwrap();
+        //the patterns are relative to the dir of the config, not to the working dir
+        assert_eq!(
+            table("[accounts.work]\nenabled = false\npre_sync = [\"vpn-up\"]\n[accounts.private]\nenabled = false\n"),
+            read_config_includes(&dir.path().join("mailwatch.toml")).unwrap()
+        );
+    }
+
+    fn overridden(config: &str, vars: &[(&str, &str)]) -> toml::Table {
+        let mut config = toml::from_str(config).unwrap();
+        override_by_env(
+            &mut config,
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        config
+    }
+
+    #[test]
+    fn it_should_override_the_config_by_the_environment() {
+        let config = overridden(
+            "[watcher]\nsettle_ms = 100\n",
+            &[
+                ("MAILWATCH_WATCHER__SETTLE_MS", "500"),
+                ("MAILWATCH_MBSYNC__ARGS", "[\"-V\"]"),
+                ("MAILWATCH_MBSYNC__COMMAND", "/usr/local/bin/mbsync"),
+            ],
+        );
+        assert_eq!(
+            toml::from_str::<toml::Table>(
+                "[watcher]\nsettle_ms = 500\n[mbsync]\nargs = [\"-V\"]\ncommand = \"/usr/local/bin/mbsync\"\n"
+            )
+            .unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_should_keep_the_case_of_the_names_of_the_config() {
+        let config = overridden(
+            "[accounts.Work]\nenabled = true\n",
+            &[
+                ("MAILWATCH_ACCOUNTS__WORK__ENABLED", "false"),
+                ("MAILWATCH_ACCOUNTS__Private__ENABLED", "false"),
+            ],
+        );
+        assert_eq!(
+            toml::from_str::<toml::Table>(
+                "[accounts.Work]\nenabled = false\n[accounts.Private]\nenabled = \"false\"\n"
+            )
+            .unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn it_should_keep_overrides_as_strings_unless_the_config_needs_their_type() {
+        let mut config = toml::from_str(
+            "[dovecot]\ndir = \"/srv/mail\"\n[timer]\ninboxes = \"0 * * * * *\"\nall = 3600\n[mbsync]\ncommand = \"mbsync\"\nargs = []\n",
+        )
+        .unwrap();
+        let ambiguous = override_by_env(
+            &mut config,
+            [
+                ("MAILWATCH_DOVECOT__DIR", "2024"),
+                ("MAILWATCH_MBSYNC__COMMAND", "true"),
+                ("MAILWATCH_TIMER__INBOXES", "60"),
+                ("MAILWATCH_ACCOUNTS__Private__ENABLED", "false"),
+            ]
+            .map(|(name, value)| (name.to_owned(), value.to_owned())),
+        );
+        assert_eq!(Some("2024"), config["dovecot"]["dir"].as_str());
+        let config = typed_config(config, &ambiguous).unwrap();
+        assert!(
+            matches!(config.dovecot.dir, MailRootsConfig::Single(MailRootConfig::Path(dir)) if dir == Path::new("2024"))
+        );
+        assert_eq!("true", config.mbsync.unwrap().command);
+        assert!(matches!(
+            config.timer.inboxes.schedule(),
+            Ok(Schedule::Interval(interval)) if interval == Duration::from_secs(60)
+        ));
+        assert!(!config.accounts["Private"].enabled);
+    }
+
+    #[test]
+    fn it_should_ignore_other_variables() {
+        let config = overridden(
+            "[dovecot]\ndir = \"/srv/mail\"\n",
+            &[
+                ("MAILWATCH_ACCOUNT", "work"),
+                ("MAILWATCH_MAILBOX", "INBOX"),
+                ("DOVECOT__DIR", "/tmp"),
+            ],
+        );
+        assert_eq!(
+            toml::from_str::<toml::Table>("[dovecot]\ndir = \"/srv/mail\"\n").unwrap(),
+            config
+        );
+    }
 }
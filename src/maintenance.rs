@@ -0,0 +1,66 @@
+use std::{process::Command, time::Duration};
+
+use crate::executor::{CommandRunner, ExecError, ExecReport, FailureKind};
+
+//a command run by the timer beside the syncs, e.g. to expunge old mails
+#[derive(Debug, Clone)]
+pub struct MaintenanceJob {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    runner: CommandRunner,
+}
+
+impl MaintenanceJob {
+    pub fn new(name: &str, command: &str, args: &[String]) -> Self {
+        Self {
+            name: name.to_owned(),
+            command: command.to_owned(),
+            args: args.to_vec(),
+            runner: CommandRunner::default(),
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.runner.timeout = Some(timeout);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn execute(&self) -> Result<ExecReport, ExecError> {
+        log::info!("execute maintenance {}", self.name);
+        let mut command = Command::new(&self.command);
+        command.args(&self.args);
+        let status = self.runner.run(command, &self.name)?.status;
+        if !status.success() {
+            return Err(ExecError::Failed(status, FailureKind::Other));
+        }
+        Ok(ExecReport::with_status(status))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::MaintenanceJob;
+    use crate::executor::ExecError;
+
+    #[test]
+    fn it_should_report_the_result_of_the_command() {
+        let job = MaintenanceJob::new("compact", "sh", &["-c".to_owned(), "exit 0".to_owned()]);
+        assert!(job.execute().unwrap().status.unwrap().success());
+        let job = MaintenanceJob::new("compact", "sh", &["-c".to_owned(), "exit 3".to_owned()]);
+        assert!(
+            matches!(job.execute(), Err(ExecError::Failed(status, _)) if status.code() == Some(3))
+        );
+        let job = MaintenanceJob::new("compact", "/nonexistent/command", &[]);
+        assert!(matches!(job.execute(), Err(ExecError::IoError(_))));
+        let job = MaintenanceJob::new("compact", "sleep", &["10".to_owned()])
+            .with_timeout(Duration::from_millis(100));
+        assert!(matches!(job.execute(), Err(ExecError::Timeout(_))));
+    }
+}
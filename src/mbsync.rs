@@ -1,13 +1,176 @@
 use std::{
-    io,
-    process::{Command, Stdio},
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions, TryLockError},
+    iter,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
 };
 
-use crate::updater::MailUpdaterTask;
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::{
+    accounts::Accounts,
+    executor::{
+        execute_accounts, CommandRunner, ExecError, ExecReport, Executor, FailureKind,
+        RunnerOptions, SyncCounts,
+    },
+    mbsyncrc::MbSyncRc,
+    updater::{MailUpdaterTask, TaskSource},
+};
+
+//what happens to a sync of a channel locked by another mbsync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Wait,
+    Defer,
+}
 
 pub struct MbSyncExecutor {
     command: String,
     args: Vec<String>,
+    runner: CommandRunner,
+    //accounts with their own command and arguments, e.g. another config file
+    accounts: HashMap<String, (String, Vec<String>)>,
+    //values of placeholders in the arguments, those of an account take precedence
+    variables: HashMap<String, String>,
+    account_variables: HashMap<String, HashMap<String, String>>,
+    //the channel of an account, if it is named otherwise than its directory
+    channels: HashMap<String, String>,
+    //accounts synced by a group of channels instead
+    groups: HashMap<String, String>,
+    //options depending on the trigger of the sync, e.g. --pull --new for the timer
+    source_args: HashMap<TaskSource, Vec<String>>,
+    //the remote names of the mailboxes of an account, if named otherwise than in dovecot
+    mailbox_names: HashMap<String, HashMap<String, String>>,
+    //the directory of the lock files of the channels
+    locks: Option<(PathBuf, LockMode)>,
+    //properties of the transient systemd scope of the syncs, e.g. MemoryMax=1G
+    scope: Option<Vec<String>>,
+    //the config of mbsync to validate the tasks
+    rc: Option<MbSyncRc>,
+    //the accounts synced by --all, locked by their channel on a sync of all accounts
+    known: Option<Accounts>,
+}
+
+//the summary of mbsync, older versions name the sides master and slave
+static PULLED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"pulled (\d+) new message\(s\) and (\d+) flag update\(s\)").unwrap());
+static PUSHED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"pushed (\d+) new message\(s\) and (\d+) flag update\(s\)").unwrap());
+static EXPUNGED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"expunged (\d+) message\(s\) from").unwrap());
+
+//transient failures, e.g. a lost wifi or a failed handshake by a captive portal. the messages of
+//mbsync and of the os, a missing host in the config or a rejected certificate are no such failure.
+static NETWORK_ERROR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)cannot resolve|name resolution|name or service not known|cannot connect|connection (refused|reset|timed out|closed)|reset by peer|network is unreachable|no route to host|socket error|timed out|timeout|(ssl|tls) handshake|broken pipe|unexpected eof",
+    )
+    .unwrap()
+});
+//the diagnostics of the config, not every line with a path like ~/.config/isync/mbsyncrc
+static CONFIG_ERROR: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?i)no such channel|no (channel|group) named|unknown (keyword|option|channel|store|section)|no channels defined|refers to (undefined|non-existing) store|cannot (open|read) config|:\d+: (unknown|missing|invalid|unterminated|excess|superfluous|keyword|parameter)|:\d+: .* is not recognized",
+    )
+    .unwrap()
+});
+
+//mbsync exits with 1 on every error, so its messages tell the cause
+fn classify_failure(status: ExitStatus, errors: &[String]) -> FailureKind {
+    if status.code().is_none() {
+        return FailureKind::Interrupted;
+    }
+    if errors.iter().any(|line| CONFIG_ERROR.is_match(line)) {
+        FailureKind::Config
+    } else if errors.iter().any(|line| NETWORK_ERROR.is_match(line)) {
+        FailureKind::Network
+    } else {
+        FailureKind::Other
+    }
+}
+
+const TARGET_PLACEHOLDERS: [&str; 3] = ["{target}", "{account}", "{mailbox}"];
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"(:?)\{(\w+)\}").unwrap());
+
+//{target} is account:mailbox, account or --all. an argument with {account} is the target on a
+//full sync, e.g. {account}:{mailbox} becomes --all. one with a missing mailbox is left out,
+//except :{mailbox} which is just removed.
+fn expand(
+    arg: &str,
+    task: &MailUpdaterTask,
+    target: &str,
+    variables: &HashMap<&str, &str>,
+) -> Option<String> {
+    if task.specific_account.is_none() && arg.contains("{account}") {
+        return Some(target.to_owned());
+    }
+    //a single pass, the values are never expanded again
+    let mut missing = false;
+    let arg = PLACEHOLDER.replace_all(arg, |caps: &Captures| {
+        let value = match &caps[2] {
+            "target" => Some(target),
+            "account" => task.specific_account.as_deref(),
+            "mailbox" if task.specific_mailbox.is_none() && !caps[1].is_empty() => {
+                return String::new()
+            }
+            "mailbox" => task.specific_mailbox.as_deref(),
+            name => match variables.get(name) {
+                Some(value) => Some(*value),
+                None => return caps[0].to_owned(),
+            },
+        };
+        match value {
+            Some(value) => format!("{}{}", &caps[1], value),
+            None => {
+                missing = true;
+                String::new()
+            }
+        }
+    });
+    (!missing).then(|| arg.into_owned())
+}
+
+//mbsync splits channel:mailbox at the first colon and the mailboxes at commas, so colons and
+//spaces need no escaping, but a mailbox with a comma can only be synced with its channel
+fn escaped(task: &MailUpdaterTask) -> MailUpdaterTask {
+    match &task.specific_mailbox {
+        Some(mailbox) if mailbox.contains(',') => {
+            log::warn!(
+                "mailbox {} can not be passed to mbsync, sync the whole account",
+                mailbox
+            );
+            MailUpdaterTask {
+                specific_mailbox: None,
+                ..task.clone()
+            }
+        }
+        _ => task.clone(),
+    }
+}
+
+//the counts of the summary, none without one, e.g. with -q
+fn parse_counts(lines: &[String]) -> Option<SyncCounts> {
+    let mut counts = SyncCounts::default();
+    let mut found = false;
+    let number = |value: &str| value.parse::<u64>().unwrap_or_default();
+    for line in lines {
+        if let Some(caps) = PULLED.captures(line) {
+            counts.pulled += number(&caps[1]);
+            counts.pulled_flags += number(&caps[2]);
+            found = true;
+        } else if let Some(caps) = PUSHED.captures(line) {
+            counts.pushed += number(&caps[1]);
+            counts.pushed_flags += number(&caps[2]);
+            found = true;
+        } else if let Some(caps) = EXPUNGED.captures(line) {
+            counts.expunged += number(&caps[1]);
+            found = true;
+        }
+    }
+    found.then_some(counts)
 }
 
 impl MbSyncExecutor {
@@ -15,40 +178,971 @@ impl MbSyncExecutor {
         Self {
             command: command.to_owned(),
             args: args.iter().map(|arg| arg.to_owned()).collect(),
+            runner: CommandRunner::default(),
+            accounts: HashMap::new(),
+            variables: HashMap::new(),
+            account_variables: HashMap::new(),
+            channels: HashMap::new(),
+            groups: HashMap::new(),
+            source_args: HashMap::new(),
+            mailbox_names: HashMap::new(),
+            locks: None,
+            scope: None,
+            rc: None,
+            known: None,
         }
     }
 
-    fn execute_command(&self, task: &MailUpdaterTask) -> Result<(), io::Error> {
-        let mut command = Command::new(&self.command);
-        command
-            .args(&self.args)
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit());
-        match &task.specific_account {
-            Some(acc) => {
-                let arg = format!(
-                    "{}{}",
-                    acc,
-                    match &task.specific_mailbox {
-                        Some(mailbox) => format!(":{}", mailbox),
-                        None => "".to_owned(),
-                    }
-                );
-                log::info!("execut command with {}", arg);
-                command.arg(arg);
-            }
+    //the value of the placeholder {name}, e.g. {config} for a config file of the account
+    pub fn with_variable(mut self, account: Option<&str>, name: &str, value: &str) -> Self {
+        let variables = match account {
+            Some(account) => self
+                .account_variables
+                .entry(account.to_owned())
+                .or_default(),
+            None => &mut self.variables,
+        };
+        variables.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    //e.g. Junk of dovecot is [Gmail]/Spam for mbsync
+    pub fn with_mailbox_name(mut self, account: &str, mailbox: &str, name: &str) -> Self {
+        self.mailbox_names
+            .entry(account.to_owned())
+            .or_default()
+            .insert(mailbox.to_owned(), name.to_owned());
+        self
+    }
+
+    //the task with the mailbox name of mbsync
+    fn translated(&self, task: &MailUpdaterTask) -> MailUpdaterTask {
+        let name = match (&task.specific_account, &task.specific_mailbox) {
+            (Some(account), Some(mailbox)) => self
+                .mailbox_names
+                .get(account)
+                .and_then(|names| names.get(mailbox)),
+            _ => None,
+        };
+        escaped(&MailUpdaterTask {
+            specific_mailbox: name.or(task.specific_mailbox.as_ref()).cloned(),
+            ..task.clone()
+        })
+    }
+
+    //the channel of the target argument, {account} is still the account
+    pub fn with_channel(mut self, account: &str, channel: &str) -> Self {
+        self.channels.insert(account.to_owned(), channel.to_owned());
+        self
+    }
+
+    //a sync locks <channel>.lock in the directory with flock, a manual run may use the same lock,
+    //e.g. flock <dir>/work.lock mbsync work. a sync of all accounts locks all.lock and the locks of
+    //every known channel, a sync of a group the group and its channels.
+    pub fn with_lock_dir(mut self, dir: &Path, mode: LockMode) -> Self {
+        self.locks = Some((dir.to_path_buf(), mode));
+        self
+    }
+
+    //the channels of accounts without a mapping are only known by the accounts
+    pub fn with_accounts(mut self, accounts: Accounts) -> Self {
+        self.known = Some(accounts);
+        self
+    }
+
+    //sorted, so concurrent syncs take the locks in the same order
+    fn lock_names(&self, task: &MailUpdaterTask) -> Vec<String> {
+        let mut names = match &task.specific_account {
             None => {
-                log::info!("execute command with --all");
-                command.arg("--all");
+                let accounts = self
+                    .known
+                    .iter()
+                    .flat_map(Accounts::list)
+                    .collect::<Vec<_>>();
+                let known = self
+                    .channels
+                    .values()
+                    .chain(self.groups.values())
+                    .chain(self.rc.iter().flat_map(|rc| rc.channels()))
+                    .chain(accounts.iter().map(|account| self.channel(account)));
+                iter::once("all")
+                    .chain(known.map(String::as_str))
+                    .map(str::to_owned)
+                    .collect()
             }
+            Some(account) => match (self.groups.get(account), &task.specific_mailbox) {
+                (Some(group), Some(mailbox)) if self.group_channels(group, mailbox).len() == 1 => {
+                    vec![self.group_channels(group, mailbox)[0].clone()]
+                }
+                (Some(group), _) => {
+                    let members = self.rc.as_ref().and_then(|rc| rc.group(group));
+                    iter::once(group)
+                        .chain(members.into_iter().flatten())
+                        .cloned()
+                        .collect()
+                }
+                (None, _) => vec![self.channel(account).clone()],
+            },
+        };
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    //the locks are released by dropping the files
+    fn lock(&self, task: &MailUpdaterTask) -> Result<Vec<File>, ExecError> {
+        let Some((dir, mode)) = &self.locks else {
+            return Ok(Vec::new());
+        };
+        fs::create_dir_all(dir)?;
+        let mut files = Vec::new();
+        for name in self.lock_names(task) {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join(format!("{}.lock", name)))?;
+            match mode {
+                LockMode::Wait => file.lock()?,
+                LockMode::Defer => match file.try_lock() {
+                    Ok(()) => {}
+                    Err(TryLockError::WouldBlock) => {
+                        return Err(ExecError::Deferred(format!("lock of {}", name)))
+                    }
+                    Err(TryLockError::Error(err)) => return Err(err.into()),
+                },
+            }
+            files.push(file);
+        }
+        Ok(files)
+    }
+
+    //runs mbsync by systemd-run --user --scope, so its resources are limited and it is its own unit
+    pub fn with_systemd_scope(mut self, properties: &[String]) -> Self {
+        self.scope = Some(properties.to_vec());
+        self
+    }
+
+    //the command itself or systemd-run with the command
+    fn command(&self, program: &str, target: &str) -> Command {
+        let Some(properties) = &self.scope else {
+            return Command::new(program);
+        };
+        //unit names allow only a few characters and have to be unique
+        let name = target
+            .trim_start_matches('-')
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        let mut command = Command::new("systemd-run");
+        command
+            .args(["--user", "--scope", "--quiet"])
+            .arg(format!("--unit=mailwatch-{}-{}", name, fastrand::u32(..)))
+            .arg(format!("--description=mailwatch sync of {}", target));
+        for property in properties {
+            command.arg(format!("--property={}", property));
         }
-        command.spawn()?.wait()?;
-        Ok(())
+        command.arg("--").arg(program);
+        command
+    }
+
+    //options before the arguments of the tasks of the source, a sync of all accounts is always full
+    pub fn with_source_args(mut self, source: TaskSource, args: &[String]) -> Self {
+        self.source_args.insert(source, args.to_vec());
+        self
+    }
+
+    //a task of the account runs mbsync with the group, one of a mailbox runs the channel of the
+    //group including the mailbox if known by the mbsyncrc
+    pub fn with_group(mut self, account: &str, group: &str) -> Self {
+        self.groups.insert(account.to_owned(), group.to_owned());
+        self
+    }
+
+    //the group or channel of the account
+    fn channel<'a>(&'a self, account: &'a String) -> &'a String {
+        self.groups
+            .get(account)
+            .or_else(|| self.channels.get(account))
+            .unwrap_or(account)
     }
 
-    pub fn execute(&self, task: &MailUpdaterTask) {
-        if let Err(err) = self.execute_command(task) {
-            log::error!("error while executing command: {}", err);
+    //the channels of the group which sync the mailbox
+    fn group_channels<'a>(&'a self, group: &str, mailbox: &'a str) -> Vec<&'a String> {
+        let Some((rc, members)) = self
+            .rc
+            .as_ref()
+            .and_then(|rc| rc.group(group).map(|members| (rc, members)))
+        else {
+            return Vec::new();
+        };
+        members
+            .iter()
+            .filter(|member| {
+                rc.channel(member)
+                    .is_none_or(|channel| channel.includes(mailbox))
+            })
+            .collect()
+    }
+
+    fn target(&self, task: &MailUpdaterTask) -> String {
+        let Some(account) = &task.specific_account else {
+            return "--all".to_owned();
+        };
+        match (self.groups.get(account), &task.specific_mailbox) {
+            (Some(group), Some(mailbox)) => match self.group_channels(group, mailbox)[..] {
+                [channel] => format!("{}:{}", channel, mailbox),
+                _ => group.clone(),
+            },
+            (Some(group), None) => group.clone(),
+            (None, Some(mailbox)) => format!("{}:{}", self.channel(account), mailbox),
+            (None, None) => self.channel(account).clone(),
         }
     }
+
+    //tasks of unknown channels fail and those of mailboxes excluded by the patterns are skipped
+    pub fn with_mbsyncrc(mut self, rc: MbSyncRc) -> Self {
+        self.rc = Some(rc);
+        self
+    }
+
+    //the accounts without a channel or group in the config of mbsync
+    pub fn unknown_accounts(&self, accounts: &[String]) -> Vec<String> {
+        let Some(rc) = &self.rc else {
+            return Vec::new();
+        };
+        accounts
+            .iter()
+            .filter(|account| !self.accounts.contains_key(*account))
+            .filter(|account| !rc.contains(self.channel(account)))
+            .cloned()
+            .collect()
+    }
+
+    //false if mbsync would not sync the mailbox anyway
+    fn validate(&self, task: &MailUpdaterTask) -> Result<bool, ExecError> {
+        let (Some(rc), Some(account)) = (&self.rc, &task.specific_account) else {
+            return Ok(true);
+        };
+        //accounts with their own command may use another config
+        if self.accounts.contains_key(account) {
+            return Ok(true);
+        }
+        let channel = self.channel(account);
+        if !rc.contains(channel) {
+            return Err(ExecError::Config(format!(
+                "no mbsync channel {} for account {}",
+                channel, account
+            )));
+        }
+        Ok(match (rc.channel(channel), &task.specific_mailbox) {
+            (Some(channel), Some(mailbox)) => channel.includes(mailbox),
+            (None, Some(mailbox)) => !self.group_channels(channel, mailbox).is_empty(),
+            _ => true,
+        })
+    }
+
+    //a sync of all accounts runs the command for the account separately
+    pub fn with_account_command(mut self, account: &str, command: &str, args: &[String]) -> Self {
+        self.accounts
+            .insert(account.to_owned(), (command.to_owned(), args.to_vec()));
+        self
+    }
+
+    //the channels of the mbsyncrc without those of the excluded accounts, none without the mbsyncrc
+    fn channels_except(&self, excluded: &[String]) -> Option<Vec<String>> {
+        let rc = self.rc.as_ref()?;
+        let excluded = excluded
+            .iter()
+            .flat_map(|account| match self.groups.get(account) {
+                Some(group) => iter::once(group)
+                    .chain(rc.group(group).into_iter().flatten())
+                    .collect(),
+                None => vec![self.channel(account)],
+            })
+            .collect::<HashSet<_>>();
+        let mut channels = rc
+            .channels()
+            .filter(|channel| !excluded.contains(channel))
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort_unstable();
+        Some(channels)
+    }
+
+    fn execute_command(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Result<ExecReport, ExecError> {
+        let task = &self.translated(task);
+        if !self.validate(task)? {
+            log::info!(
+                "skip {}:{}, it is excluded by the patterns of mbsync",
+                task.specific_account.as_deref().unwrap_or_default(),
+                task.specific_mailbox.as_deref().unwrap_or_default()
+            );
+            return Ok(ExecReport::default());
+        }
+        let _lock = self.lock(task)?;
+        let (command, args) = task
+            .specific_account
+            .as_ref()
+            .and_then(|account| self.accounts.get(account))
+            .map_or((&self.command, &self.args), |(command, args)| {
+                (command, args)
+            });
+        let full = task.specific_account.is_none();
+        let targets = match self.channels_except(excluded) {
+            Some(channels) if full && !excluded.is_empty() => channels,
+            _ => vec![self.target(task)],
+        };
+        let mut result = match targets.is_empty() {
+            true => Ok(ExecReport::default()),
+            false => self.run_command(command, args, task, &targets, full),
+        };
+        if full {
+            //the accounts with their own command are not part of --all
+            let mut accounts = self
+                .accounts
+                .keys()
+                .filter(|account| !excluded.contains(account))
+                .collect::<Vec<_>>();
+            accounts.sort_unstable();
+            let accounts_result = execute_accounts(accounts, |account| {
+                let (command, args) = &self.accounts[*account];
+                let account_task =
+                    MailUpdaterTask::new(Some((*account).clone()), None).with_source(task.source);
+                let targets = [self.target(&account_task)];
+                self.run_command(command, args, &account_task, &targets, full)
+            });
+            result = match (result, accounts_result) {
+                (Ok(mut report), Ok(accounts_report)) => {
+                    report.merge(accounts_report);
+                    Ok(report)
+                }
+                (Err(err), _) | (Ok(_), Err(err)) => Err(err),
+            };
+        }
+        result
+    }
+
+    fn run_command(
+        &self,
+        program: &str,
+        args: &[String],
+        task: &MailUpdaterTask,
+        targets: &[String],
+        full: bool,
+    ) -> Result<ExecReport, ExecError> {
+        let target = targets.join(" ");
+        let mut command = self.command(program, &target);
+        let mut variables: HashMap<&str, &str> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        if let Some(account_variables) = task
+            .specific_account
+            .as_ref()
+            .and_then(|account| self.account_variables.get(account))
+        {
+            variables.extend(
+                account_variables
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str())),
+            );
+        }
+        if let Some(source_args) = self.source_args.get(&task.source).filter(|_| !full) {
+            command.args(source_args);
+        }
+        //an argument with {target}, or with {account} on a full sync, is repeated for every target
+        for arg in args {
+            let targeted = arg.contains("{target}")
+                || (task.specific_account.is_none() && arg.contains("{account}"));
+            match targeted {
+                true => command.args(
+                    targets
+                        .iter()
+                        .filter_map(|target| expand(arg, task, target, &variables)),
+                ),
+                false => command.args(expand(arg, task, &target, &variables)),
+            };
+        }
+        //without a placeholder the target is the last argument
+        let templated = args.iter().any(|arg| {
+            TARGET_PLACEHOLDERS
+                .iter()
+                .any(|placeholder| arg.contains(placeholder))
+        });
+        if !templated {
+            command.args(targets);
+        }
+        log::info!("execute command with {} ({:?})", target, task.source);
+        let label = match &task.specific_account {
+            Some(_) => target,
+            None => "all".to_owned(),
+        };
+        self.runner
+            .prepare(&mut command, task.specific_account.as_deref());
+        let output = self.runner.run(command, &label)?;
+        let status = output.status;
+        if !status.success() {
+            return Err(ExecError::Failed(
+                status,
+                classify_failure(status, &output.errors),
+            ));
+        }
+        let report = ExecReport::with_status(status);
+        Ok(match parse_counts(&output.lines) {
+            Some(counts) => {
+                log::info!(
+                    "[{}] pulled {} messages, pushed {} messages",
+                    label,
+                    counts.pulled,
+                    counts.pushed
+                );
+                report.with_counts(counts)
+            }
+            None => report,
+        })
+    }
+}
+
+impl RunnerOptions for MbSyncExecutor {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl Executor for MbSyncExecutor {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.runner
+            .suppressed(task, || self.execute_command(task, &[]))
+    }
+
+    //the channels of the mbsyncrc are synced instead of --all
+    fn execute_except(
+        &self,
+        task: &MailUpdaterTask,
+        excluded: &[String],
+    ) -> Option<Result<ExecReport, ExecError>> {
+        self.rc.as_ref()?;
+        Some(
+            self.runner
+                .suppressed(task, || self.execute_command(task, excluded)),
+        )
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs::{self, File},
+        os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+        process::ExitStatus,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::TempDir;
+
+    use super::{classify_failure, escaped, expand, parse_counts, LockMode, MbSyncExecutor};
+    use crate::{
+        accounts::Accounts,
+        executor::{ExecError, Executor, FailureKind, RunnerOptions, SyncCounts},
+        mbsyncrc::MbSyncRc,
+        updater::{MailUpdaterTask, TaskSource},
+    };
+
+    #[test]
+    fn it_should_parse_the_summary() {
+        let output = "Processed 6 box(es) in 1 channel(s),
+pulled 3 new message(s) and 2 flag update(s) into near side,
+pushed 1 new message(s) and 0 flag update(s) into far side,
+expunged 4 message(s) from far side,
+expunged 0 message(s) from near side.";
+        let lines = output.lines().map(str::to_owned).collect::<Vec<_>>();
+        assert_eq!(
+            Some(SyncCounts {
+                pulled: 3,
+                pulled_flags: 2,
+                pushed: 1,
+                pushed_flags: 0,
+                expunged: 4,
+            }),
+            parse_counts(&lines)
+        );
+        assert_eq!(None, parse_counts(&["C: 1/1  B: 6/6".to_owned()]));
+    }
+
+    #[test]
+    fn it_should_expand_placeholders() {
+        let variables = HashMap::from([("config", "~/.mbsyncrc-work")]);
+        let mailbox_task = MailUpdaterTask::new(Some("work".to_owned()), Some("INBOX".to_owned()));
+        let account_task = MailUpdaterTask::new(Some("work".to_owned()), None);
+        let all_task = MailUpdaterTask::new(None, None);
+        let expanded =
+            |arg: &str, task: &MailUpdaterTask, target: &str| expand(arg, task, target, &variables);
+        assert_eq!(
+            Some("~/.mbsyncrc-work".to_owned()),
+            expanded("{config}", &all_task, "--all")
+        );
+        assert_eq!(
+            Some("work:INBOX".to_owned()),
+            expanded("{account}:{mailbox}", &mailbox_task, "work:INBOX")
+        );
+        assert_eq!(
+            Some("work".to_owned()),
+            expanded("{account}:{mailbox}", &account_task, "work")
+        );
+        assert_eq!(
+            Some("--all".to_owned()),
+            expanded("{account}:{mailbox}", &all_task, "--all")
+        );
+        assert_eq!(None, expanded("--folder={mailbox}", &account_task, "work"));
+        assert_eq!(
+            Some("--all".to_owned()),
+            expanded("{target}", &all_task, "--all")
+        );
+        assert_eq!(
+            Some("{unknown}".to_owned()),
+            expanded("{unknown}", &all_task, "--all")
+        );
+        //the names are not expanded again
+        let braced_task =
+            MailUpdaterTask::new(Some("work".to_owned()), Some("{account}".to_owned()));
+        assert_eq!(
+            Some("work:{account}".to_owned()),
+            expanded("{account}:{mailbox}", &braced_task, "work:{account}")
+        );
+        assert_eq!(
+            Some("--folder={account}".to_owned()),
+            expanded("--folder={mailbox}", &braced_task, "work:{account}")
+        );
+    }
+
+    #[test]
+    fn it_should_escape_mailboxes() {
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[]);
+        let target = |mailbox: &str| {
+            executor.target(&escaped(&MailUpdaterTask::new(
+                Some("work".to_owned()),
+                Some(mailbox.to_owned()),
+            )))
+        };
+        assert_eq!("work:Sent Messages", target("Sent Messages"));
+        assert_eq!("work:A:B", target("A:B"));
+        assert_eq!("work", target("Invoices 1,2"));
+    }
+
+    #[test]
+    fn it_should_translate_mailbox_names() {
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[])
+            .with_mailbox_name("gmail", "Junk", "[Gmail]/Spam")
+            .with_mailbox_name("gmail", "Sent", "Sent Messages");
+        let target = |account: &str, mailbox: &str| {
+            executor.target(&executor.translated(&MailUpdaterTask::new(
+                Some(account.to_owned()),
+                Some(mailbox.to_owned()),
+            )))
+        };
+        assert_eq!("gmail:[Gmail]/Spam", target("gmail", "Junk"));
+        assert_eq!("gmail:Sent Messages", target("gmail", "Sent"));
+        assert_eq!("gmail:INBOX", target("gmail", "INBOX"));
+        assert_eq!("work:Junk", target("work", "Junk"));
+    }
+
+    #[test]
+    fn it_should_classify_failures() {
+        let failed = ExitStatus::from_raw(1 << 8);
+        let errors = |line: &str| vec![line.to_owned()];
+        assert_eq!(
+            FailureKind::Network,
+            classify_failure(
+                failed,
+                &errors(
+                    "Error: cannot connect to imap.example.org (1.2.3.4:993): Connection refused"
+                )
+            )
+        );
+        assert_eq!(
+            FailureKind::Network,
+            classify_failure(
+                failed,
+                &errors("IMAP error: Cannot resolve server 'imap.example.org': Temporary failure in name resolution")
+            )
+        );
+        assert_eq!(
+            FailureKind::Network,
+            classify_failure(failed, &errors("Error: SSL handshake failed"))
+        );
+        assert_eq!(
+            FailureKind::Network,
+            classify_failure(failed, &errors("Socket error: timeout."))
+        );
+        assert_eq!(
+            FailureKind::Other,
+            classify_failure(
+                failed,
+                &errors("Error: IMAP store 'work-remote' has neither Tunnel nor Host")
+            )
+        );
+        assert_eq!(
+            FailureKind::Other,
+            classify_failure(
+                failed,
+                &errors("IMAP command 'AUTHENTICATE PLAIN' returned an error: NO [AUTHENTICATIONFAILED] Invalid credentials")
+            )
+        );
+        assert_eq!(
+            FailureKind::Config,
+            classify_failure(failed, &errors("No such channel 'work'."))
+        );
+        assert_eq!(
+            FailureKind::Config,
+            classify_failure(
+                failed,
+                &errors("/home/user/.config/isync/mbsyncrc:12: keyword 'Hots' is not recognized")
+            )
+        );
+        assert_eq!(
+            FailureKind::Network,
+            classify_failure(
+                failed,
+                &errors("Error: cannot connect to imap.example.com (configured in /home/user/.config/isync/mbsyncrc): Connection refused")
+            )
+        );
+        assert_eq!(
+            FailureKind::Other,
+            classify_failure(failed, &errors("Error: mailbox is locked"))
+        );
+        //killed by SIGTERM
+        assert_eq!(
+            FailureKind::Interrupted,
+            classify_failure(ExitStatus::from_raw(15), &[])
+        );
+    }
+
+    #[test]
+    fn it_should_run_the_command_of_the_account() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let script = |name: &str| {
+            vec![
+                "-c".to_owned(),
+                format!("echo {} $1 >> {}", name, out.display()),
+                "sh".to_owned(),
+            ]
+        };
+        let executor = MbSyncExecutor::new(&"sh".to_owned(), &script("default"))
+            .with_account_command("work", "sh", &script("work"));
+        executor
+            .execute(&MailUpdaterTask::new(Some("work".to_owned()), None))
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("private".to_owned()), None))
+            .unwrap();
+        executor.execute(&MailUpdaterTask::new(None, None)).unwrap();
+        assert_eq!(
+            "work work\ndefault private\ndefault --all\nwork work\n",
+            fs::read_to_string(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_run_the_other_account_commands_after_a_failure() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let script = |name: &str| {
+            vec![
+                "-c".to_owned(),
+                format!("echo {} >> {}; test {} != work", name, out.display(), name),
+                "sh".to_owned(),
+            ]
+        };
+        let executor = MbSyncExecutor::new(&"sh".to_owned(), &script("default"))
+            .with_account_command("work", "sh", &script("work"))
+            .with_account_command("private", "sh", &script("private"));
+        assert!(matches!(
+            executor.execute(&MailUpdaterTask::new(None, None)),
+            Err(ExecError::Failed(..))
+        ));
+        let mut synced = fs::read_to_string(out)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        synced.sort();
+        assert_eq!(vec!["default", "private", "work"], synced);
+    }
+
+    #[test]
+    fn it_should_map_accounts_to_channels() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let args = vec![
+            "-c".to_owned(),
+            format!("echo $1 >> {}", out.display()),
+            "sh".to_owned(),
+        ];
+        let executor =
+            MbSyncExecutor::new(&"sh".to_owned(), &args).with_channel("frank-gmail", "gmail");
+        executor
+            .execute(&MailUpdaterTask::new(
+                Some("frank-gmail".to_owned()),
+                Some("INBOX".to_owned()),
+            ))
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(Some("work".to_owned()), None))
+            .unwrap();
+        assert_eq!("gmail:INBOX\nwork\n", fs::read_to_string(out).unwrap());
+    }
+
+    #[test]
+    fn it_should_validate_tasks_with_the_mbsyncrc() {
+        let rc = MbSyncRc::parse("Channel work\nPatterns * !Trash\n");
+        let executor = MbSyncExecutor::new(&"false".to_owned(), &[]).with_mbsyncrc(rc);
+        let task = |account: &str, mailbox: &str| {
+            MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+        };
+        assert!(executor.execute(&task("work", "Trash")).is_ok());
+        assert!(matches!(
+            executor.execute(&task("work", "INBOX")),
+            Err(ExecError::Failed(_, _))
+        ));
+        assert!(matches!(
+            executor.execute(&task("private", "INBOX")),
+            Err(ExecError::Config(_))
+        ));
+        assert_eq!(
+            vec!["private".to_owned()],
+            executor.unknown_accounts(&["work".to_owned(), "private".to_owned()])
+        );
+    }
+
+    #[test]
+    fn it_should_sync_groups() {
+        let rc = MbSyncRc::parse(
+            "Channel gmail-inbox\nPatterns INBOX\n\nChannel gmail-lists\nPatterns Lists/*\n\nGroup gmail gmail-inbox gmail-lists\n",
+        );
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[])
+            .with_mbsyncrc(rc)
+            .with_group("frank-gmail", "gmail");
+        let task = |mailbox: Option<&str>| {
+            MailUpdaterTask::new(Some("frank-gmail".to_owned()), mailbox.map(str::to_owned))
+        };
+        assert_eq!("gmail", executor.target(&task(None)));
+        assert_eq!(
+            "gmail-lists:Lists/rust",
+            executor.target(&task(Some("Lists/rust")))
+        );
+        assert!(matches!(executor.validate(&task(Some("Trash"))), Ok(false)));
+        assert!(executor
+            .unknown_accounts(&["frank-gmail".to_owned()])
+            .is_empty());
+    }
+
+    #[test]
+    fn it_should_sync_the_channels_of_all_but_the_excluded_accounts() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let rc = MbSyncRc::parse(
+            "Channel gmail-inbox\n\nChannel gmail-lists\n\nChannel work\n\nChannel archive\n\nGroup gmail gmail-inbox gmail-lists\n",
+        );
+        let script = vec![
+            "-c".to_owned(),
+            format!("echo \"$@\" >> {}", out.display()),
+            "sh".to_owned(),
+        ];
+        let executor = MbSyncExecutor::new(&"sh".to_owned(), &script)
+            .with_mbsyncrc(rc)
+            .with_group("frank-gmail", "gmail")
+            .with_channel("frank-archive", "archive");
+        let all = MailUpdaterTask::new(None, None);
+        executor
+            .execute_except(&all, &["frank-archive".to_owned()])
+            .unwrap()
+            .unwrap();
+        executor
+            .execute_except(&all, &["frank-gmail".to_owned()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            "gmail-inbox gmail-lists work\narchive work\n",
+            fs::read_to_string(&out).unwrap()
+        );
+        //without the mbsyncrc the channels are unknown
+        assert!(MbSyncExecutor::new(&"sh".to_owned(), &script)
+            .execute_except(&all, &["frank-archive".to_owned()])
+            .is_none());
+    }
+
+    #[test]
+    fn it_should_lock_the_synced_channels() {
+        let rc = MbSyncRc::parse(
+            "Channel gmail-inbox\nPatterns INBOX\n\nChannel gmail-lists\nPatterns Lists/*\n\nChannel work\n\nGroup gmail gmail-inbox gmail-lists\n",
+        );
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[])
+            .with_mbsyncrc(rc)
+            .with_group("frank-gmail", "gmail");
+        let task = |account: Option<&str>, mailbox: Option<&str>| {
+            MailUpdaterTask::new(account.map(str::to_owned), mailbox.map(str::to_owned))
+        };
+        assert_eq!(
+            vec!["all", "gmail", "gmail-inbox", "gmail-lists", "work"],
+            executor.lock_names(&task(None, None))
+        );
+        assert_eq!(
+            vec!["gmail", "gmail-inbox", "gmail-lists"],
+            executor.lock_names(&task(Some("frank-gmail"), None))
+        );
+        assert_eq!(
+            vec!["gmail-lists"],
+            executor.lock_names(&task(Some("frank-gmail"), Some("Lists/rust")))
+        );
+        assert_eq!(
+            vec!["work"],
+            executor.lock_names(&task(Some("work"), Some("INBOX")))
+        );
+    }
+
+    #[test]
+    fn it_should_lock_the_channels_of_all_accounts_without_the_mbsyncrc() {
+        let accounts = Accounts::new(Vec::new()).unwrap();
+        accounts.add("work");
+        accounts.add("frank-gmail");
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[])
+            .with_channel("frank-gmail", "gmail")
+            .with_accounts(accounts.clone());
+        let all = MailUpdaterTask::new(None, None);
+        assert_eq!(vec!["all", "gmail", "work"], executor.lock_names(&all));
+        //accounts found later are locked as well
+        accounts.add("private");
+        assert_eq!(
+            vec!["all", "gmail", "private", "work"],
+            executor.lock_names(&all)
+        );
+    }
+
+    #[test]
+    fn it_should_add_the_args_of_the_source() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let script = dir.path().join("mbsync");
+        fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", out.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let executor = MbSyncExecutor::new(&script.display().to_string(), &[])
+            .with_source_args(
+                TaskSource::Timer,
+                &["--pull".to_owned(), "--new".to_owned()],
+            )
+            .with_account_command("private", &script.display().to_string(), &[]);
+        let task = |account: Option<&str>, source: TaskSource| {
+            MailUpdaterTask::new(account.map(str::to_owned), None).with_source(source)
+        };
+        for task in [
+            task(Some("work"), TaskSource::Timer),
+            task(Some("work"), TaskSource::FileWatch),
+            task(None, TaskSource::Timer),
+        ] {
+            executor.execute(&task).unwrap();
+        }
+        assert_eq!(
+            "--pull --new work\nwork\n--all\nprivate\n",
+            fs::read_to_string(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_pass_the_target_of_a_full_sync_to_templates() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let script = dir.path().join("mbsync");
+        fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", out.display()),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let rc = MbSyncRc::parse("Channel work\n\nChannel private\n\nChannel archive\n");
+        let args = [
+            "-c".to_owned(),
+            "{config}".to_owned(),
+            "{account}:{mailbox}".to_owned(),
+        ];
+        let executor = MbSyncExecutor::new(&script.display().to_string(), &args)
+            .with_variable(None, "config", "mbsyncrc")
+            .with_mbsyncrc(rc);
+        let all = MailUpdaterTask::new(None, None);
+        executor.execute(&all).unwrap();
+        executor
+            .execute_except(&all, &["archive".to_owned()])
+            .unwrap()
+            .unwrap();
+        executor
+            .execute(&MailUpdaterTask::new(
+                Some("work".to_owned()),
+                Some("INBOX".to_owned()),
+            ))
+            .unwrap();
+        assert_eq!(
+            "-c mbsyncrc --all\n-c mbsyncrc private work\n-c mbsyncrc work:INBOX\n",
+            fs::read_to_string(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_defer_syncs_of_locked_channels() {
+        let dir = TempDir::new().unwrap();
+        let executor = MbSyncExecutor::new(&"true".to_owned(), &[])
+            .with_channel("frank-gmail", "gmail")
+            .with_lock_dir(dir.path(), LockMode::Defer);
+        let task = MailUpdaterTask::new(Some("frank-gmail".to_owned()), None);
+        executor.execute(&task).unwrap();
+        //e.g. a manual run of mbsync
+        let lock = File::create(dir.path().join("gmail.lock")).unwrap();
+        lock.lock().unwrap();
+        assert!(matches!(
+            executor.execute(&task),
+            Err(ExecError::Deferred(_))
+        ));
+        drop(lock);
+        executor.execute(&task).unwrap();
+    }
+
+    #[test]
+    fn it_should_run_mbsync_in_a_systemd_scope() {
+        let executor = MbSyncExecutor::new(&"mbsync".to_owned(), &[])
+            .with_systemd_scope(&["MemoryMax=1G".to_owned()]);
+        let command = executor.command("mbsync", "work:Lists/rust");
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!("systemd-run", command.get_program());
+        assert!(args[3].starts_with("--unit=mailwatch-work_Lists_rust-"));
+        assert_eq!(["--property=MemoryMax=1G", "--", "mbsync"], args[5..]);
+    }
+
+    #[test]
+    fn it_should_kill_hanging_commands() {
+        let executor = MbSyncExecutor::new(
+            &"sh".to_owned(),
+            &["-c".to_owned(), "sleep 5".to_owned(), "sh".to_owned()],
+        )
+        .with_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let result = executor.execute(&MailUpdaterTask::new(Some("acc1".to_owned()), None));
+        assert!(matches!(result, Err(ExecError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
 }
@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+//a channel of the mbsync config with its stores and mailbox patterns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MbSyncChannel {
+    pub name: String,
+    pub far: Option<String>,
+    pub near: Option<String>,
+    pub patterns: Vec<String>,
+}
+
+//* matches everything, % everything except the hierarchy delimiter
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+        Some(('%', rest)) => (0..=name.len())
+            .take_while(|skip| *skip == 0 || name[skip - 1] != '/')
+            .any(|skip| matches(rest, &name[skip..])),
+        Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+    }
+}
+
+impl MbSyncChannel {
+    //like mbsync the last matching pattern decides, without patterns the mailboxes are unknown
+    pub fn includes(&self, mailbox: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let name = mailbox.chars().collect::<Vec<_>>();
+        self.patterns
+            .iter()
+            .fold(false, |included, pattern| match pattern.strip_prefix('!') {
+                Some(pattern) if matches(&pattern.chars().collect::<Vec<_>>(), &name) => false,
+                None if matches(&pattern.chars().collect::<Vec<_>>(), &name) => true,
+                _ => included,
+            })
+    }
+}
+
+//the channels and groups of a mbsync config file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MbSyncRc {
+    channels: HashMap<String, MbSyncChannel>,
+    groups: HashMap<String, Vec<String>>,
+}
+
+//the words of a line, double quotes keep spaces
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+enum Section {
+    Channel(MbSyncChannel),
+    Group(String, Vec<String>),
+    Other,
+}
+
+impl MbSyncRc {
+    pub fn read(path: &Path) -> Result<Self, io::Error> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    //sections start with their keyword and end with an empty line
+    pub fn parse(content: &str) -> Self {
+        let mut rc = Self::default();
+        let mut section = Section::Other;
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+            let words = words(line);
+            let Some((keyword, values)) = words.split_first() else {
+                rc.end(std::mem::replace(&mut section, Section::Other));
+                continue;
+            };
+            match (keyword.to_lowercase().as_str(), &mut section) {
+                ("channel" | "channels", Section::Group(_, members)) => {
+                    members.extend(values.iter().cloned())
+                }
+                ("channel", _) => {
+                    rc.end(std::mem::replace(&mut section, Section::Other));
+                    section = Section::Channel(MbSyncChannel {
+                        name: values.first().cloned().unwrap_or_default(),
+                        ..Default::default()
+                    });
+                }
+                ("group", _) => {
+                    rc.end(std::mem::replace(&mut section, Section::Other));
+                    section = Section::Group(
+                        values.first().cloned().unwrap_or_default(),
+                        values.iter().skip(1).cloned().collect(),
+                    );
+                }
+                //older versions name the sides master and slave
+                ("far" | "master", Section::Channel(channel)) => {
+                    channel.far = values.first().cloned()
+                }
+                ("near" | "slave", Section::Channel(channel)) => {
+                    channel.near = values.first().cloned()
+                }
+                ("patterns" | "pattern", Section::Channel(channel)) => {
+                    channel.patterns.extend(values.iter().cloned())
+                }
+                ("imapaccount" | "imapstore" | "maildirstore", _) => {
+                    rc.end(std::mem::replace(&mut section, Section::Other));
+                }
+                _ => {}
+            }
+        }
+        rc.end(section);
+        rc
+    }
+
+    fn end(&mut self, section: Section) {
+        match section {
+            Section::Channel(channel) => {
+                self.channels.insert(channel.name.clone(), channel);
+            }
+            //members may be limited to mailboxes with channel:mailbox
+            Section::Group(name, members) => {
+                let members = members
+                    .iter()
+                    .map(|member| member.split(':').next().unwrap_or_default().to_owned())
+                    .collect();
+                self.groups.insert(name, members);
+            }
+            Section::Other => {}
+        }
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&MbSyncChannel> {
+        self.channels.get(name)
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = &String> {
+        self.channels.keys()
+    }
+
+    pub fn group(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+
+    //a channel or a group
+    pub fn contains(&self, name: &str) -> bool {
+        self.channels.contains_key(name) || self.groups.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MbSyncChannel, MbSyncRc};
+
+    #[test]
+    fn it_should_parse_channels_and_groups() {
+        let rc = MbSyncRc::parse(
+            "IMAPStore work-remote
+Host imap.example.org
+
+# the work account
+Channel work
+Far :work-remote:
+Near :work-local:
+Patterns * !Trash \"!Old Mails/*\"
+Create Near
+
+Group both work
+Channels private:INBOX
+",
+        );
+        assert_eq!(
+            Some(&MbSyncChannel {
+                name: "work".to_owned(),
+                far: Some(":work-remote:".to_owned()),
+                near: Some(":work-local:".to_owned()),
+                patterns: vec![
+                    "*".to_owned(),
+                    "!Trash".to_owned(),
+                    "!Old Mails/*".to_owned()
+                ],
+            }),
+            rc.channel("work")
+        );
+        assert_eq!(
+            Some(&["work".to_owned(), "private".to_owned()][..]),
+            rc.group("both")
+        );
+        assert!(rc.contains("both"));
+        assert!(!rc.contains("private"));
+    }
+
+    #[test]
+    fn it_should_match_the_patterns() {
+        let channel = MbSyncChannel {
+            patterns: vec![
+                "%".to_owned(),
+                "!Trash".to_owned(),
+                "Lists/*".to_owned(),
+                "!Lists/Spam".to_owned(),
+            ],
+            ..Default::default()
+        };
+        assert!(channel.includes("INBOX"));
+        assert!(!channel.includes("Trash"));
+        assert!(channel.includes("Lists/rust/announce"));
+        assert!(!channel.includes("Lists/Spam"));
+        assert!(!channel.includes("Archive/2024"));
+        assert!(MbSyncChannel::default().includes("Archive/2024"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::process::Command;
+
+use crate::{
+    executor::{CommandRunner, ExecError, ExecReport, Executor, FailureKind, RunnerOptions},
+    updater::MailUpdaterTask,
+};
+
+pub struct OfflineImapExecutor {
+    command: String,
+    args: Vec<String>,
+    runner: CommandRunner,
+}
+
+impl OfflineImapExecutor {
+    pub fn new(command: &str, args: &[String]) -> Self {
+        Self {
+            command: command.to_owned(),
+            args: args.to_vec(),
+            runner: CommandRunner::default(),
+        }
+    }
+
+    //-a account -f folder, without an account all accounts of the config are synced
+    fn task_args(task: &MailUpdaterTask) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(account) = &task.specific_account {
+            args.extend(["-a".to_owned(), account.clone()]);
+            if let Some(mailbox) = &task.specific_mailbox {
+                args.extend(["-f".to_owned(), mailbox.clone()]);
+            }
+        }
+        args
+    }
+
+    fn execute_command(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        let task_args = Self::task_args(task);
+        log::info!(
+            "execute offlineimap with {:?} ({:?})",
+            task_args,
+            task.source
+        );
+        let label = match &task.specific_account {
+            Some(account) => account.clone(),
+            None => "all".to_owned(),
+        };
+        let mut command = Command::new(&self.command);
+        command.args(&self.args).args(task_args);
+        self.runner
+            .prepare(&mut command, task.specific_account.as_deref());
+        let output = self.runner.run(command, &label)?;
+        if !output.status.success() {
+            let kind = match output.status.code() {
+                None => FailureKind::Interrupted,
+                Some(_) => FailureKind::Other,
+            };
+            return Err(ExecError::Failed(output.status, kind));
+        }
+        Ok(ExecReport::with_status(output.status))
+    }
+}
+
+impl RunnerOptions for OfflineImapExecutor {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl Executor for OfflineImapExecutor {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.runner.suppressed(task, || self.execute_command(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OfflineImapExecutor;
+    use crate::updater::MailUpdaterTask;
+
+    #[test]
+    fn it_should_map_tasks_to_arguments() {
+        let task = |account: Option<&str>, mailbox: Option<&str>| {
+            OfflineImapExecutor::task_args(&MailUpdaterTask::new(
+                account.map(str::to_owned),
+                mailbox.map(str::to_owned),
+            ))
+        };
+        assert_eq!(
+            vec!["-a", "work", "-f", "INBOX"],
+            task(Some("work"), Some("INBOX"))
+        );
+        assert_eq!(vec!["-a", "work"], task(Some("work"), None));
+        assert!(task(None, None).is_empty());
+    }
+}
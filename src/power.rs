@@ -0,0 +1,123 @@
+use std::{fs, path::Path, process::Command};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowerState {
+    pub on_battery: bool,
+    //the lowest capacity of the batteries in percent
+    pub capacity: Option<u8>,
+    pub metered: bool,
+}
+
+impl PowerState {
+    pub fn read(check_metered: bool) -> Self {
+        let (on_battery, capacity) = Self::read_power_supplies(Path::new(POWER_SUPPLY_DIR));
+        Self {
+            on_battery,
+            capacity,
+            metered: check_metered && Self::read_metered(),
+        }
+    }
+
+    //syncs are limited on battery or a metered connection
+    pub fn is_constrained(&self) -> bool {
+        self.on_battery || self.metered
+    }
+
+    fn read_power_supplies(dir: &Path) -> (bool, Option<u8>) {
+        let mut external = false;
+        let mut discharging = false;
+        let mut capacity: Option<u8> = None;
+        let Ok(entries) = fs::read_dir(dir) else {
+            return (false, None);
+        };
+        for entry in entries.flatten() {
+            let read = |name: &str| {
+                fs::read_to_string(entry.path().join(name))
+                    .map(|value| value.trim().to_owned())
+                    .unwrap_or_default()
+            };
+            match read("type").as_str() {
+                "Battery" => {
+                    discharging |= read("status") == "Discharging";
+                    if let Ok(battery) = read("capacity").parse::<u8>() {
+                        capacity = Some(capacity.map_or(battery, |capacity| capacity.min(battery)));
+                    }
+                }
+                "Mains" | "USB" => external |= read("online") == "1",
+                _ => {}
+            }
+        }
+        (discharging && !external, capacity)
+    }
+
+    //the metered state of NetworkManager, yes or guessed yes
+    fn read_metered() -> bool {
+        let output = Command::new("busctl")
+            .args([
+                "get-property",
+                "org.freedesktop.NetworkManager",
+                "/org/freedesktop/NetworkManager",
+                "org.freedesktop.NetworkManager",
+                "Metered",
+            ])
+            .output();
+        match output {
+            Ok(output) if output.status.success() => matches!(
+                String::from_utf8_lossy(&output.stdout).trim(),
+                "u 1" | "u 3"
+            ),
+            Ok(output) => {
+                log::debug!("could not read metered state: {}", output.status);
+                false
+            }
+            Err(err) => {
+                log::debug!("could not read metered state: {}", err);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::PowerState;
+
+    #[test]
+    fn it_should_read_the_power_supplies() {
+        let dir = TempDir::new().unwrap();
+        let supply = |name: &str, values: &[(&str, &str)]| {
+            fs::create_dir(dir.path().join(name)).unwrap();
+            for (file, value) in values {
+                fs::write(dir.path().join(name).join(file), format!("{}\n", value)).unwrap();
+            }
+        };
+        supply(
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("capacity", "42"),
+            ],
+        );
+        supply("AC", &[("type", "Mains"), ("online", "0")]);
+        assert_eq!(
+            (true, Some(42)),
+            PowerState::read_power_supplies(dir.path())
+        );
+        fs::write(dir.path().join("AC/online"), "1\n").unwrap();
+        assert_eq!(
+            (false, Some(42)),
+            PowerState::read_power_supplies(dir.path())
+        );
+        assert_eq!(
+            (false, None),
+            PowerState::read_power_supplies(&dir.path().join("missing"))
+        );
+    }
+}
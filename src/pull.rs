@@ -0,0 +1,142 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use crate::{
+    executor::{
+        execute_accounts, CommandRunner, ExecError, ExecReport, Executor, FailureKind,
+        RunnerOptions,
+    },
+    updater::MailUpdaterTask,
+};
+
+//tools only fetching the mails of an account, so mailbox tasks sync the whole account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullTool {
+    Getmail,
+    Fetchmail,
+}
+
+impl PullTool {
+    //the default command of the tool
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Getmail => "getmail",
+            Self::Fetchmail => "fetchmail",
+        }
+    }
+
+    fn rc_args(&self, rc_file: &Path) -> Vec<String> {
+        let flag = match self {
+            Self::Getmail => "--rcfile",
+            Self::Fetchmail => "--fetchmailrc",
+        };
+        vec![flag.to_owned(), rc_file.to_string_lossy().into_owned()]
+    }
+
+    //fetchmail exits with 1 if there was no new mail
+    fn result(&self, status: ExitStatus) -> Result<(), FailureKind> {
+        match (self, status.code()) {
+            (_, Some(0)) | (Self::Fetchmail, Some(1)) => Ok(()),
+            (_, None) => Err(FailureKind::Interrupted),
+            (Self::Fetchmail, Some(2) | Some(11)) => Err(FailureKind::Network),
+            (Self::Fetchmail, Some(3) | Some(5) | Some(6)) => Err(FailureKind::Config),
+            (_, Some(_)) => Err(FailureKind::Other),
+        }
+    }
+}
+
+pub struct PullExecutor {
+    tool: PullTool,
+    command: String,
+    args: Vec<String>,
+    rc_files: HashMap<String, PathBuf>,
+    runner: CommandRunner,
+}
+
+impl PullExecutor {
+    pub fn new(tool: PullTool, args: &[String]) -> Self {
+        Self {
+            tool,
+            command: tool.command().to_owned(),
+            args: args.to_vec(),
+            rc_files: HashMap::new(),
+            runner: CommandRunner::default(),
+        }
+    }
+
+    pub fn with_command(mut self, command: &str) -> Self {
+        self.command = command.to_owned();
+        self
+    }
+
+    pub fn with_rc_file(mut self, account: &str, rc_file: &Path) -> Self {
+        self.rc_files
+            .insert(account.to_owned(), rc_file.to_path_buf());
+        self
+    }
+
+    fn pull_account(&self, account: &str) -> Result<ExecReport, ExecError> {
+        let rc_file = self
+            .rc_files
+            .get(account)
+            .ok_or_else(|| ExecError::Other(format!("no rc file for account {}", account)))?;
+        log::info!("execute {} for {}", self.command, account);
+        let mut command = Command::new(&self.command);
+        command.args(&self.args).args(self.tool.rc_args(rc_file));
+        self.runner.prepare(&mut command, Some(account));
+        let output = self.runner.run(command, account)?;
+        self.tool
+            .result(output.status)
+            .map_err(|kind| ExecError::Failed(output.status, kind))?;
+        Ok(ExecReport::with_status(output.status))
+    }
+
+    fn execute_command(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        match &task.specific_account {
+            Some(account) => self.pull_account(account),
+            None => {
+                let mut accounts = self.rc_files.keys().collect::<Vec<_>>();
+                accounts.sort_unstable();
+                execute_accounts(accounts, |account| self.pull_account(account))
+            }
+        }
+    }
+}
+
+impl RunnerOptions for PullExecutor {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl Executor for PullExecutor {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.runner.suppressed(task, || self.execute_command(task))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, path::Path, process::ExitStatus};
+
+    use super::PullTool;
+    use crate::executor::FailureKind;
+
+    #[test]
+    fn it_should_interpret_the_exit_codes() {
+        let exit = |code: i32| ExitStatus::from_raw(code << 8);
+        assert_eq!(Ok(()), PullTool::Fetchmail.result(exit(1)));
+        assert_eq!(
+            Err(FailureKind::Network),
+            PullTool::Fetchmail.result(exit(2))
+        );
+        assert_eq!(Err(FailureKind::Other), PullTool::Getmail.result(exit(1)));
+        assert_eq!(
+            vec!["--rcfile", "/home/me/.getmail/work"],
+            PullTool::Getmail.rc_args(Path::new("/home/me/.getmail/work"))
+        );
+    }
+}
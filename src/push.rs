@@ -0,0 +1,225 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::updater::{MailUpdaterTask, TaskSource};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BODY_SIZE: usize = 64 * 1024;
+const MAX_LINE_LEN: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+//the json send by the http driver of dovecot's push_notification plugin
+#[derive(Deserialize, Debug)]
+struct PushNotification {
+    user: String,
+    folder: Option<String>,
+    event: Option<String>,
+}
+
+enum PushResponse {
+    Accepted,
+    BadRequest,
+    //the callback rejected the task, e.g. of an unknown account
+    Forbidden,
+    MethodNotAllowed,
+}
+
+//listens for push notifications and converts them into tasks, returns the bound address. the
+//callback accepts the task, the values are sent by anyone reaching the address. every connection
+//is served by its own thread, so a slow client does not block the others.
+pub fn run_push_receiver<A, F>(address: A, callback: F) -> io::Result<SocketAddr>
+where
+    A: ToSocketAddrs,
+    F: Fn(MailUpdaterTask) -> bool + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    let callback = Arc::new(callback);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let callback = callback.clone();
+            let result = stream.map(|stream| {
+                thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, callback.as_ref()) {
+                        log::warn!("error while receiving push notification: {}", err);
+                    }
+                })
+            });
+            if let Err(err) = result {
+                log::warn!("error while receiving push notification: {}", err);
+            }
+        }
+    });
+    Ok(local_address)
+}
+
+fn handle_connection<F>(mut stream: TcpStream, callback: &F) -> io::Result<()>
+where
+    F: Fn(MailUpdaterTask) -> bool,
+{
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let response = match read_request(&stream)? {
+        Some(body) => match serde_json::from_slice::<PushNotification>(&body) {
+            //a leading dash would be an option of the sync command
+            Ok(notification)
+                if notification.user.is_empty() || notification.user.starts_with('-') =>
+            {
+                log::warn!("invalid user {:?} of push notification", notification.user);
+                PushResponse::BadRequest
+            }
+            Ok(notification) => {
+                log::info!(
+                    "push notification {:?} for {} {:?}",
+                    notification.event,
+                    notification.user,
+                    notification.folder
+                );
+                match callback(
+                    MailUpdaterTask::new(Some(notification.user), notification.folder)
+                        .with_source(TaskSource::Push),
+                ) {
+                    true => PushResponse::Accepted,
+                    false => PushResponse::Forbidden,
+                }
+            }
+            Err(err) => {
+                log::warn!("invalid push notification: {}", err);
+                PushResponse::BadRequest
+            }
+        },
+        None => PushResponse::MethodNotAllowed,
+    };
+    let status = match response {
+        PushResponse::Accepted => "204 No Content",
+        PushResponse::BadRequest => "400 Bad Request",
+        PushResponse::Forbidden => "403 Forbidden",
+        PushResponse::MethodNotAllowed => "405 Method Not Allowed",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
+
+//a line of at most MAX_LINE_LEN bytes
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let len = reader.take(MAX_LINE_LEN + 1).read_line(line)?;
+    if len as u64 > MAX_LINE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(len)
+}
+
+//the body of a post request, none for other methods
+fn read_request(stream: &TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    read_line(&mut reader, &mut request_line)?;
+    let mut content_length = 0;
+    for headers in 0.. {
+        if headers == MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many headers",
+            ));
+        }
+        let mut header = String::new();
+        if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "content-length"))?;
+            }
+        }
+    }
+    if !request_line.starts_with("POST ") {
+        return Ok(None);
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "push notification too large",
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{error::Error, sync::mpsc};
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    pub fn it_should_convert_push_notifications_to_tasks() -> Result<(), Box<dyn Error>> {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let address = run_push_receiver("127.0.0.1:0", move |task| {
+            tasks_tx.send(task).unwrap();
+            true
+        })?;
+        let body = r#"{"user":"frank","unseen":1,"folder":"INBOX","event":"messageNew"}"#;
+        let mut stream = TcpStream::connect(address)?;
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert_eq!(
+            MailUpdaterTask::new(Some("frank".to_owned()), Some("INBOX".to_owned()))
+                .with_source(TaskSource::Push),
+            tasks_rx.recv_timeout(Duration::from_secs(2))?
+        );
+        Ok(())
+    }
+
+    fn send(address: SocketAddr, request: &str) -> Result<String, Box<dyn Error>> {
+        let mut stream = TcpStream::connect(address)?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[rstest]
+    pub fn it_should_reject_invalid_push_notifications() -> Result<(), Box<dyn Error>> {
+        let address = run_push_receiver("127.0.0.1:0", |task| {
+            task.specific_account.as_deref() == Some("frank")
+        })?;
+        //a slow client does not block the others
+        let _slow = TcpStream::connect(address)?;
+        let post = |body: &str| {
+            format!(
+                "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        };
+        assert!(send(address, &post(r#"{"user":"-V"}"#))?.starts_with("HTTP/1.1 400"));
+        assert!(send(address, &post(r#"{"user":"tom"}"#))?.starts_with("HTTP/1.1 403"));
+        assert!(send(address, &post(r#"{"user":"frank"}"#))?.starts_with("HTTP/1.1 204"));
+        let long_header = format!("POST / HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(10000));
+        //the connection is closed without a response
+        assert!(send(address, &long_header).map_or(true, |response| response.is_empty()));
+        Ok(())
+    }
+}
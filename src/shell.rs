@@ -0,0 +1,162 @@
+use std::process::Command;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use crate::{
+    executor::{CommandRunner, ExecError, ExecReport, Executor, FailureKind, RunnerOptions},
+    updater::MailUpdaterTask,
+};
+
+//runs a shell command line with the placeholders {account} and {mailbox}
+pub struct ShellExecutor {
+    template: String,
+    account_template: Option<String>,
+    all_template: Option<String>,
+    runner: CommandRunner,
+}
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{(account|mailbox)\}").unwrap());
+
+//a single word for sh, even with spaces or quotes in the name
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+impl ShellExecutor {
+    //missing values are passed as empty words
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_owned(),
+            account_template: None,
+            all_template: None,
+            runner: CommandRunner::default(),
+        }
+    }
+
+    //the command line of tasks without a mailbox
+    pub fn with_account_template(mut self, template: &str) -> Self {
+        self.account_template = Some(template.to_owned());
+        self
+    }
+
+    //the command line of tasks of all accounts, e.g. my-sync.sh --all
+    pub fn with_all_template(mut self, template: &str) -> Self {
+        self.all_template = Some(template.to_owned());
+        self
+    }
+
+    fn command_line(&self, task: &MailUpdaterTask) -> String {
+        let template = match (&task.specific_account, &task.specific_mailbox) {
+            (None, _) => self.all_template.as_ref(),
+            (Some(_), None) => self.account_template.as_ref(),
+            (Some(_), Some(_)) => None,
+        }
+        .unwrap_or(&self.template);
+        //in a single pass, so placeholders in the values are not replaced again
+        PLACEHOLDER
+            .replace_all(template, |caps: &Captures| {
+                let value = match &caps[1] {
+                    "account" => &task.specific_account,
+                    _ => &task.specific_mailbox,
+                };
+                quote(value.as_deref().unwrap_or_default())
+            })
+            .into_owned()
+    }
+
+    fn execute_command(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        let command_line = self.command_line(task);
+        log::info!("execute {} ({:?})", command_line, task.source);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&command_line);
+        //the values are available to the scripts as well
+        command
+            .env(
+                "MAILWATCH_ACCOUNT",
+                task.specific_account.as_deref().unwrap_or_default(),
+            )
+            .env(
+                "MAILWATCH_MAILBOX",
+                task.specific_mailbox.as_deref().unwrap_or_default(),
+            );
+        let label = task.specific_account.as_deref().unwrap_or("all");
+        self.runner
+            .prepare(&mut command, task.specific_account.as_deref());
+        let output = self.runner.run(command, label)?;
+        if !output.status.success() {
+            let kind = match output.status.code() {
+                None => FailureKind::Interrupted,
+                Some(_) => FailureKind::Other,
+            };
+            return Err(ExecError::Failed(output.status, kind));
+        }
+        Ok(ExecReport::with_status(output.status))
+    }
+}
+
+impl RunnerOptions for ShellExecutor {
+    fn runner_mut(&mut self) -> &mut CommandRunner {
+        &mut self.runner
+    }
+}
+
+impl Executor for ShellExecutor {
+    fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+        self.runner.suppressed(task, || self.execute_command(task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::ShellExecutor;
+    use crate::{
+        executor::{Executor, RunnerOptions},
+        updater::MailUpdaterTask,
+    };
+
+    #[test]
+    fn it_should_expand_the_templates() {
+        let executor = ShellExecutor::new("my-sync.sh {account} {mailbox}")
+            .with_all_template("my-sync.sh --all");
+        let task = |account: Option<&str>, mailbox: Option<&str>| {
+            MailUpdaterTask::new(account.map(str::to_owned), mailbox.map(str::to_owned))
+        };
+        assert_eq!(
+            "my-sync.sh 'work' 'Tom'\\''s mails'",
+            executor.command_line(&task(Some("work"), Some("Tom's mails")))
+        );
+        assert_eq!(
+            "my-sync.sh 'work' ''",
+            executor.command_line(&task(Some("work"), None))
+        );
+        assert_eq!("my-sync.sh --all", executor.command_line(&task(None, None)));
+        assert_eq!(
+            "my-sync.sh '{mailbox}' '$(touch /tmp/x)'",
+            executor.command_line(&task(Some("{mailbox}"), Some("$(touch /tmp/x)")))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn it_should_set_the_environment_of_the_account() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let executor =
+            ShellExecutor::new(&format!("echo {{account}} $PASS_CMD >> {}", out.display()))
+                .with_env("work", "PASS_CMD", "pass show work");
+        for account in ["work", "private"] {
+            executor
+                .execute(&MailUpdaterTask::new(Some(account.to_owned()), None))
+                .unwrap();
+        }
+        assert_eq!(
+            "work pass show work\nprivate\n",
+            fs::read_to_string(out).unwrap()
+        );
+    }
+}
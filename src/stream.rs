@@ -0,0 +1,102 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use futures_core::Stream;
+
+use crate::watcher::FileWatcherEvent;
+
+#[derive(Default)]
+struct StreamState {
+    events: VecDeque<FileWatcherEvent>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+//the events of a FileWatcher as async stream
+pub struct FileWatcherStream {
+    state: Arc<Mutex<StreamState>>,
+    _watcher: Box<dyn Send>,
+}
+
+impl FileWatcherStream {
+    pub(crate) fn new(events: Receiver<FileWatcherEvent>, watcher: Box<dyn Send>) -> Self {
+        let state = Arc::new(Mutex::new(StreamState::default()));
+        let thread_state = state.clone();
+        //forward the blocking channel to the waker of the stream
+        thread::spawn(move || {
+            for event in events {
+                let mut state = thread_state.lock().unwrap();
+                state.events.push_back(event);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+            let mut state = thread_state.lock().unwrap();
+            state.closed = true;
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Self {
+            state,
+            _watcher: watcher,
+        }
+    }
+}
+
+impl Stream for FileWatcherStream {
+    type Item = FileWatcherEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(event) = state.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        pin::Pin,
+        task::{Context, Poll, Waker},
+        thread::sleep,
+        time::Duration,
+    };
+
+    use futures_core::Stream;
+    use tempfile::TempDir;
+
+    use crate::watcher::FileWatcher;
+
+    #[test]
+    fn it_should_stream_events() {
+        let path = TempDir::new().unwrap().into_path();
+        fs::create_dir_all(path.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails")).unwrap();
+        let mut stream = FileWatcher::new(&path).unwrap().into_stream();
+        let mut context = Context::from_waker(Waker::noop());
+        assert!(Pin::new(&mut stream).poll_next(&mut context).is_pending());
+        File::create_new(path.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml")).unwrap();
+        for _ in 0..20 {
+            if let Poll::Ready(event) = Pin::new(&mut stream).poll_next(&mut context) {
+                let event = event.unwrap();
+                assert_eq!("acc1", event.account);
+                assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+                return;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        panic!("no event streamed");
+    }
+}
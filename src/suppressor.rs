@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{updater::MailUpdaterTask, watcher::FileWatcherEvent};
+
+//a running sync (None) or a sync which is settling until the instant
+type Suppression = (MailUpdaterTask, Option<Instant>);
+
+//suppresses the watcher events which are caused by the writes of a running sync
+#[derive(Debug, Clone, Default)]
+pub struct SyncSuppressor {
+    suppressions: Arc<Mutex<Vec<Suppression>>>,
+    settle: Duration,
+}
+
+impl SyncSuppressor {
+    pub fn new(settle: Duration) -> Self {
+        Self {
+            suppressions: Arc::default(),
+            settle,
+        }
+    }
+
+    pub fn begin(&self, task: &MailUpdaterTask) {
+        self.suppressions.lock().unwrap().push((task.clone(), None));
+    }
+
+    pub fn end(&self, task: &MailUpdaterTask) {
+        let mut suppressions = self.suppressions.lock().unwrap();
+        if let Some(suppression) = suppressions
+            .iter_mut()
+            .find(|(running, until)| running == task && until.is_none())
+        {
+            suppression.1 = Some(Instant::now() + self.settle);
+        }
+    }
+
+    pub fn is_suppressed(&self, event: &FileWatcherEvent) -> bool {
+        let now = Instant::now();
+        let mut suppressions = self.suppressions.lock().unwrap();
+        suppressions.retain(|(_, until)| until.is_none_or(|until| until > now));
+        let event_task = MailUpdaterTask::new(Some(event.account.clone()), event.mailbox.clone());
+        suppressions
+            .iter()
+            .any(|(task, _)| task.covers(&event_task))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, thread::sleep, time::Duration};
+
+    use crate::{
+        updater::MailUpdaterTask,
+        watcher::{FileWatcherEvent, FileWatcherEventKind},
+    };
+
+    use super::SyncSuppressor;
+
+    fn event(account: &str, mailbox: Option<&str>) -> FileWatcherEvent {
+        FileWatcherEvent {
+            account: account.to_owned(),
+            mailbox: mailbox.map(|mailbox| mailbox.to_owned()),
+            kind: FileWatcherEventKind::Modify,
+            path: PathBuf::new(),
+            renamed_from: None,
+            process: None,
+        }
+    }
+
+    #[test]
+    fn it_should_suppress_events_of_running_syncs() {
+        let suppressor = SyncSuppressor::new(Duration::from_millis(100));
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), Some("INBOX".to_owned()));
+        assert!(!suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+        suppressor.begin(&task);
+        assert!(suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+        assert!(!suppressor.is_suppressed(&event("acc1", Some("Sent"))));
+        assert!(!suppressor.is_suppressed(&event("acc2", Some("INBOX"))));
+        suppressor.end(&task);
+        assert!(suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+        sleep(Duration::from_millis(150));
+        assert!(!suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+    }
+
+    #[test]
+    fn it_should_suppress_all_events_of_full_syncs() {
+        let suppressor = SyncSuppressor::new(Duration::ZERO);
+        let task = MailUpdaterTask::new(None, None);
+        suppressor.begin(&task);
+        assert!(suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+        assert!(suppressor.is_suppressed(&event("acc2", None)));
+        suppressor.end(&task);
+        assert!(!suppressor.is_suppressed(&event("acc1", Some("INBOX"))));
+    }
+}
@@ -1,44 +1,963 @@
 use std::{
-    thread::{self, sleep},
-    time::Duration,
+    collections::HashMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use chrono::Utc;
+use chrono::{DateTime, Local, NaiveTime};
+use glob::Pattern;
 
-use crate::updater::MailUpdaterTask;
+use crate::{
+    accounts::Accounts,
+    connectivity::ConnectivityCheck,
+    maintenance::MaintenanceJob,
+    power::PowerState,
+    updater::{MailUpdaterTask, TaskSource},
+};
+
+//for cron expressions without a next time
+const NEVER: Duration = Duration::from_secs(100 * 365 * 24 * 3600);
+//only for the log, so counting stops there
+const MAX_MISSED_RUNS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Interval(Duration),
+    //at the multiples of the interval in local time, e.g. on the hour
+    Aligned(Duration),
+    //in local time
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    //the common five fields, or the fields of the cron crate with seconds
+    pub fn cron(expression: &str) -> Result<Self, cron::error::Error> {
+        let expression = match expression.split_whitespace().count() {
+            5 => format!("0 {}", expression),
+            _ => expression.to_owned(),
+        };
+        Ok(Self::Cron(Box::new(cron::Schedule::from_str(&expression)?)))
+    }
+
+    fn next_after(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Self::Interval(interval) => now + *interval,
+            Self::Aligned(interval) => aligned_after(now, *interval),
+            Self::Cron(schedule) => schedule.after(&now).next().unwrap_or(now + NEVER),
+        }
+    }
+
+    //the runs passed since the due one, e.g. while suspended or blocked by the callback
+    fn missed_runs(&self, nextrun: DateTime<Local>, now: DateTime<Local>) -> usize {
+        let mut missed = 0;
+        let mut run = self.next_after(nextrun);
+        while run <= now && missed < MAX_MISSED_RUNS {
+            missed += 1;
+            run = self.next_after(run);
+        }
+        missed
+    }
+}
+
+fn aligned_after(now: DateTime<Local>, interval: Duration) -> DateTime<Local> {
+    let interval = interval.as_secs().max(1) as i64;
+    let offset = now.offset().local_minus_utc() as i64;
+    let local = now.timestamp() + offset;
+    let next = (local.div_euclid(interval) + 1) * interval - offset;
+    DateTime::from_timestamp(next, 0)
+        .map(|next| next.with_timezone(&Local))
+        .unwrap_or(now + NEVER)
+}
+
+//a daily window, it spans midnight if it ends before it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    //times like 23:00
+    pub fn parse(start: &str, end: &str) -> Result<Self, chrono::ParseError> {
+        Ok(Self {
+            start: NaiveTime::parse_from_str(start, "%H:%M")?,
+            end: NaiveTime::parse_from_str(end, "%H:%M")?,
+        })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+//what the timer syncs when it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StartupSync {
+    #[default]
+    All,
+    Inboxes,
+    Nothing,
+}
+
+//how the timer saves power on battery or metered connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerPolicy {
+    slowdown: u32,
+    skip_all: bool,
+    min_capacity: Option<u8>,
+    check_metered: bool,
+}
+
+impl PowerPolicy {
+    //the intervals are multiplied by the slowdown
+    pub fn new(slowdown: u32) -> Self {
+        Self {
+            slowdown: slowdown.max(1),
+            skip_all: false,
+            min_capacity: None,
+            check_metered: false,
+        }
+    }
+
+    pub fn with_skipped_all(mut self) -> Self {
+        self.skip_all = true;
+        self
+    }
+
+    //below the capacity in percent the timer stops on battery
+    pub fn with_min_capacity(mut self, capacity: u8) -> Self {
+        self.min_capacity = Some(capacity);
+        self
+    }
+
+    pub fn with_metered_check(mut self) -> Self {
+        self.check_metered = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MailboxGroup {
+    name: String,
+    schedule: Schedule,
+    mailboxes: Vec<Pattern>,
+    //slow mailboxes keep their schedule when all is synced
+    slow: bool,
+}
+
+impl MailboxGroup {
+    //patterns match the mailboxes seen so far, plain names are synced even if never seen
+    fn mailboxes(&self, accounts: &Accounts, account: &str) -> Vec<String> {
+        let known = accounts.mailboxes(account);
+        let mut mailboxes = Vec::new();
+        for pattern in &self.mailboxes {
+            if Pattern::escape(pattern.as_str()) == pattern.as_str() {
+                mailboxes.push(pattern.as_str().to_owned());
+            } else {
+                mailboxes.extend(
+                    known
+                        .iter()
+                        .filter(|mailbox| pattern.matches(mailbox))
+                        .cloned(),
+                );
+            }
+        }
+        mailboxes.sort();
+        mailboxes.dedup();
+        mailboxes.retain(|mailbox| !accounts.is_mailbox_ignored(account, mailbox));
+        mailboxes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TimerOptions {
+    all: Schedule,
+    groups: Vec<MailboxGroup>,
+    quiet_hours: Option<QuietHours>,
+    jitter: Duration,
+    adaptive_inboxes: Option<(Duration, Duration)>,
+    connectivity_check: Option<ConnectivityCheck>,
+    power_policy: Option<PowerPolicy>,
+    startup_sync: StartupSync,
+    startup_stagger: Option<Duration>,
+    maintenance: Vec<(Schedule, MaintenanceJob)>,
+}
 
-pub fn run_timer<F>(inboxes_secs: u64, all_secs: u64, accounts: Vec<String>, mut callback: F)
+impl TimerOptions {
+    //the inboxes form the first mailbox group
+    pub fn new(inboxes: Schedule, all: Schedule) -> Self {
+        Self {
+            all,
+            groups: vec![MailboxGroup {
+                name: "inboxes".to_owned(),
+                schedule: inboxes,
+                mailboxes: vec![Pattern::new("INBOX").unwrap()],
+                slow: false,
+            }],
+            quiet_hours: None,
+            jitter: Duration::ZERO,
+            adaptive_inboxes: None,
+            connectivity_check: None,
+            power_policy: None,
+            startup_sync: StartupSync::All,
+            startup_stagger: None,
+            maintenance: Vec::new(),
+        }
+    }
+
+    //every run is delayed randomly up to the jitter, so several instances do not sync at once
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    //no syncs are triggered while offline, all is synced when online again
+    pub fn with_connectivity_check(mut self, check: ConnectivityCheck) -> Self {
+        self.connectivity_check = Some(check);
+        self
+    }
+
+    pub fn with_startup_sync(mut self, startup_sync: StartupSync) -> Self {
+        self.startup_sync = startup_sync;
+        self
+    }
+
+    //the startup syncs the accounts one by one with the delay instead of all at once
+    pub fn with_startup_stagger(mut self, delay: Duration) -> Self {
+        self.startup_stagger = Some(delay);
+        self
+    }
+
+    //the job runs by the schedule independent of the syncs, quiet hours and connectivity
+    pub fn with_maintenance(mut self, schedule: Schedule, job: MaintenanceJob) -> Self {
+        self.maintenance.push((schedule, job));
+        self
+    }
+
+    pub fn with_power_policy(mut self, policy: PowerPolicy) -> Self {
+        self.power_policy = Some(policy);
+        self
+    }
+
+    //no syncs are triggered within the quiet hours
+    pub fn with_quiet_hours(mut self, quiet_hours: QuietHours) -> Self {
+        self.quiet_hours = Some(quiet_hours);
+        self
+    }
+
+    pub fn with_group(
+        self,
+        name: &str,
+        schedule: Schedule,
+        mailboxes: &[String],
+    ) -> Result<Self, glob::PatternError> {
+        self.add_group(name, schedule, mailboxes, false)
+    }
+
+    //e.g. archives synced once a day, independent of the all interval
+    pub fn with_slow_group(
+        self,
+        name: &str,
+        schedule: Schedule,
+        mailboxes: &[String],
+    ) -> Result<Self, glob::PatternError> {
+        self.add_group(name, schedule, mailboxes, true)
+    }
+
+    fn add_group(
+        mut self,
+        name: &str,
+        schedule: Schedule,
+        mailboxes: &[String],
+        slow: bool,
+    ) -> Result<Self, glob::PatternError> {
+        self.groups.push(MailboxGroup {
+            name: name.to_owned(),
+            schedule,
+            mailboxes: mailboxes
+                .iter()
+                .map(|mailbox| Pattern::new(mailbox))
+                .collect::<Result<_, _>>()?,
+            slow,
+        });
+        Ok(self)
+    }
+
+    //the inbox of an account is synced more often the more recent its last activity was
+    pub fn with_adaptive_inboxes(mut self, min: Duration, max: Duration) -> Self {
+        self.adaptive_inboxes = Some((min, max.max(min)));
+        self
+    }
+
+    fn random_jitter(&self) -> Duration {
+        Duration::from_millis(fastrand::u64(0..=self.jitter.as_millis() as u64))
+    }
+
+    //the slowdown stretches the intervals, cron expressions keep their times
+    fn next_run(
+        &self,
+        schedule: &Schedule,
+        now: DateTime<Local>,
+        slowdown: u32,
+    ) -> DateTime<Local> {
+        let nextrun = match schedule {
+            Schedule::Interval(interval) => now + *interval * slowdown,
+            Schedule::Aligned(interval) => aligned_after(now, *interval * slowdown),
+            Schedule::Cron(_) => schedule.next_after(now),
+        };
+        nextrun + self.random_jitter()
+    }
+
+    //accounts without known activity are synced by the max interval
+    fn inbox_interval(&self, idle: Option<Duration>) -> Option<Duration> {
+        let (min, max) = self.adaptive_inboxes?;
+        Some(idle.map_or(max, |idle| idle.clamp(min, max)))
+    }
+}
+
+//the longest sleep, to notice clock jumps in time
+const TIMER_TICK: Duration = Duration::from_secs(60);
+const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(30);
+
+//the difference between the elapsed wall clock and monotonic time, e.g. by a suspend
+fn clock_jump(wall: chrono::Duration, monotonic: Duration) -> Option<chrono::Duration> {
+    let jump = wall - chrono::Duration::from_std(monotonic).unwrap_or(chrono::Duration::MAX);
+    (jump.abs().to_std().unwrap_or_default() > CLOCK_JUMP_THRESHOLD).then_some(jump)
+}
+
+//changes of a running timer
+enum TimerControl {
+    Restart(Box<TimerOptions>, Accounts),
+    //wakes up the timer to notice the stop
+    Stop,
+}
+
+pub struct TimerHandle {
+    control: Sender<TimerControl>,
+    stopped: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl TimerHandle {
+    //no further syncs are triggered, a running callback is finished
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.control.send(TimerControl::Stop);
+    }
+
+    pub fn join(self) -> thread::Result<()> {
+        self.thread.join()
+    }
+
+    //the timer continues with the new options and accounts, without another startup sync
+    pub fn restart(&self, options: TimerOptions, accounts: Accounts) {
+        if self
+            .control
+            .send(TimerControl::Restart(Box::new(options), accounts))
+            .is_err()
+        {
+            log::warn!("could not restart the stopped timer");
+        }
+    }
+}
+
+struct Timer<F> {
+    options: TimerOptions,
+    accounts: Accounts,
+    callback: F,
+    control: Receiver<TimerControl>,
+    stopped: Arc<AtomicBool>,
+    nextrun_all: DateTime<Local>,
+    nextrun_groups: Vec<DateTime<Local>>,
+    //with adaptive inboxes every account has its own next run
+    nextrun_inboxes: HashMap<String, DateTime<Local>>,
+    nextrun_maintenance: Vec<DateTime<Local>>,
+    //a job is not started again while its last run is still running
+    maintenance_runs: Vec<Option<JoinHandle<()>>>,
+    offline: bool,
+    //by the power policy
+    constrained: bool,
+    slowdown: u32,
+    skip_all: bool,
+    low_battery: bool,
+}
+
+impl<F> Timer<F>
 where
-    F: FnMut(MailUpdaterTask) + Send + 'static,
+    F: FnMut(MailUpdaterTask),
 {
-    thread::spawn(move || {
-        //trigger a all at beginning
-        callback(MailUpdaterTask::new(None, None));
-        let now = Utc::now();
-        let mut nextrun_all = now + Duration::from_secs(all_secs);
-        let mut nextrun_inboxes = now + Duration::from_secs(inboxes_secs);
-
-        loop {
-            let now = Utc::now();
-            let wait_duration = (nextrun_all - now).min(nextrun_inboxes - now);
-            sleep(wait_duration.to_std().unwrap());
-            let now = Utc::now();
-            if now > nextrun_all {
-                log::info!("timer refresh all");
-                callback(MailUpdaterTask::new(None, None));
-                nextrun_all = now + Duration::from_secs(all_secs);
-                nextrun_inboxes = now + Duration::from_secs(inboxes_secs);
-            }
-            if now > nextrun_inboxes {
-                for account in &accounts {
-                    log::info!("timer refresh INBOX {}", account);
-                    callback(MailUpdaterTask::new(
-                        Some(account.to_owned()),
-                        Some("INBOX".to_owned()),
-                    ))
+    fn new(
+        options: TimerOptions,
+        accounts: Accounts,
+        callback: F,
+        control: Receiver<TimerControl>,
+        stopped: Arc<AtomicBool>,
+    ) -> Self {
+        let now = Local::now();
+        let mut timer = Self {
+            options,
+            accounts,
+            callback,
+            control,
+            stopped,
+            nextrun_all: now,
+            nextrun_groups: Vec::new(),
+            nextrun_inboxes: HashMap::new(),
+            nextrun_maintenance: Vec::new(),
+            maintenance_runs: Vec::new(),
+            offline: false,
+            constrained: false,
+            slowdown: 1,
+            skip_all: false,
+            low_battery: false,
+        };
+        timer.reschedule(now);
+        timer
+    }
+
+    fn reschedule(&mut self, now: DateTime<Local>) {
+        self.nextrun_all = self.options.next_run(&self.options.all, now, self.slowdown);
+        //slow groups keep their next run once scheduled
+        let previous = std::mem::take(&mut self.nextrun_groups);
+        self.nextrun_groups = self
+            .options
+            .groups
+            .iter()
+            .enumerate()
+            .map(|(index, group)| match previous.get(index) {
+                Some(nextrun) if group.slow => *nextrun,
+                _ => self.options.next_run(&group.schedule, now, self.slowdown),
+            })
+            .collect();
+        for account in self.accounts.list() {
+            if let Some(interval) = self.inbox_interval(&account) {
+                let nextrun = now + interval * self.slowdown + self.options.random_jitter();
+                self.nextrun_inboxes.insert(account, nextrun);
+            }
+        }
+        self.schedule_adaptive_inboxes();
+        if self.nextrun_maintenance.len() != self.options.maintenance.len() {
+            self.nextrun_maintenance = self
+                .options
+                .maintenance
+                .iter()
+                .map(|(schedule, _)| schedule.next_after(now))
+                .collect();
+            self.maintenance_runs = self.options.maintenance.iter().map(|_| None).collect();
+        }
+    }
+
+    fn inbox_interval(&self, account: &str) -> Option<Duration> {
+        let idle = self
+            .accounts
+            .last_activity(account)
+            .map(|activity| activity.elapsed());
+        self.options.inbox_interval(idle)
+    }
+
+    //the inbox group runs for the account due first, new accounts are found by its schedule
+    fn schedule_adaptive_inboxes(&mut self) {
+        if let Some(nextrun) = self.nextrun_inboxes.values().min() {
+            self.nextrun_groups[0] = self.nextrun_groups[0].min(*nextrun);
+        }
+    }
+
+    fn inboxes_due(&mut self, now: DateTime<Local>) {
+        self.nextrun_groups[0] = now;
+        for nextrun in self.nextrun_inboxes.values_mut() {
+            *nextrun = now;
+        }
+    }
+
+    fn nextrun(&self) -> DateTime<Local> {
+        self.nextrun_groups
+            .iter()
+            .chain(&self.nextrun_maintenance)
+            .fold(self.nextrun_all, |nextrun, group| nextrun.min(*group))
+    }
+
+    fn run_startup(&mut self) {
+        let mailbox = match self.options.startup_sync {
+            StartupSync::Nothing => return,
+            //trigger a all at beginning
+            StartupSync::All if self.options.startup_stagger.is_none() => {
+                (self.callback)(MailUpdaterTask::new(None, None).with_source(TaskSource::Startup));
+                return;
+            }
+            StartupSync::All => None,
+            StartupSync::Inboxes => Some("INBOX".to_owned()),
+        };
+        self.refresh_accounts();
+        for (index, account) in self.accounts.list().into_iter().enumerate() {
+            if index > 0 {
+                self.pause(self.options.startup_stagger.unwrap_or_default());
+            }
+            if self.is_stopped() {
+                return;
+            }
+            log::info!("startup refresh {}", account);
+            (self.callback)(
+                MailUpdaterTask::new(Some(account), mailbox.clone())
+                    .with_source(TaskSource::Startup),
+            );
+        }
+    }
+
+    fn run(&mut self) {
+        self.pause(self.options.random_jitter());
+        if !self.is_stopped() {
+            self.run_startup();
+        }
+        self.reschedule(Local::now());
+        while !self.is_stopped() {
+            let (wall_before, monotonic_before) = (Local::now(), Instant::now());
+            let wait_duration = (self.nextrun() - wall_before).to_std().unwrap_or_default();
+            if self.pause(wait_duration.min(TIMER_TICK)) {
+                continue;
+            }
+            let now = Local::now();
+            if let Some(jump) = clock_jump(now - wall_before, monotonic_before.elapsed()) {
+                log::warn!("clock jumped by {}s, e.g. by a resume", jump.num_seconds());
+                //a jump back would delay the runs, so they are recomputed. the missed changes
+                //are caught up by the inboxes and the due groups, or all if that was due.
+                let all_due = now >= self.nextrun_all;
+                let groups_due: Vec<bool> = self
+                    .nextrun_groups
+                    .iter()
+                    .map(|nextrun| now >= *nextrun)
+                    .collect();
+                self.reschedule(now);
+                match all_due {
+                    true => self.nextrun_all = now,
+                    false => {
+                        self.inboxes_due(now);
+                        for (nextrun, due) in self.nextrun_groups.iter_mut().zip(groups_due) {
+                            if due {
+                                *nextrun = now;
+                            }
+                        }
+                    }
                 }
-                nextrun_inboxes = now + Duration::from_secs(inboxes_secs);
             }
+            self.update_power_state();
+            self.run_due(now);
+        }
+    }
+
+    //the missed runs are caught up by a single one
+    fn log_missed_runs(
+        &self,
+        name: &str,
+        schedule: &Schedule,
+        nextrun: DateTime<Local>,
+        now: DateTime<Local>,
+    ) {
+        let missed = schedule.missed_runs(nextrun, now);
+        if missed > 0 {
+            log::info!("missed {} runs of {}, catch up once", missed, name);
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    //true if interrupted by a restart or stop
+    fn pause(&mut self, duration: Duration) -> bool {
+        match self.control.recv_timeout(duration) {
+            Ok(TimerControl::Restart(options, accounts)) => {
+                log::info!("restart timer");
+                self.options = *options;
+                self.accounts = accounts;
+                self.nextrun_inboxes.clear();
+                self.nextrun_groups.clear();
+                self.nextrun_maintenance.clear();
+                self.reschedule(Local::now());
+                true
+            }
+            Ok(TimerControl::Stop) => {
+                log::info!("stop timer");
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            //without a handle the timer just keeps running
+            Err(RecvTimeoutError::Disconnected) => {
+                sleep(duration);
+                false
+            }
+        }
+    }
+
+    //accounts removed on disk are no longer scheduled
+    fn refresh_accounts(&mut self) {
+        if let Err(err) = self.accounts.refresh() {
+            log::warn!("error while refreshing accounts: {}", err);
+        }
+        let accounts = self.accounts.list();
+        self.nextrun_inboxes
+            .retain(|account, _| accounts.contains(account));
+    }
+
+    //probes only if something is due or to detect the end of an outage
+    fn check_connectivity(&mut self, now: DateTime<Local>) {
+        let Some(check) = &self.options.connectivity_check else {
+            return;
+        };
+        if !self.offline && now < self.nextrun() {
+            return;
+        }
+        let online = check.is_online();
+        if self.offline && online {
+            log::info!("online again, catch up with a sync of all");
+            self.nextrun_all = now;
+        } else if !self.offline && !online {
+            log::warn!("offline, skip the timer");
+        }
+        self.offline = !online;
+    }
+
+    fn update_power_state(&mut self) {
+        let Some(policy) = self.options.power_policy else {
+            return;
+        };
+        let state = PowerState::read(policy.check_metered);
+        let constrained = state.is_constrained();
+        if constrained != self.constrained {
+            log::info!("power state {:?}, adapt the timer", state);
+            self.constrained = constrained;
+        }
+        self.slowdown = if constrained { policy.slowdown } else { 1 };
+        self.skip_all = constrained && policy.skip_all;
+        self.low_battery = state.on_battery
+            && policy
+                .min_capacity
+                .zip(state.capacity)
+                .is_some_and(|(min_capacity, capacity)| capacity < min_capacity);
+    }
+
+    fn run_maintenance(&mut self, now: DateTime<Local>) {
+        for (index, (schedule, job)) in self.options.maintenance.iter().enumerate() {
+            if now < self.nextrun_maintenance[index] {
+                continue;
+            }
+            self.nextrun_maintenance[index] = schedule.next_after(now);
+            if self.maintenance_runs[index]
+                .as_ref()
+                .is_some_and(|run| !run.is_finished())
+            {
+                log::warn!("maintenance {} is still running, skip it", job.name());
+                continue;
+            }
+            let job = job.clone();
+            self.maintenance_runs[index] = Some(thread::spawn(move || {
+                if let Err(err) = job.execute() {
+                    log::error!("maintenance {} failed: {}", job.name(), err);
+                }
+            }));
         }
+    }
+
+    fn run_due(&mut self, now: DateTime<Local>) {
+        self.run_maintenance(now);
+        let quiet = self
+            .options
+            .quiet_hours
+            .is_some_and(|quiet_hours| quiet_hours.contains(now.time()));
+        if !quiet {
+            self.check_connectivity(now);
+        }
+        //the due syncs are skipped, but rescheduled
+        if quiet || self.offline || self.low_battery {
+            log::debug!("quiet hours, offline or low battery, skip the timer");
+            if now >= self.nextrun_all {
+                self.nextrun_all = self.options.next_run(&self.options.all, now, self.slowdown);
+            }
+            for (group, nextrun) in self.options.groups.iter().zip(&mut self.nextrun_groups) {
+                if now >= *nextrun {
+                    *nextrun = self.options.next_run(&group.schedule, now, self.slowdown);
+                }
+            }
+            return;
+        }
+        if now >= self.nextrun_all && self.skip_all {
+            log::info!("on battery or metered connection, skip the refresh of all");
+            self.nextrun_all = self.options.next_run(&self.options.all, now, self.slowdown);
+        }
+        if now >= self.nextrun_all {
+            self.log_missed_runs("all", &self.options.all, self.nextrun_all, now);
+            log::info!("timer refresh all");
+            (self.callback)(MailUpdaterTask::new(None, None).with_source(TaskSource::Timer));
+            //the groups are synced by it as well
+            self.reschedule(now);
+        }
+        if self.nextrun_groups.iter().any(|nextrun| now >= *nextrun) {
+            self.refresh_accounts();
+        }
+        for index in 0..self.nextrun_groups.len() {
+            if now < self.nextrun_groups[index] {
+                continue;
+            }
+            let group = &self.options.groups[index];
+            self.log_missed_runs(
+                &group.name,
+                &group.schedule,
+                self.nextrun_groups[index],
+                now,
+            );
+            for account in self.accounts.list() {
+                if index == 0 {
+                    if let Some(interval) = self.inbox_interval(&account) {
+                        let nextrun = self.nextrun_inboxes.entry(account.clone()).or_insert(now);
+                        if now < *nextrun {
+                            continue;
+                        }
+                        *nextrun = now + interval * self.slowdown + self.options.random_jitter();
+                    }
+                }
+                let group = &self.options.groups[index];
+                for mailbox in group.mailboxes(&self.accounts, &account) {
+                    log::info!("timer refresh {} {} of {}", group.name, mailbox, account);
+                    (self.callback)(
+                        MailUpdaterTask::new(Some(account.clone()), Some(mailbox))
+                            .with_source(TaskSource::Timer),
+                    )
+                }
+            }
+            let group = &self.options.groups[index];
+            self.nextrun_groups[index] = self.options.next_run(&group.schedule, now, self.slowdown);
+            if index == 0 {
+                self.schedule_adaptive_inboxes();
+            }
+        }
+    }
+}
+
+pub fn run_timer<F>(options: TimerOptions, accounts: Accounts, callback: F) -> TimerHandle
+where
+    F: FnMut(MailUpdaterTask) + Send + 'static,
+{
+    let (control_tx, control_rx) = mpsc::channel();
+    let stopped = Arc::new(AtomicBool::new(false));
+    let timer_stopped = stopped.clone();
+    let thread = thread::spawn(move || {
+        Timer::new(options, accounts, callback, control_rx, timer_stopped).run()
     });
+    TimerHandle {
+        control: control_tx,
+        stopped,
+        thread,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{TimeZone, Timelike};
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn it_should_schedule_by_cron_expressions() {
+        let schedule = Schedule::cron("*/5 8-22 * * MON-FRI").unwrap();
+        //a saturday
+        let now = Local.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+        let next = schedule.next_after(now);
+        assert_eq!(Local.with_ymd_and_hms(2025, 3, 3, 8, 0, 0).unwrap(), next);
+        let next = schedule.next_after(next);
+        assert_eq!(5, next.minute());
+        assert!(Schedule::cron("every hour").is_err());
+    }
+
+    #[test]
+    fn it_should_align_schedules_to_the_wall_clock() {
+        let schedule = Schedule::Aligned(Duration::from_secs(3600));
+        let now = Local.with_ymd_and_hms(2025, 3, 1, 12, 34, 56).unwrap();
+        let next = schedule.next_after(now);
+        assert_eq!(Local.with_ymd_and_hms(2025, 3, 1, 13, 0, 0).unwrap(), next);
+        assert_eq!(
+            Local.with_ymd_and_hms(2025, 3, 1, 14, 0, 0).unwrap(),
+            schedule.next_after(next)
+        );
+        let schedule = Schedule::Aligned(Duration::from_secs(15 * 60));
+        assert_eq!(
+            Local.with_ymd_and_hms(2025, 3, 1, 12, 45, 0).unwrap(),
+            schedule.next_after(now)
+        );
+    }
+
+    #[test]
+    fn it_should_count_missed_runs() {
+        let schedule = Schedule::Interval(Duration::from_secs(600));
+        let nextrun = Local.with_ymd_and_hms(2025, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(0, schedule.missed_runs(nextrun, nextrun));
+        assert_eq!(
+            0,
+            schedule.missed_runs(nextrun, nextrun + chrono::Duration::minutes(9))
+        );
+        assert_eq!(
+            3,
+            schedule.missed_runs(nextrun, nextrun + chrono::Duration::minutes(30))
+        );
+        let schedule = Schedule::cron("0 * * * *").unwrap();
+        assert_eq!(
+            2,
+            schedule.missed_runs(nextrun, nextrun + chrono::Duration::minutes(150))
+        );
+    }
+
+    #[test]
+    fn it_should_delay_runs_by_the_jitter() {
+        let interval = Schedule::Interval(Duration::from_secs(60));
+        let options = TimerOptions::new(interval.clone(), interval.clone())
+            .with_jitter(Duration::from_secs(10));
+        let now = Local::now();
+        for _ in 0..100 {
+            let delay = options.next_run(&interval, now, 1) - now;
+            assert!(delay >= chrono::Duration::seconds(60));
+            assert!(delay <= chrono::Duration::seconds(70));
+        }
+    }
+
+    #[test]
+    fn it_should_detect_clock_jumps() {
+        let minutes = |minutes| chrono::Duration::minutes(minutes);
+        assert_eq!(None, clock_jump(minutes(1), Duration::from_secs(60)));
+        assert_eq!(
+            Some(minutes(59)),
+            clock_jump(minutes(60), Duration::from_secs(60))
+        );
+        assert_eq!(
+            Some(minutes(-60)),
+            clock_jump(minutes(-59), Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn it_should_adapt_the_inbox_interval_to_the_activity() {
+        let interval = Schedule::Interval(Duration::from_secs(300));
+        let options = TimerOptions::new(interval.clone(), interval)
+            .with_adaptive_inboxes(Duration::from_secs(60), Duration::from_secs(1800));
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        assert_eq!(Some(minutes(1)), options.inbox_interval(Some(minutes(0))));
+        assert_eq!(Some(minutes(10)), options.inbox_interval(Some(minutes(10))));
+        assert_eq!(
+            Some(minutes(30)),
+            options.inbox_interval(Some(minutes(600)))
+        );
+        assert_eq!(Some(minutes(30)), options.inbox_interval(None));
+    }
+
+    #[test]
+    fn it_should_detect_quiet_hours() {
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        let night = QuietHours::parse("23:00", "07:00").unwrap();
+        assert!(night.contains(time(23, 30)));
+        assert!(night.contains(time(6, 59)));
+        assert!(!night.contains(time(7, 0)));
+        assert!(!night.contains(time(12, 0)));
+        let lunch = QuietHours::parse("12:00", "13:30").unwrap();
+        assert!(lunch.contains(time(12, 0)));
+        assert!(!lunch.contains(time(13, 30)));
+        assert!(QuietHours::parse("25:00", "07:00").is_err());
+    }
+
+    #[test]
+    fn it_should_match_the_mailboxes_of_groups() {
+        let accounts = Accounts::new(Vec::new()).unwrap();
+        accounts.add_mailbox("acc1", "Archive/2024");
+        accounts.add_mailbox("acc1", "Sent");
+        let options = TimerOptions::new(
+            Schedule::Interval(Duration::from_secs(60)),
+            Schedule::Interval(Duration::from_secs(600)),
+        )
+        .with_group(
+            "slow",
+            Schedule::Interval(Duration::from_secs(3600)),
+            &["Alerts".to_owned(), "Archive/*".to_owned()],
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["INBOX"],
+            options.groups[0].mailboxes(&accounts, "acc1")
+        );
+        assert_eq!(
+            vec!["Alerts", "Archive/2024"],
+            options.groups[1].mailboxes(&accounts, "acc1")
+        );
+        assert_eq!(
+            vec!["Alerts"],
+            options.groups[1].mailboxes(&accounts, "acc2")
+        );
+    }
+
+    #[test]
+    fn it_should_restart_with_new_options() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("acc1")).unwrap();
+        let accounts = Accounts::new(vec![root.path().to_path_buf()]).unwrap();
+        let hour = Schedule::Interval(Duration::from_secs(3600));
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let handle = run_timer(
+            TimerOptions::new(hour.clone(), hour.clone()).with_startup_sync(StartupSync::Nothing),
+            accounts.clone(),
+            move |task| {
+                let _ = tasks_tx.send(task);
+            },
+        );
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(500)).is_err());
+        handle.restart(
+            TimerOptions::new(Schedule::Interval(Duration::from_secs(1)), hour)
+                .with_startup_sync(StartupSync::Nothing),
+            accounts,
+        );
+        let task = tasks_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(Some("acc1"), task.specific_account.as_deref());
+        assert_eq!(Some("INBOX"), task.specific_mailbox.as_deref());
+        handle.stop();
+        handle.join().unwrap();
+        assert!(tasks_rx.recv().is_err());
+    }
+
+    #[test]
+    fn it_should_forget_removed_accounts() {
+        let root = TempDir::new().unwrap();
+        fs::create_dir(root.path().join("acc1")).unwrap();
+        fs::create_dir(root.path().join("acc2")).unwrap();
+        let accounts = Accounts::new(vec![root.path().to_path_buf()]).unwrap();
+        let interval = Schedule::Interval(Duration::from_secs(300));
+        let options = TimerOptions::new(interval.clone(), interval)
+            .with_adaptive_inboxes(Duration::from_secs(60), Duration::from_secs(1800));
+        let mut timer = Timer::new(options, accounts, |_| {}, mpsc::channel().1, Arc::default());
+        assert_eq!(2, timer.nextrun_inboxes.len());
+        fs::remove_dir(root.path().join("acc2")).unwrap();
+        timer.refresh_accounts();
+        assert!(timer.nextrun_inboxes.contains_key("acc1"));
+        assert!(!timer.nextrun_inboxes.contains_key("acc2"));
+    }
+
+    #[test]
+    fn it_should_keep_the_schedule_of_slow_groups() {
+        let accounts = Accounts::new(Vec::new()).unwrap();
+        let day = Schedule::Interval(Duration::from_secs(24 * 3600));
+        let options = TimerOptions::new(day.clone(), day.clone())
+            .with_slow_group("archive", day.clone(), &["Archive".to_owned()])
+            .unwrap();
+        let mut timer = Timer::new(options, accounts, |_| {}, mpsc::channel().1, Arc::default());
+        let nextrun = timer.nextrun_groups.clone();
+        timer.reschedule(Local::now() + chrono::Duration::hours(1));
+        assert!(timer.nextrun_groups[0] > nextrun[0]);
+        assert_eq!(nextrun[1], timer.nextrun_groups[1]);
+    }
 }
@@ -1,13 +1,37 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, MutexGuard, RwLock,
+    },
     thread::{self},
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+use crate::executor::{ExecError, ExecReport, Executor, FailureKind};
+
+//what triggered a task, tasks merged in the queue keep the source of the queued one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum TaskSource {
+    Timer,
+    FileWatch,
+    LogWatch,
+    Push,
+    #[default]
+    Manual,
+    Startup,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MailUpdaterTask {
     pub specific_account: Option<String>,
     pub specific_mailbox: Option<String>,
+    #[serde(default)]
+    pub source: TaskSource,
 }
 
 impl MailUpdaterTask {
@@ -15,6 +39,20 @@ impl MailUpdaterTask {
         Self {
             specific_account,
             specific_mailbox,
+            source: TaskSource::default(),
+        }
+    }
+
+    pub fn with_source(mut self, source: TaskSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    //a task of all accounts shares the account with every task
+    pub fn same_account(&self, other: &MailUpdaterTask) -> bool {
+        match (&self.specific_account, &other.specific_account) {
+            (Some(account), Some(other_account)) => account == other_account,
+            _ => true,
         }
     }
 
@@ -47,56 +85,881 @@ impl MailUpdaterTask {
         specific_maxilbox == other_specific_mailbox
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub enum TaskPriority {
+    #[default]
+    Normal,
+    //processed after all normal tasks
+    Low,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskEvent {
+    Queued(MailUpdaterTask),
+    Started(MailUpdaterTask),
+    //the error message of a failed sync
+    Finished {
+        task: MailUpdaterTask,
+        result: Result<ExecReport, String>,
+        duration: Duration,
+    },
+}
+
+//the consecutive failures of an account, no tasks of it run while the circuit is open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CircuitState {
+    pub failures: u32,
+    pub open_until: Option<Instant>,
+}
+
+impl CircuitState {
+    pub fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|open_until| open_until > now)
+    }
+}
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+//network failures are retried beyond the retries of other failures, but not forever, e.g. if a
+//server is gone for good
+const MAX_NETWORK_RETRIES: u32 = 10;
+
+//the number of recent syncs of the statistics
+const STATS_WINDOW: usize = 100;
+
+//the durations and results of the recent syncs of an account or mailbox
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    recent: VecDeque<(Duration, bool)>,
+}
+
+impl TaskStats {
+    fn record(&mut self, duration: Duration, success: bool) {
+        self.recent.push_back((duration, success));
+        if self.recent.len() > STATS_WINDOW {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn runs(&self) -> usize {
+        self.recent.len()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.recent.iter().map(|(duration, _)| *duration).min()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.recent.iter().map(|(duration, _)| *duration).max()
+    }
+
+    pub fn avg(&self) -> Option<Duration> {
+        let total = self
+            .recent
+            .iter()
+            .map(|(duration, _)| *duration)
+            .sum::<Duration>();
+        (!self.recent.is_empty()).then(|| total / self.recent.len() as u32)
+    }
+
+    //between 0 and 1
+    pub fn failure_rate(&self) -> f64 {
+        match self.recent.len() {
+            0 => 0.0,
+            runs => self.recent.iter().filter(|(_, success)| !success).count() as f64 / runs as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MailUpdaterOptions {
+    max_retries: u32,
+    retry_delay: Duration,
+    max_concurrent: Option<usize>,
+    coalesce_threshold: Option<usize>,
+    max_queue_len: Option<usize>,
+    max_age: Option<Duration>,
+    min_interval: Option<Duration>,
+    state_file: Option<PathBuf>,
+    circuit_breaker: Option<(u32, Duration)>,
+}
+
+impl Default for MailUpdaterOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            retry_delay: Duration::from_secs(30),
+            max_concurrent: None,
+            coalesce_threshold: None,
+            max_queue_len: None,
+            max_age: None,
+            min_interval: None,
+            state_file: None,
+            circuit_breaker: None,
+        }
+    }
+}
+
+impl MailUpdaterOptions {
+    //failed tasks are queued again, the delay doubles with every attempt
+    pub fn with_retry(mut self, max_retries: u32, retry_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    //limits the syncs running at the same time, 1 processes one task after another
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = Some(max_concurrent.max(1));
+        self
+    }
+
+    //this many queued mailbox tasks of an account are replaced by a sync of the whole account
+    pub fn with_coalesce_threshold(mut self, threshold: usize) -> Self {
+        self.coalesce_threshold = Some(threshold.max(1));
+        self
+    }
+
+    //a longer queue is collapsed into account tasks, or one task of all accounts
+    pub fn with_max_queue_len(mut self, max_queue_len: usize) -> Self {
+        self.max_queue_len = Some(max_queue_len.max(1));
+        self
+    }
+
+    //mailbox tasks waiting longer in the queue are replaced by a sync of their account
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    //tasks of a recently synced mailbox are deferred until the interval has passed
+    pub fn with_min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = Some(min_interval);
+        self
+    }
+
+    //the pending and running tasks are stored in the file and queued again at the next start
+    pub fn with_state_file(mut self, path: &Path) -> Self {
+        self.state_file = Some(path.to_path_buf());
+        self
+    }
+
+    //after this many failed syncs in a row an account is paused, then one sync probes it again
+    pub fn with_circuit_breaker(mut self, failures: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some((failures.max(1), cooldown));
+        self
+    }
+
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        self.retry_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct QueuedTask {
+    task: MailUpdaterTask,
+    priority: TaskPriority,
+    //the number of failed executions
+    attempt: u32,
+    //the wall clock keeps running while the system is suspended
+    queued_at: SystemTime,
+}
+
+impl QueuedTask {
+    fn new(task: MailUpdaterTask, priority: TaskPriority) -> Self {
+        Self {
+            task,
+            priority,
+            attempt: 0,
+            queued_at: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct UpdaterState {
+    queue: VecDeque<QueuedTask>,
+    //tasks waiting for their time to be queued
+    delayed: Vec<(Instant, QueuedTask)>,
+    //the running tasks, changes during their sync need a follow-up task
+    in_flight: Vec<QueuedTask>,
+    //the end of the recent syncs, for the minimum interval between them
+    last_synced: Vec<(MailUpdaterTask, Instant)>,
+    subscribers: Vec<Sender<TaskEvent>>,
+    //the number of the latest snapshot of the pending tasks
+    generation: u64,
+    circuits: HashMap<String, CircuitState>,
+    //by account and mailbox of the tasks
+    statistics: HashMap<(Option<String>, Option<String>), TaskStats>,
+    shutdown: bool,
+}
+
+impl UpdaterState {
+    //drops the subscribers without a receiver
+    fn publish(&mut self, event: TaskEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    //the running task is not considered, it may miss the changes of the new tasks
+    fn insert(&mut self, queued: QueuedTask) -> bool {
+        if self
+            .queue
+            .iter()
+            .any(|other| other.task.covers(&queued.task) && other.priority <= queued.priority)
+        {
+            return false;
+        }
+        self.queue
+            .retain(|other| other.priority <= queued.priority || !queued.task.covers(&other.task));
+        let position = self
+            .queue
+            .iter()
+            .position(|other| other.priority > queued.priority)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(position, queued);
+        true
+    }
+
+    //replaces the queued mailbox tasks of the account by one account task, if there are enough
+    fn coalesce(&mut self, account: &str, threshold: usize) {
+        let mailbox_tasks = self
+            .queue
+            .iter()
+            .filter(|queued| {
+                queued.task.specific_account.as_deref() == Some(account)
+                    && queued.task.specific_mailbox.is_some()
+            })
+            .count();
+        if mailbox_tasks < threshold {
+            return;
+        }
+        let account_tasks = self
+            .queue
+            .iter()
+            .filter(|queued| queued.task.specific_account.as_deref() == Some(account));
+        let source = account_tasks
+            .clone()
+            .map(|queued| queued.task.source)
+            .next()
+            .unwrap_or_default();
+        let priority = account_tasks
+            .map(|queued| queued.priority)
+            .min()
+            .unwrap_or(TaskPriority::Normal);
+        self.queue
+            .retain(|queued| queued.task.specific_account.as_deref() != Some(account));
+        log::info!("coalesce {} tasks of {}", mailbox_tasks, account);
+        self.insert(QueuedTask::new(
+            MailUpdaterTask::new(Some(account.to_owned()), None).with_source(source),
+            priority,
+        ));
+    }
+
+    //replaces the queue by a task per account, by a task of all accounts if that is still too long
+    fn collapse(&mut self, max_len: usize) {
+        if self.queue.len() <= max_len {
+            return;
+        }
+        let mut collapsed: Vec<QueuedTask> = Vec::new();
+        for queued in &self.queue {
+            let task = MailUpdaterTask::new(queued.task.specific_account.clone(), None)
+                .with_source(queued.task.source);
+            match collapsed
+                .iter_mut()
+                .find(|other| other.task.specific_account == task.specific_account)
+            {
+                Some(other) => other.priority = other.priority.min(queued.priority),
+                None => collapsed.push(QueuedTask::new(task, queued.priority)),
+            }
+        }
+        if collapsed.len() > max_len {
+            let priority = collapsed
+                .iter()
+                .map(|queued| queued.priority)
+                .min()
+                .unwrap_or(TaskPriority::Normal);
+            let source = collapsed[0].task.source;
+            collapsed = vec![QueuedTask::new(
+                MailUpdaterTask::new(None, None).with_source(source),
+                priority,
+            )];
+        }
+        log::warn!(
+            "queue overflow, collapse {} tasks into {:?}",
+            self.queue.len(),
+            collapsed
+                .iter()
+                .map(|queued| &queued.task)
+                .collect::<Vec<_>>()
+        );
+        self.queue.clear();
+        for queued in collapsed {
+            self.insert(queued);
+        }
+    }
+
+    //the first queued task without a running task of the same account
+    fn take_runnable(&mut self, max_concurrent: Option<usize>) -> Option<QueuedTask> {
+        if max_concurrent.is_some_and(|max_concurrent| self.in_flight.len() >= max_concurrent) {
+            return None;
+        }
+        let now = Instant::now();
+        let index = self.queue.iter().position(|queued| {
+            !self
+                .in_flight
+                .iter()
+                .any(|running| running.task.same_account(&queued.task))
+                && !self.is_circuit_open(&queued.task, now)
+        })?;
+        self.queue.remove(index)
+    }
+
+    fn is_circuit_open(&self, task: &MailUpdaterTask, now: Instant) -> bool {
+        task.specific_account
+            .as_ref()
+            .and_then(|account| self.circuits.get(account))
+            .is_some_and(|circuit| circuit.is_open(now))
+    }
+
+    //opens the circuit of the account after enough failures, a success closes it
+    fn record_result(&mut self, account: &str, success: bool, failures: u32, cooldown: Duration) {
+        if success {
+            if self
+                .circuits
+                .remove(account)
+                .is_some_and(|circuit| circuit.failures >= failures)
+            {
+                log::info!("circuit of {} closed", account);
+            }
+            return;
+        }
+        let circuit = self.circuits.entry(account.to_owned()).or_default();
+        circuit.failures += 1;
+        if circuit.failures >= failures {
+            circuit.open_until = Some(Instant::now() + cooldown);
+            log::error!(
+                "circuit of {} open after {} failed syncs, probe again in {:?}",
+                account,
+                circuit.failures,
+                cooldown
+            );
+        }
+    }
+
+    //delays the queued tasks until the minimum interval since their last sync has passed
+    fn defer_recent(&mut self, min_interval: Duration, now: Instant) {
+        self.last_synced
+            .retain(|(_, synced)| now.duration_since(*synced) < min_interval);
+        let mut index = 0;
+        while index < self.queue.len() {
+            let recent = self
+                .last_synced
+                .iter()
+                .filter(|(task, _)| task.covers(&self.queue[index].task))
+                .map(|(_, synced)| *synced)
+                .max();
+            match recent {
+                Some(synced) => {
+                    let queued = self.queue.remove(index).unwrap();
+                    log::debug!("defer {:?}, synced recently", queued.task);
+                    self.delayed.push((synced + min_interval, queued));
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    //a delayed task covering the new one gets the later deadline, otherwise it is delayed itself
+    fn delay(&mut self, queued: QueuedTask, deadline: Instant) -> bool {
+        if let Some((other_deadline, _)) = self
+            .delayed
+            .iter_mut()
+            .find(|(_, other)| other.attempt == 0 && other.task.covers(&queued.task))
+        {
+            *other_deadline = (*other_deadline).max(deadline);
+            return false;
+        }
+        self.delayed.push((deadline, queued));
+        true
+    }
+
+    fn queue_due(&mut self, now: Instant) {
+        let (due, delayed) = self
+            .delayed
+            .drain(..)
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        self.delayed = delayed;
+        for (_, queued) in due {
+            self.insert(QueuedTask {
+                queued_at: SystemTime::now(),
+                ..queued
+            });
+        }
+    }
+
+    //replaces mailbox tasks queued longer than the max age by a fresh task of their account
+    fn expire(&mut self, max_age: Duration, now: SystemTime) {
+        let (stale, fresh) = self.queue.drain(..).partition::<VecDeque<_>, _>(|queued| {
+            queued.task.specific_mailbox.is_some()
+                && now.duration_since(queued.queued_at).unwrap_or_default() > max_age
+        });
+        self.queue = fresh;
+        for queued in stale {
+            log::info!("task {:?} expired, sync the whole account", queued.task);
+            let task = MailUpdaterTask::new(queued.task.specific_account, None)
+                .with_source(queued.task.source);
+            self.insert(QueuedTask::new(task, queued.priority));
+        }
+    }
+
+    fn next_deadline(&self, now: Instant) -> Option<Instant> {
+        let circuits = self
+            .circuits
+            .values()
+            .filter_map(|circuit| circuit.open_until)
+            .filter(|open_until| *open_until > now);
+        self.delayed
+            .iter()
+            .map(|(deadline, _)| *deadline)
+            .chain(circuits)
+            .min()
+    }
+
+    fn pending_tasks(&mut self) -> (u64, Vec<StoredTask>) {
+        self.generation += 1;
+        let delayed = self.delayed.iter().map(|(_, queued)| queued);
+        let tasks = self
+            .in_flight
+            .iter()
+            .chain(&self.queue)
+            .chain(delayed)
+            .map(|queued| StoredTask {
+                task: queued.task.clone(),
+                priority: queued.priority,
+            })
+            .collect();
+        (self.generation, tasks)
+    }
+
+    fn running_tasks(&self) -> Vec<MailUpdaterTask> {
+        self.in_flight
+            .iter()
+            .map(|queued| queued.task.clone())
+            .collect()
+    }
+}
+
+//the state of older versions has no priorities
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct StoredTask {
+    #[serde(flatten)]
+    task: MailUpdaterTask,
+    #[serde(default)]
+    priority: TaskPriority,
+}
+
+fn load_tasks(path: &Path) -> Vec<StoredTask> {
+    match fs::read(path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+            log::warn!("could not parse queue state {:?}: {}", path, err);
+            Vec::new()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            log::warn!("could not read queue state {:?}: {}", path, err);
+            Vec::new()
+        }
+    }
+}
+
+fn store_tasks(path: &Path, tasks: &[StoredTask]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    //replace the file atomically, a crash must not leave a partial state
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, serde_json::to_vec(tasks)?)?;
+    fs::rename(tmp_path, path)
+}
+
+type PreHook = Arc<dyn Fn(&MailUpdaterTask) + Send + Sync>;
+type PostHook = Arc<dyn Fn(&MailUpdaterTask, &Result<ExecReport, String>) + Send + Sync>;
+
+//replaced on every added hook, so a sync runs its hooks without holding the lock
+#[derive(Default)]
+struct TaskHooks {
+    pre: Arc<[PreHook]>,
+    post: Arc<[PostHook]>,
+}
+
 pub struct MailUpdater {
-    queue: Mutex<VecDeque<MailUpdaterTask>>,
+    state: Mutex<UpdaterState>,
     queue_notify: Condvar,
+    options: MailUpdaterOptions,
+    hooks: RwLock<TaskHooks>,
+    //the generation of the stored state, it is written outside of the lock of the state
+    stored: Mutex<u64>,
+    //replaceable at runtime, e.g. on a reload of the config
+    executor: RwLock<Arc<dyn Executor>>,
 }
 
 impl MailUpdater {
-    pub fn new<F>(task_callback: F) -> Arc<Self>
-    where
-        F: FnMut(&MailUpdaterTask) + Send + 'static,
-    {
+    pub fn new<X: Executor + 'static>(executor: X) -> Arc<Self> {
+        Self::with_options(executor, MailUpdaterOptions::default())
+    }
+
+    pub fn with_options<X: Executor + 'static>(
+        executor: X,
+        options: MailUpdaterOptions,
+    ) -> Arc<Self> {
+        let restored = match &options.state_file {
+            Some(path) => load_tasks(path),
+            None => Vec::new(),
+        };
         let updater = Arc::new(Self {
-            queue: Mutex::default(),
+            state: Mutex::default(),
             queue_notify: Condvar::new(),
+            options,
+            hooks: RwLock::default(),
+            stored: Mutex::default(),
+            executor: RwLock::new(Arc::new(executor)),
         });
+        if !restored.is_empty() {
+            log::info!("restore pending tasks {:?}", restored);
+        }
+        for stored in restored {
+            updater.queue_tasks_with_priority([stored.task], stored.priority);
+        }
         let thrad_updater = updater.clone();
         thread::spawn(move || {
-            thrad_updater.process_queue(task_callback);
+            thrad_updater.process_queue();
         });
         updater
     }
 
-    pub fn process_queue<F>(&self, mut callback: F)
-    where
-        F: FnMut(&MailUpdaterTask),
-    {
+    //the queued tasks run with the new executor, running ones finish with the old one
+    pub fn set_executor<X: Executor + 'static>(&self, executor: X) {
+        *self.executor.write().unwrap() = Arc::new(executor);
+    }
+
+    //runs the tasks of different accounts concurrently, those of an account one after another
+    fn process_queue(self: &Arc<Self>) {
         loop {
-            let current_task = {
-                let mut queue = self.queue.lock().unwrap();
-                while queue.is_empty() {
-                    queue = self.queue_notify.wait(queue).unwrap();
-                }
-                queue.front().unwrap().clone()
+            let current = {
+                let mut state = self.state.lock().unwrap();
+                let current = loop {
+                    if state.shutdown {
+                        return;
+                    }
+                    state.queue_due(Instant::now());
+                    if let Some(max_age) = self.options.max_age {
+                        state.expire(max_age, SystemTime::now());
+                    }
+                    if let Some(min_interval) = self.options.min_interval {
+                        state.defer_recent(min_interval, Instant::now());
+                    }
+                    if let Some(current) = state.take_runnable(self.options.max_concurrent) {
+                        break current;
+                    }
+                    state = match state.next_deadline(Instant::now()) {
+                        Some(deadline) => {
+                            let timeout = deadline.saturating_duration_since(Instant::now());
+                            self.queue_notify.wait_timeout(state, timeout).unwrap().0
+                        }
+                        None => self.queue_notify.wait(state).unwrap(),
+                    };
+                };
+                state.in_flight.push(current.clone());
+                state.publish(TaskEvent::Started(current.task.clone()));
+                current
             };
-            callback(&current_task);
-            self.queue.lock().unwrap().pop_front();
+            let updater = self.clone();
+            let executor = self.executor.read().unwrap().clone();
+            thread::spawn(move || {
+                let started = Instant::now();
+                let (pre_hooks, post_hooks) = {
+                    let hooks = updater.hooks.read().unwrap();
+                    (hooks.pre.clone(), hooks.post.clone())
+                };
+                for hook in pre_hooks.iter() {
+                    hook(&current.task);
+                }
+                let result = executor.execute(&current.task);
+                let failure = result.as_ref().err().map(ExecError::kind);
+                let result = result.map_err(|err| err.to_string());
+                for hook in post_hooks.iter() {
+                    hook(&current.task, &result);
+                }
+                updater.finish(current, result, failure, started.elapsed());
+            });
+        }
+    }
+
+    fn finish(
+        &self,
+        current: QueuedTask,
+        result: Result<ExecReport, String>,
+        failure: Option<FailureKind>,
+        duration: Duration,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.publish(TaskEvent::Finished {
+            task: current.task.clone(),
+            result: result.clone(),
+            duration,
+        });
+        if let Some(index) = state
+            .in_flight
+            .iter()
+            .position(|running| running.task == current.task)
+        {
+            state.in_flight.remove(index);
+        }
+        //an interrupted or deferred sync tells nothing about the account
+        if let (Some((failures, cooldown)), Some(account), false) = (
+            self.options.circuit_breaker,
+            &current.task.specific_account,
+            matches!(
+                failure,
+                Some(FailureKind::Interrupted | FailureKind::Deferred)
+            ),
+        ) {
+            state.record_result(account, result.is_ok(), failures, cooldown);
+        }
+        //a deferred sync did not run
+        if failure != Some(FailureKind::Deferred) {
+            state
+                .statistics
+                .entry((
+                    current.task.specific_account.clone(),
+                    current.task.specific_mailbox.clone(),
+                ))
+                .or_default()
+                .record(duration, result.is_ok());
+        }
+        if self.options.min_interval.is_some() {
+            state
+                .last_synced
+                .push((current.task.clone(), Instant::now()));
+        }
+        if let Err(err) = result {
+            if failure == Some(FailureKind::Config) {
+                log::error!(
+                    "sync of {:?} failed: {}, not retried until the configuration is fixed",
+                    current.task,
+                    err
+                );
+            } else if failure == Some(FailureKind::Deferred)
+                || current.attempt < self.options.max_retries
+                || (failure == Some(FailureKind::Network) && current.attempt < MAX_NETWORK_RETRIES)
+            {
+                let delay = self.options.retry_delay(current.attempt);
+                log::warn!(
+                    "sync of {:?} failed: {}, retry in {:?}",
+                    current.task,
+                    err,
+                    delay
+                );
+                state.delayed.push((
+                    Instant::now() + delay,
+                    QueuedTask {
+                        attempt: current.attempt + 1,
+                        ..current
+                    },
+                ));
+            } else {
+                log::error!("sync of {:?} failed: {}", current.task, err);
+            }
+        }
+        //tasks of the account may be runnable now
+        self.queue_notify.notify_all();
+        //the state of a shutdown is stored by it
+        if !state.shutdown {
+            self.persist(state);
+        }
+    }
+
+    //stops processing and accepting tasks and returns the pending ones. with a timeout it waits
+    //for the running syncs to finish.
+    pub fn shutdown(&self, timeout: Option<Duration>) -> Vec<MailUpdaterTask> {
+        let mut state = self.state.lock().unwrap();
+        state.shutdown = true;
+        self.queue_notify.notify_all();
+        if let Some(timeout) = timeout {
+            state = self
+                .queue_notify
+                .wait_timeout_while(state, timeout, |state| !state.in_flight.is_empty())
+                .unwrap()
+                .0;
+            if !state.in_flight.is_empty() {
+                log::warn!(
+                    "syncs {:?} still running at shutdown",
+                    state.running_tasks()
+                );
+            }
+        }
+        //the syncs still running are restored with the pending tasks
+        let snapshot = state.pending_tasks();
+        let drained = &mut *state;
+        let delayed = drained.delayed.drain(..).map(|(_, queued)| queued);
+        let pending = drained.queue.drain(..).chain(delayed);
+        let pending = pending.map(|queued| queued.task).collect();
+        drop(state);
+        self.store(snapshot);
+        pending
+    }
+
+    fn persist(&self, mut state: MutexGuard<UpdaterState>) {
+        if self.options.state_file.is_some() {
+            let snapshot = state.pending_tasks();
+            drop(state);
+            self.store(snapshot);
+        }
+    }
+
+    //an older snapshot written after a newer one is skipped
+    fn store(&self, (generation, tasks): (u64, Vec<StoredTask>)) {
+        let Some(path) = &self.options.state_file else {
+            return;
+        };
+        let mut stored = self.stored.lock().unwrap();
+        if *stored > generation {
+            return;
         }
+        if let Err(err) = store_tasks(path, &tasks) {
+            log::error!("could not store queue state {:?}: {}", path, err);
+        }
+        *stored = generation;
+    }
+
+    //runs before every sync, in the thread of the sync
+    pub fn add_pre_hook<F>(&self, hook: F)
+    where
+        F: Fn(&MailUpdaterTask) + Send + Sync + 'static,
+    {
+        let mut hooks = self.hooks.write().unwrap();
+        hooks.pre = hooks
+            .pre
+            .iter()
+            .cloned()
+            .chain([Arc::new(hook) as PreHook])
+            .collect();
+    }
+
+    //runs after every sync with its result
+    pub fn add_post_hook<F>(&self, hook: F)
+    where
+        F: Fn(&MailUpdaterTask, &Result<ExecReport, String>) + Send + Sync + 'static,
+    {
+        let mut hooks = self.hooks.write().unwrap();
+        hooks.post = hooks
+            .post
+            .iter()
+            .cloned()
+            .chain([Arc::new(hook) as PostHook])
+            .collect();
+    }
+
+    //the events of all tasks queued afterwards
+    pub fn subscribe(&self) -> Receiver<TaskEvent> {
+        let (events_tx, events_rx) = mpsc::channel();
+        self.state.lock().unwrap().subscribers.push(events_tx);
+        events_rx
+    }
+
+    pub fn circuits(&self) -> HashMap<String, CircuitState> {
+        self.state.lock().unwrap().circuits.clone()
+    }
+
+    pub fn statistics(&self) -> HashMap<(Option<String>, Option<String>), TaskStats> {
+        self.state.lock().unwrap().statistics.clone()
+    }
+
+    pub fn in_flight(&self) -> Vec<MailUpdaterTask> {
+        self.state.lock().unwrap().running_tasks()
     }
 
     pub fn queue_task(&self, task: MailUpdaterTask) {
-        let mut queue = self.queue.lock().unwrap();
-        if !queue.iter().any(|queued_task| queued_task.covers(&task)) {
-            queue.push_back(task);
+        self.queue_tasks([task]);
+    }
+
+    //queues the task after the delay, queueing it again before extends the delay
+    pub fn queue_task_after(&self, task: MailUpdaterTask, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown {
+            return;
+        }
+        let event = TaskEvent::Queued(task.clone());
+        let queued = QueuedTask::new(task, TaskPriority::Normal);
+        //the dispatcher has to wait for the new deadline
+        self.queue_notify.notify_all();
+        if state.delay(queued, Instant::now() + delay) {
+            state.publish(event);
+            self.persist(state);
+        }
+    }
+
+    pub fn queue_tasks<I>(&self, tasks: I)
+    where
+        I: IntoIterator<Item = MailUpdaterTask>,
+    {
+        self.queue_tasks_with_priority(tasks, TaskPriority::Normal);
+    }
+
+    pub fn queue_tasks_with_priority<I>(&self, tasks: I, priority: TaskPriority)
+    where
+        I: IntoIterator<Item = MailUpdaterTask>,
+    {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown {
+            return;
+        }
+        let mut queued = false;
+        for task in tasks {
+            let account = task.specific_account.clone();
+            let event = TaskEvent::Queued(task.clone());
+            if state.insert(QueuedTask::new(task, priority)) {
+                state.publish(event);
+                queued = true;
+            }
+            if let (Some(account), Some(threshold)) = (account, self.options.coalesce_threshold) {
+                state.coalesce(&account, threshold);
+            }
+        }
+        if let Some(max_queue_len) = self.options.max_queue_len {
+            state.collapse(max_queue_len);
+        }
+        if queued {
             self.queue_notify.notify_one();
+            self.persist(state);
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::MailUpdaterTask;
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc::{self, Sender},
+            Arc, Barrier, Mutex,
+        },
+        thread,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use tempfile::TempDir;
+
+    use super::{
+        load_tasks, MailUpdater, MailUpdaterOptions, MailUpdaterTask, QueuedTask, StoredTask,
+        TaskEvent, TaskPriority, TaskSource, TaskStats, UpdaterState, MAX_NETWORK_RETRIES,
+        STATS_WINDOW,
+    };
+    use crate::executor::{ExecError, ExecReport, Executor};
 
     #[test]
     fn it_should_cover_tasks() {
@@ -128,4 +991,617 @@ mod tests {
         let task = MailUpdaterTask::new(Some("account".to_owned()), Some("mailbox1".to_owned()));
         assert!(queued_task.covers(&task));
     }
+
+    #[test]
+    fn it_should_pass_the_source_to_the_callback() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            tasks_tx.send(task.source).unwrap();
+            Ok::<_, String>(())
+        });
+        updater.queue_task(
+            MailUpdaterTask::new(Some("acc1".to_owned()), None).with_source(TaskSource::FileWatch),
+        );
+        assert_eq!(
+            TaskSource::FileWatch,
+            tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_publish_task_events() {
+        let updater = MailUpdater::new(|task: &MailUpdaterTask| match &task.specific_account {
+            Some(_) => Ok(()),
+            None => Err("mbsync failed"),
+        });
+        let events = updater.subscribe();
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), None);
+        updater.queue_task(task.clone());
+        let next = || events.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(TaskEvent::Queued(task.clone()), next());
+        assert_eq!(TaskEvent::Started(task.clone()), next());
+        assert!(matches!(next(), TaskEvent::Finished { result: Ok(_), .. }));
+        updater.queue_task(MailUpdaterTask::new(None, None));
+        next();
+        next();
+        match next() {
+            TaskEvent::Finished { task, result, .. } => {
+                assert_eq!(MailUpdaterTask::new(None, None), task);
+                assert_eq!(Err("mbsync failed".to_owned()), result);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    #[test]
+    fn it_should_run_queued_tasks_with_the_new_executor() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let old_tx = tasks_tx.clone();
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            old_tx.send(("old", task.clone())).unwrap();
+            Ok::<_, String>(())
+        });
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), None);
+        updater.queue_task(task.clone());
+        let next = || tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(("old", task.clone()), next());
+        updater.set_executor(move |task: &MailUpdaterTask| {
+            tasks_tx.send(("new", task.clone())).unwrap();
+            Ok::<_, String>(())
+        });
+        updater.queue_task(task.clone());
+        assert_eq!(("new", task), next());
+    }
+
+    #[test]
+    fn it_should_keep_statistics_of_the_recent_syncs() {
+        let mut stats = TaskStats::default();
+        assert_eq!(None, stats.avg());
+        for seconds in 1..=STATS_WINDOW as u64 + 2 {
+            stats.record(Duration::from_secs(seconds), seconds % 4 != 0);
+        }
+        assert_eq!(STATS_WINDOW, stats.runs());
+        assert_eq!(Some(Duration::from_secs(3)), stats.min());
+        assert_eq!(Some(Duration::from_secs(102)), stats.max());
+        assert_eq!(Some(Duration::from_millis(52500)), stats.avg());
+        assert_eq!(0.25, stats.failure_rate());
+    }
+
+    #[test]
+    fn it_should_run_hooks_around_tasks() {
+        let (calls_tx, calls_rx) = mpsc::channel();
+        let callback_tx = calls_tx.clone();
+        let updater = MailUpdater::new(move |_: &MailUpdaterTask| {
+            callback_tx.send("sync".to_owned()).unwrap();
+            Err("mbsync failed")
+        });
+        let pre_tx = calls_tx.clone();
+        updater.add_pre_hook(move |_| pre_tx.send("pre".to_owned()).unwrap());
+        updater.add_post_hook(move |_, result| {
+            calls_tx.send(format!("post {:?}", result)).unwrap();
+        });
+        updater.queue_task(MailUpdaterTask::new(Some("acc1".to_owned()), None));
+        let calls = (0..3)
+            .map(|_| calls_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["pre", "sync", r#"post Err("mbsync failed")"#], calls);
+    }
+
+    #[test]
+    fn it_should_add_hooks_while_a_task_runs() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let release_rx = Mutex::new(release_rx);
+        let updater = MailUpdater::new(move |_: &MailUpdaterTask| {
+            started_tx.send(()).unwrap();
+            let _ = release_rx
+                .lock()
+                .unwrap()
+                .recv_timeout(Duration::from_secs(2));
+            Ok::<_, String>(())
+        });
+        updater.add_pre_hook(|_| {});
+        updater.queue_task(MailUpdaterTask::new(Some("acc1".to_owned()), None));
+        started_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        let adding = Instant::now();
+        updater.add_post_hook(|_, _| {});
+        assert!(adding.elapsed() < Duration::from_secs(1));
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn it_should_process_low_priority_tasks_last() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            if task.specific_mailbox.as_deref() == Some("Blocker") {
+                task_barrier.wait();
+            }
+            tasks_tx.send(task.clone()).unwrap();
+            Ok::<_, String>(())
+        });
+        let task = |account: &str, mailbox: &str| {
+            MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+        };
+        //block the processing until all tasks are queued
+        updater.queue_task(task("acc1", "Blocker"));
+        updater.queue_tasks_with_priority(
+            [task("acc1", "INBOX"), task("acc1", "Archive")],
+            TaskPriority::Low,
+        );
+        updater.queue_tasks([task("acc1", "Sent"), task("acc1", "Archive")]);
+        barrier.wait();
+        let processed = (0..4)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                task("acc1", "Blocker"),
+                task("acc1", "Sent"),
+                task("acc1", "Archive"),
+                task("acc1", "INBOX"),
+            ],
+            processed
+        );
+    }
+
+    #[test]
+    fn it_should_queue_one_follow_up_of_changes_during_a_sync() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let first = AtomicBool::new(true);
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            if first.swap(false, Ordering::Relaxed) {
+                task_barrier.wait();
+                task_barrier.wait();
+            }
+            tasks_tx.send(task.clone()).unwrap();
+            Ok::<_, String>(())
+        });
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), Some("INBOX".to_owned()));
+        updater.queue_task(task.clone());
+        barrier.wait();
+        assert_eq!(vec![task.clone()], updater.in_flight());
+        updater.queue_task(task.clone());
+        updater.queue_task(task.clone());
+        barrier.wait();
+        assert_eq!(task, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        assert_eq!(task, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn it_should_retry_failed_tasks() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let options = MailUpdaterOptions::default().with_retry(2, Duration::from_millis(10));
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                tasks_tx.send(task.clone()).unwrap();
+                Err("mbsync failed")
+            },
+            options,
+        );
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), None);
+        updater.queue_task(task.clone());
+        for _ in 0..3 {
+            assert_eq!(task, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        }
+        //the retries are exhausted
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn it_should_not_retry_config_errors() {
+        struct MissingCommand(Sender<MailUpdaterTask>);
+        impl Executor for MissingCommand {
+            fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+                self.0.send(task.clone()).unwrap();
+                Err(io::Error::from(io::ErrorKind::NotFound).into())
+            }
+        }
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let options = MailUpdaterOptions::default().with_retry(2, Duration::from_millis(10));
+        let updater = MailUpdater::with_options(MissingCommand(tasks_tx), options);
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), None);
+        updater.queue_task(task.clone());
+        assert_eq!(task, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn it_should_retry_deferred_syncs_and_network_failures() {
+        struct Transient(Sender<MailUpdaterTask>, fn() -> ExecError);
+        impl Executor for Transient {
+            fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+                self.0.send(task.clone()).unwrap();
+                Err((self.1)())
+            }
+        }
+        let errors: [fn() -> ExecError; 2] = [
+            || ExecError::Deferred("vpn-check".to_owned()),
+            || ExecError::Timeout(Duration::from_secs(60)),
+        ];
+        for error in errors {
+            let (tasks_tx, tasks_rx) = mpsc::channel();
+            //they are retried beyond the retries of failures
+            let options = MailUpdaterOptions::default().with_retry(0, Duration::from_millis(10));
+            let updater = MailUpdater::with_options(Transient(tasks_tx, error), options);
+            let task = MailUpdaterTask::new(Some("acc1".to_owned()), None);
+            updater.queue_task(task.clone());
+            for _ in 0..3 {
+                assert_eq!(task, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_give_up_network_failures_eventually() {
+        struct Unreachable(Sender<MailUpdaterTask>);
+        impl Executor for Unreachable {
+            fn execute(&self, task: &MailUpdaterTask) -> Result<ExecReport, ExecError> {
+                self.0.send(task.clone()).unwrap();
+                Err(ExecError::Timeout(Duration::from_secs(60)))
+            }
+        }
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let options = MailUpdaterOptions::default().with_retry(0, Duration::from_millis(1));
+        let updater = MailUpdater::with_options(Unreachable(tasks_tx), options);
+        updater.queue_task(MailUpdaterTask::new(Some("acc1".to_owned()), None));
+        for _ in 0..=MAX_NETWORK_RETRIES {
+            tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        }
+        assert!(tasks_rx.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn it_should_sync_different_accounts_concurrently() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            //both accounts have to run at the same time to pass the barrier
+            if task.specific_mailbox.as_deref() == Some("Blocker") {
+                barrier.wait();
+            }
+            tasks_tx.send(task.clone()).unwrap();
+            Ok::<_, String>(())
+        });
+        let task = |account: &str, mailbox: &str| {
+            MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+        };
+        let acc1 = task("acc1", "Blocker");
+        let acc1_inbox = task("acc1", "INBOX");
+        let acc2 = task("acc2", "Blocker");
+        updater.queue_tasks([acc1.clone(), acc2.clone()]);
+        updater.queue_task(acc1_inbox.clone());
+        let mut processed = (0..3)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        //the task of the running account waits for it
+        assert_eq!(acc1_inbox, processed.pop().unwrap());
+        processed.sort_by_key(|task| task.specific_account.clone());
+        assert_eq!(vec![acc1, acc2], processed);
+    }
+
+    #[test]
+    fn it_should_limit_the_concurrent_syncs() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let running = AtomicUsize::new(0);
+        let options = MailUpdaterOptions::default().with_max_concurrent(1);
+        let updater = MailUpdater::with_options(
+            move |_: &MailUpdaterTask| {
+                let concurrent = running.fetch_add(1, Ordering::SeqCst) + 1;
+                thread::sleep(Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+                tasks_tx.send(concurrent).unwrap();
+                Ok::<_, String>(())
+            },
+            options,
+        );
+        updater.queue_tasks(
+            ["acc1", "acc2", "acc3"]
+                .map(|account| MailUpdaterTask::new(Some(account.to_owned()), None)),
+        );
+        for _ in 0..3 {
+            assert_eq!(1, tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap());
+        }
+    }
+
+    #[test]
+    fn it_should_return_pending_tasks_at_shutdown() {
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let updater = MailUpdater::new(move |_: &MailUpdaterTask| {
+            task_barrier.wait();
+            thread::sleep(Duration::from_millis(50));
+            Ok::<_, String>(())
+        });
+        let task =
+            |mailbox: &str| MailUpdaterTask::new(Some("acc1".to_owned()), Some(mailbox.to_owned()));
+        updater.queue_tasks([task("INBOX"), task("Sent")]);
+        barrier.wait();
+        assert_eq!(
+            vec![task("Sent")],
+            updater.shutdown(Some(Duration::from_secs(2)))
+        );
+        assert!(updater.in_flight().is_empty());
+        updater.queue_task(task("Archive"));
+        assert!(updater.shutdown(None).is_empty());
+    }
+
+    #[test]
+    fn it_should_coalesce_mailbox_tasks_of_an_account() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let options = MailUpdaterOptions::default().with_coalesce_threshold(3);
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                if task.specific_account.as_deref() == Some("blocker") {
+                    task_barrier.wait();
+                }
+                tasks_tx.send(task.clone()).unwrap();
+                Ok::<_, String>(())
+            },
+            options.with_max_concurrent(1),
+        );
+        let task = |account: &str, mailbox: &str| {
+            MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+        };
+        updater.queue_task(MailUpdaterTask::new(Some("blocker".to_owned()), None));
+        updater.queue_tasks([
+            task("acc1", "INBOX"),
+            task("acc2", "INBOX"),
+            task("acc1", "Sent"),
+            task("acc1", "Archive"),
+        ]);
+        barrier.wait();
+        let processed = (0..3)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                MailUpdaterTask::new(Some("blocker".to_owned()), None),
+                task("acc2", "INBOX"),
+                MailUpdaterTask::new(Some("acc1".to_owned()), None),
+            ],
+            processed
+        );
+    }
+
+    #[test]
+    fn it_should_collapse_an_overflowing_queue() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let options = MailUpdaterOptions::default().with_max_queue_len(2);
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                //blocks until the tasks are queued
+                if task.specific_account.as_deref() == Some("blocker") {
+                    task_barrier.wait();
+                    task_barrier.wait();
+                }
+                tasks_tx.send(task.clone()).unwrap();
+                Ok::<_, String>(())
+            },
+            options.with_max_concurrent(1),
+        );
+        let task = |account: &str, mailbox: &str| {
+            MailUpdaterTask::new(Some(account.to_owned()), Some(mailbox.to_owned()))
+        };
+        let account = |account: &str| MailUpdaterTask::new(Some(account.to_owned()), None);
+        updater.queue_task(account("blocker"));
+        barrier.wait();
+        updater.queue_tasks([task("acc1", "INBOX"), task("acc2", "INBOX")]);
+        updater.queue_tasks([task("acc1", "Sent"), task("acc1", "Archive")]);
+        barrier.wait();
+        let processed = (0..3)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![account("blocker"), account("acc1"), account("acc2")],
+            processed
+        );
+        updater.queue_task(account("blocker"));
+        barrier.wait();
+        updater.queue_tasks([task("acc1", "INBOX"), task("acc2", "INBOX")]);
+        updater.queue_task(task("acc3", "INBOX"));
+        barrier.wait();
+        assert_eq!(
+            account("blocker"),
+            tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap()
+        );
+        assert_eq!(
+            MailUpdaterTask::new(None, None),
+            tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_should_replace_expired_mailbox_tasks_by_account_tasks() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let barrier = Arc::new(Barrier::new(2));
+        let task_barrier = barrier.clone();
+        let options = MailUpdaterOptions::default().with_max_age(Duration::from_millis(100));
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                if task.specific_account.as_deref() == Some("blocker") {
+                    task_barrier.wait();
+                }
+                tasks_tx.send(task.clone()).unwrap();
+                Ok::<_, String>(())
+            },
+            options.with_max_concurrent(1),
+        );
+        let task =
+            |mailbox: &str| MailUpdaterTask::new(Some("acc1".to_owned()), Some(mailbox.to_owned()));
+        updater.queue_task(MailUpdaterTask::new(Some("blocker".to_owned()), None));
+        updater.queue_tasks([task("INBOX"), task("Sent")]);
+        thread::sleep(Duration::from_millis(200));
+        barrier.wait();
+        let processed = (0..2)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            MailUpdaterTask::new(Some("acc1".to_owned()), None),
+            processed[1]
+        );
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn it_should_expire_tasks_by_the_wall_clock() {
+        let mut state = UpdaterState::default();
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), Some("INBOX".to_owned()));
+        state.insert(QueuedTask::new(task.clone(), TaskPriority::Normal));
+        //the monotonic clock stands still while suspended, the wall clock doesn't
+        let resumed = SystemTime::now() + Duration::from_secs(3600);
+        state.expire(Duration::from_secs(600), resumed);
+        assert_eq!(
+            vec![MailUpdaterTask::new(Some("acc1".to_owned()), None)],
+            state
+                .queue
+                .iter()
+                .map(|queued| queued.task.clone())
+                .collect::<Vec<_>>()
+        );
+        //a clock set back doesn't expire the tasks
+        let mut state = UpdaterState::default();
+        state.insert(QueuedTask::new(task.clone(), TaskPriority::Normal));
+        state.expire(Duration::from_secs(600), SystemTime::UNIX_EPOCH);
+        assert_eq!(task, state.queue[0].task);
+    }
+
+    #[test]
+    fn it_should_defer_tasks_of_recently_synced_mailboxes() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let options = MailUpdaterOptions::default().with_min_interval(Duration::from_millis(300));
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                tasks_tx.send((task.clone(), Instant::now())).unwrap();
+                Ok::<_, String>(())
+            },
+            options,
+        );
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), Some("INBOX".to_owned()));
+        updater.queue_task(task.clone());
+        let (_, first) = tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        updater.queue_task(task.clone());
+        let (deferred, second) = tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(task, deferred);
+        assert!(second.duration_since(first) >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn it_should_extend_the_delay_of_scheduled_tasks() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let updater = MailUpdater::new(move |task: &MailUpdaterTask| {
+            tasks_tx.send((task.clone(), Instant::now())).unwrap();
+            Ok::<_, String>(())
+        });
+        let task = MailUpdaterTask::new(Some("acc1".to_owned()), Some("INBOX".to_owned()));
+        let start = Instant::now();
+        updater.queue_task_after(task.clone(), Duration::from_millis(200));
+        thread::sleep(Duration::from_millis(100));
+        updater.queue_task_after(task.clone(), Duration::from_millis(200));
+        let (processed, synced) = tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(task, processed);
+        assert!(synced.duration_since(start) >= Duration::from_millis(300));
+        assert!(tasks_rx.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+
+    #[test]
+    fn it_should_pause_accounts_with_failing_syncs() {
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let options =
+            MailUpdaterOptions::default().with_circuit_breaker(2, Duration::from_millis(300));
+        let updater = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                tasks_tx.send((task.clone(), Instant::now())).unwrap();
+                match task.specific_mailbox.as_deref() {
+                    Some("INBOX") => Ok(()),
+                    _ => Err("mbsync failed"),
+                }
+            },
+            options,
+        );
+        let task =
+            |mailbox: &str| MailUpdaterTask::new(Some("acc1".to_owned()), Some(mailbox.to_owned()));
+        for mailbox in ["Sent", "Archive"] {
+            updater.queue_task(task(mailbox));
+            tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert!(updater.circuits()["acc1"].is_open(Instant::now()));
+        let opened = Instant::now();
+        updater.queue_task(task("INBOX"));
+        let (probe, probed) = tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(task("INBOX"), probe);
+        assert!(probed.duration_since(opened) >= Duration::from_millis(200));
+        thread::sleep(Duration::from_millis(50));
+        assert!(updater.circuits().is_empty());
+    }
+
+    #[test]
+    fn it_should_restore_pending_tasks_from_the_state_file() {
+        let dir = TempDir::new().unwrap();
+        let state_file = dir.path().join("queue.json");
+        let options = MailUpdaterOptions::default().with_state_file(&state_file);
+        let updater = MailUpdater::with_options(
+            move |_: &MailUpdaterTask| {
+                thread::sleep(Duration::from_millis(500));
+                Ok::<_, String>(())
+            },
+            options.clone(),
+        );
+        let task =
+            |mailbox: &str| MailUpdaterTask::new(Some("acc1".to_owned()), Some(mailbox.to_owned()));
+        updater.queue_tasks([task("INBOX"), task("Sent")]);
+        //the sync of INBOX is interrupted by the shutdown
+        updater.shutdown(None);
+        let (tasks_tx, tasks_rx) = mpsc::channel();
+        let _restored = MailUpdater::with_options(
+            move |task: &MailUpdaterTask| {
+                tasks_tx.send(task.clone()).unwrap();
+                Ok::<_, String>(())
+            },
+            options,
+        );
+        let mut processed = (0..2)
+            .map(|_| tasks_rx.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect::<Vec<_>>();
+        processed.sort_by_key(|task| task.specific_mailbox.clone());
+        assert_eq!(vec![task("INBOX"), task("Sent")], processed);
+    }
+
+    #[test]
+    fn it_should_store_the_final_state_at_shutdown() {
+        let dir = TempDir::new().unwrap();
+        let state_file = dir.path().join("queue.json");
+        let updater = MailUpdater::with_options(
+            move |_: &MailUpdaterTask| {
+                thread::sleep(Duration::from_millis(200));
+                Ok::<_, String>(())
+            },
+            MailUpdaterOptions::default()
+                .with_max_concurrent(1)
+                .with_state_file(&state_file),
+        );
+        let task = |account: &str| MailUpdaterTask::new(Some(account.to_owned()), None);
+        updater.queue_task(task("acc1"));
+        thread::sleep(Duration::from_millis(50));
+        updater.queue_tasks_with_priority([task("acc2")], TaskPriority::Low);
+        //the running sync of acc1 finishes before the shutdown
+        updater.shutdown(Some(Duration::from_secs(2)));
+        assert_eq!(
+            vec![StoredTask {
+                task: task("acc2"),
+                priority: TaskPriority::Low
+            }],
+            load_tasks(&state_file)
+        );
+    }
 }
@@ -0,0 +1,137 @@
+use std::{io, path::PathBuf, process::Command};
+
+#[cfg(target_os = "linux")]
+use std::{
+    ffi::{CStr, CString},
+    mem,
+    os::unix::process::CommandExt,
+    ptr,
+};
+
+//a user of the system the syncs of an account run as, e.g. by a mailwatch of root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalUser {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+    pub home: PathBuf,
+}
+
+#[cfg(target_os = "linux")]
+const PASSWD_BUFFER_LEN: usize = 16384;
+
+impl LocalUser {
+    //the user of the passwd database with its supplementary groups
+    #[cfg(target_os = "linux")]
+    pub fn lookup(name: &str) -> Result<Self, io::Error> {
+        let c_name = CString::new(name)?;
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut buffer = vec![0 as libc::c_char; PASSWD_BUFFER_LEN];
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if result.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no user {}", name),
+            ));
+        }
+        let home = unsafe { CStr::from_ptr(passwd.pw_dir) }
+            .to_string_lossy()
+            .into_owned();
+        let mut groups = vec![0; 64];
+        loop {
+            let mut len = groups.len() as libc::c_int;
+            let rc = unsafe {
+                libc::getgrouplist(
+                    c_name.as_ptr(),
+                    passwd.pw_gid,
+                    groups.as_mut_ptr(),
+                    &mut len,
+                )
+            };
+            groups.resize(len as usize, 0);
+            if rc >= 0 {
+                break;
+            }
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            groups,
+            home: PathBuf::from(home),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn lookup(name: &str) -> Result<Self, io::Error> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("can not run as {}, only supported on linux", name),
+        ))
+    }
+
+    //the command runs with the ids and the home of the user, so its config and credentials are used
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, command: &mut Command) {
+        command
+            .env("HOME", &self.home)
+            .env("USER", &self.name)
+            .env("LOGNAME", &self.name);
+        let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+        //the groups have to be set before the ids, while still privileged
+        let set = move || {
+            if unsafe { libc::setgroups(groups.len(), groups.as_ptr()) } == -1
+                || unsafe { libc::setgid(gid) } == -1
+                || unsafe { libc::setuid(uid) } == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        };
+        unsafe { command.pre_exec(set) };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _command: &mut Command) {}
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{path::Path, process::Command};
+
+    use super::LocalUser;
+
+    #[test]
+    fn it_should_run_commands_as_the_user() {
+        let root = LocalUser::lookup("root").unwrap();
+        assert_eq!(0, root.uid);
+        assert_eq!(Path::new("/root"), root.home);
+        assert!(LocalUser::lookup("no-such-user").is_err());
+        //switching the user needs root
+        if unsafe { libc::getuid() } != 0 {
+            return;
+        }
+        let nobody = LocalUser::lookup("nobody").unwrap();
+        let mut command = Command::new("id");
+        command.arg("-u");
+        nobody.apply(&mut command);
+        let output = command.output().unwrap();
+        assert_eq!(
+            nobody.uid.to_string(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+}
@@ -1,134 +1,1028 @@
 use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, fs, io, iter,
     path::{Path, PathBuf},
-    sync::mpsc::{self, Receiver, RecvTimeoutError, SendError, Sender},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-use notify::{Event, INotifyWatcher, RecursiveMode, Watcher};
+use glob::Pattern;
+use notify::{
+    event::{AccessKind, ModifyKind, RenameMode},
+    Event, PollWatcher, RecursiveMode, Watcher,
+};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use thiserror::Error;
 use utf7_imap::decode_utf7_imap;
 
+#[cfg(target_os = "linux")]
+use crate::fanotify::FanotifyWatcher;
+#[cfg(feature = "stream")]
+use crate::stream::FileWatcherStream;
+use crate::{
+    accounts::{AccountLayout, MailboxFilter},
+    suppressor::SyncSuppressor,
+};
+
 #[derive(Debug, Error)]
 pub enum FileWatcherError {
     #[error("notify error: {0}")]
     NotifyError(#[from] notify::Error),
+    #[error("IO-Error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid path pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("invalid ignore pattern: {0}")]
+    InvalidIgnorePattern(#[from] glob::PatternError),
+    #[error("path pattern {0} has no account group")]
+    MissingAccountGroup(String),
+    #[error("watcher backend {0} is not supported on this platform")]
+    UnsupportedBackend(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileWatcherEventKind {
+    Create,
+    Modify,
+    Remove,
+    //a mailbox directory was renamed
+    Rename,
+    //a file of a mailbox was opened, only reported with sync on access
+    Access,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileWatcherEvent {
     pub account: String,
-    pub mailbox: String,
+    pub mailbox: Option<String>,
+    pub kind: FileWatcherEventKind,
+    //the changed file or directory
+    pub path: PathBuf,
+    //the old name of a renamed mailbox
+    pub renamed_from: Option<String>,
+    //only known for backends reporting the originating process
+    pub process: Option<WriterProcess>,
+}
+
+impl FileWatcherEvent {
+    fn same_mailbox(&self, other: &FileWatcherEvent) -> bool {
+        self.account == other.account
+            && self.mailbox == other.mailbox
+            && self.renamed_from == other.renamed_from
+            && (self.kind == FileWatcherEventKind::Access)
+                == (other.kind == FileWatcherEventKind::Access)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriterProcess {
+    pub pid: u32,
+    pub comm: Option<String>,
 }
 
 #[derive(Error, Debug)]
 enum ProduceEventError {
     #[error("skip event")]
     Skip,
-    #[error("send error: {0}")]
-    SendError(#[from] SendError<FileWatcherEvent>),
+    #[error("receiver of the events is gone")]
+    Disconnected,
+}
+
+//a path pattern with an optional filter for the names of the changed files
+type PathPattern = (Regex, Option<Pattern>);
+//the path of a mailbox directory below a renamed one, the file matching its pattern and the mailbox
+type MailboxDir = (PathBuf, Option<String>, (String, Option<String>));
+
+static PATH_PATTERNS: [(&str, Option<&str>); 4] = [
+    //sdbox
+    (
+        r"^/?(?P<account>[^/]+)/Mail/mailboxes/(?P<mailbox>.+)/dbox-Mails$",
+        None,
+    ),
+    //mdbox, the storage is shared by all mailboxes of the account
+    (
+        r"^/?(?P<account>[^/]+)/mdbox/mailboxes/(?P<mailbox>.+)/dbox-Mails$",
+        None,
+    ),
+    (r"^/?(?P<account>[^/]+)/mdbox/storage$", None),
+    //sdbox with the u.<uid> files directly in the mailbox, next to the index files
+    (
+        r"^/?(?P<account>[^/]+)/Mail/mailboxes/(?P<mailbox>.+)$",
+        Some("u.*"),
+    ),
+];
+
+const ACCOUNT_GROUP: &str = "(?P<account>[^/]+)";
+
+//the default patterns with an account group spanning the directories of the layout
+fn default_path_patterns(layout: &AccountLayout) -> Vec<PathPattern> {
+    let account_group = format!("(?P<account>[^/]+{})", "/[^/]+".repeat(layout.depth() - 1));
+    PATH_PATTERNS
+        .iter()
+        .map(|(pattern, filename)| {
+            (
+                Regex::new(&pattern.replace(ACCOUNT_GROUP, &account_group)).unwrap(),
+                filename.map(|filename| Pattern::new(filename).unwrap()),
+            )
+        })
+        .collect()
+}
+
+static IGNORE_PATTERNS: Lazy<Vec<Pattern>> = Lazy::new(|| {
+    ["dovecot.index.cache", "dovecot.map.index*", ".temp*"]
+        .iter()
+        .map(|pattern| Pattern::new(pattern).unwrap())
+        .collect()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileWatcherBackend {
+    //the native backend of the platform
+    #[default]
+    Recommended,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Inotify,
+    //reports the writing process, needs CAP_SYS_ADMIN and linux 5.9
+    #[cfg(target_os = "linux")]
+    Fanotify,
+    #[cfg(target_os = "macos")]
+    FsEvents,
+    #[cfg(any(
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly",
+        target_os = "ios"
+    ))]
+    Kqueue,
+    #[cfg(target_os = "windows")]
+    ReadDirectoryChanges,
+    //for filesystems without change notifications, like NFS
+    Poll(Duration),
+}
+
+type DecodeFn = dyn Fn(&str) -> String + Send + Sync;
+
+//decodes the mailbox names of the directories
+#[derive(Clone, Default)]
+pub enum MailboxDecoder {
+    //modified UTF-7 of IMAP, the default of dovecot
+    #[default]
+    Mutf7,
+    //the names are used as they are, e.g. with mailbox_list_utf8
+    Raw,
+    Custom(Arc<DecodeFn>),
+}
+
+impl MailboxDecoder {
+    pub fn custom<F>(decode: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(decode))
+    }
+
+    pub fn decode(&self, name: &str) -> String {
+        match self {
+            MailboxDecoder::Mutf7 => decode_utf7_imap(name.to_owned()),
+            MailboxDecoder::Raw => name.to_owned(),
+            MailboxDecoder::Custom(decode) => decode(name),
+        }
+    }
+}
+
+impl fmt::Debug for MailboxDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailboxDecoder::Mutf7 => write!(f, "Mutf7"),
+            MailboxDecoder::Raw => write!(f, "Raw"),
+            MailboxDecoder::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileWatcherOptions {
+    path_patterns: Vec<PathPattern>,
+    custom_path_pattern: bool,
+    account_layout: AccountLayout,
+    ignore_patterns: Vec<Pattern>,
+    ignored_dirs: Vec<String>,
+    mailbox_decoder: MailboxDecoder,
+    backend: FileWatcherBackend,
+    fallback_poll_interval: Duration,
+    debounce: Duration,
+    suppressor: Option<SyncSuppressor>,
+    ignored_processes: Vec<String>,
+    access_cooldown: Option<Duration>,
+    mailbox_filter: MailboxFilter,
+}
+
+impl Default for FileWatcherOptions {
+    fn default() -> Self {
+        Self {
+            path_patterns: default_path_patterns(&AccountLayout::default()),
+            custom_path_pattern: false,
+            account_layout: AccountLayout::default(),
+            ignore_patterns: IGNORE_PATTERNS.clone(),
+            ignored_dirs: Vec::new(),
+            mailbox_decoder: MailboxDecoder::default(),
+            backend: FileWatcherBackend::default(),
+            fallback_poll_interval: Duration::from_secs(10),
+            debounce: Duration::ZERO,
+            suppressor: None,
+            ignored_processes: Vec::new(),
+            access_cooldown: None,
+            mailbox_filter: MailboxFilter::default(),
+        }
+    }
+}
+
+impl FileWatcherOptions {
+    pub fn with_path_pattern(mut self, pattern: &str) -> Result<Self, FileWatcherError> {
+        let regex = Regex::new(pattern)?;
+        if !regex.capture_names().any(|name| name == Some("account")) {
+            return Err(FileWatcherError::MissingAccountGroup(pattern.to_owned()));
+        }
+        self.path_patterns = vec![(regex, None)];
+        self.custom_path_pattern = true;
+        Ok(self)
+    }
+
+    //a custom path pattern has to match the directories of the layout with its account group
+    pub fn with_account_layout(mut self, layout: AccountLayout) -> Self {
+        if !self.custom_path_pattern {
+            self.path_patterns = default_path_patterns(&layout);
+        }
+        self.account_layout = layout;
+        self
+    }
+
+    //replaces the default list of ignored filenames
+    pub fn with_ignore_patterns(mut self, patterns: &[String]) -> Result<Self, FileWatcherError> {
+        self.ignore_patterns = patterns
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    //directories with these names are skipped anywhere below the root, like dovecot's INDEX and
+    //CONTROL directories
+    pub fn with_ignored_dirs(mut self, dirs: &[String]) -> Self {
+        self.ignored_dirs = dirs.to_vec();
+        self
+    }
+
+    pub fn with_mailbox_decoder(mut self, decoder: MailboxDecoder) -> Self {
+        self.mailbox_decoder = decoder;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: FileWatcherBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    //used for the subtrees exceeding the watch limit of the backend
+    pub fn with_fallback_poll_interval(mut self, interval: Duration) -> Self {
+        self.fallback_poll_interval = interval;
+        self
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub fn with_suppressor(mut self, suppressor: SyncSuppressor) -> Self {
+        self.suppressor = Some(suppressor);
+        self
+    }
+
+    //changes of these processes are ignored, if the backend reports the process
+    pub fn with_ignored_processes(mut self, processes: &[String]) -> Self {
+        self.ignored_processes = processes.to_vec();
+        self
+    }
+
+    pub fn with_mailbox_filter(mut self, filter: MailboxFilter) -> Self {
+        self.mailbox_filter = filter;
+        self
+    }
+
+    //reports opened files of a mailbox as access event, at most once per cooldown and mailbox
+    pub fn with_sync_on_access(mut self, cooldown: Duration) -> Self {
+        self.access_cooldown = Some(cooldown);
+        self
+    }
+}
+
+//collects events for the same account and mailbox within a window and emits them once
+struct EventDebouncer {
+    events_tx: Sender<FileWatcherEvent>,
+    window: Duration,
+    pending: Vec<(Instant, FileWatcherEvent)>,
+}
+
+impl EventDebouncer {
+    fn new(events_tx: Sender<FileWatcherEvent>, window: Duration) -> Self {
+        Self {
+            events_tx,
+            window,
+            pending: Vec::new(),
+        }
+    }
+
+    fn send(&self, event: FileWatcherEvent) -> Result<(), ProduceEventError> {
+        self.events_tx
+            .send(event)
+            .map_err(|_| ProduceEventError::Disconnected)
+    }
+
+    fn push(&mut self, event: FileWatcherEvent) -> Result<(), ProduceEventError> {
+        if self.window.is_zero() {
+            return self.send(event);
+        }
+        if !self
+            .pending
+            .iter()
+            .any(|(_, pending)| pending.same_mailbox(&event))
+        {
+            self.pending.push((Instant::now() + self.window, event));
+        }
+        Ok(())
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.pending
+            .first()
+            .map(|(deadline, _)| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn flush_due(&mut self) -> Result<(), ProduceEventError> {
+        let now = Instant::now();
+        while self
+            .pending
+            .first()
+            .is_some_and(|(deadline, _)| *deadline <= now)
+        {
+            let (_, event) = self.pending.remove(0);
+            self.send(event)?;
+        }
+        Ok(())
+    }
+}
+
+//the next event of the watchers, without a timeout until they stopped
+pub fn wait_for_event(
+    events: &Receiver<FileWatcherEvent>,
+    timeout: Option<Duration>,
+) -> Result<FileWatcherEvent, RecvTimeoutError> {
+    match timeout {
+        Some(timeout) => events.recv_timeout(timeout),
+        None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    }
+}
+
+//waits like wait_for_event and then drains the pending events, up to max
+pub fn wait_for_events(
+    events: &Receiver<FileWatcherEvent>,
+    max: usize,
+    timeout: Option<Duration>,
+) -> Result<Vec<FileWatcherEvent>, RecvTimeoutError> {
+    let mut batch = vec![wait_for_event(events, timeout)?];
+    while batch.len() < max {
+        match events.try_recv() {
+            Ok(event) => batch.push(event),
+            Err(_) => break,
+        }
+    }
+    Ok(batch)
 }
 
-static PATH_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"^/?([^/]+)/Mail/mailboxes/(.+)/dbox-Mails$").unwrap());
+//identifies the watched root, to notice when it was unmounted or replaced
+type RootIdentity = (u64, u64);
 
 pub struct FileWatcher {
     events: Receiver<FileWatcherEvent>,
-    _watcher: INotifyWatcher,
+    root: PathBuf,
+    root_identity: Option<RootIdentity>,
+    _watcher: Box<dyn Send>,
 }
 
 impl FileWatcher {
     pub fn new(path: &Path) -> Result<Self, FileWatcherError> {
+        Self::with_options(path, FileWatcherOptions::default())
+    }
+
+    pub fn with_options(
+        path: &Path,
+        options: FileWatcherOptions,
+    ) -> Result<Self, FileWatcherError> {
         let (notify_tx, notify_rx) = mpsc::channel::<Result<Event, notify::Error>>();
         let (events_tx, events_rx) = mpsc::channel::<FileWatcherEvent>();
-        let mut watcher = notify::recommended_watcher(notify_tx)?;
-        watcher.watch(path, RecursiveMode::Recursive)?;
+        //the backends report resolved paths for a symlinked root
+        let basepath = fs::canonicalize(path)?;
+        let watcher = Self::create_watcher(&options, &basepath, notify_tx)?;
         let filewatcher = Self {
             events: events_rx,
+            root: path.to_path_buf(),
+            root_identity: Self::root_identity(path),
             _watcher: watcher,
         };
-        Self::handle_events(path.to_path_buf(), notify_rx, events_tx);
+        Self::handle_events(basepath, options, notify_rx, events_tx);
         Ok(filewatcher)
     }
 
+    //the mailboxes with files modified after the time, e.g. changed while not running
+    pub fn changed_since(
+        path: &Path,
+        options: &FileWatcherOptions,
+        since: SystemTime,
+    ) -> Result<Vec<FileWatcherEvent>, FileWatcherError> {
+        let basepath = fs::canonicalize(path)?;
+        let mut events: Vec<FileWatcherEvent> = Vec::new();
+        let mut dirs = vec![basepath.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    dirs.push(entry.path());
+                    continue;
+                }
+                let filename = entry.file_name().to_string_lossy().into_owned();
+                if !file_type.is_file()
+                    || entry.metadata()?.modified()? <= since
+                    || options
+                        .ignore_patterns
+                        .iter()
+                        .any(|pattern| pattern.matches(&filename))
+                {
+                    continue;
+                }
+                let Some((account, mailbox)) =
+                    Self::mailbox_of(&basepath, options, &dir, Some(&filename))
+                else {
+                    continue;
+                };
+                if mailbox
+                    .as_ref()
+                    .is_some_and(|mailbox| options.mailbox_filter.is_ignored(&account, mailbox))
+                    || events
+                        .iter()
+                        .any(|event| event.account == account && event.mailbox == mailbox)
+                {
+                    continue;
+                }
+                events.push(FileWatcherEvent {
+                    account,
+                    mailbox,
+                    kind: FileWatcherEventKind::Modify,
+                    path: entry.path(),
+                    renamed_from: None,
+                    process: None,
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    //false if the root disappeared or was replaced since the watches were established
+    pub fn is_root_available(&self) -> bool {
+        self.root_identity.is_some() && Self::root_identity(&self.root) == self.root_identity
+    }
+
+    #[cfg(unix)]
+    fn root_identity(path: &Path) -> Option<RootIdentity> {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path)
+            .ok()
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    fn root_identity(path: &Path) -> Option<RootIdentity> {
+        fs::metadata(path).ok().map(|_| (0, 0))
+    }
+
+    fn create_watcher(
+        options: &FileWatcherOptions,
+        path: &Path,
+        notify_tx: Sender<Result<Event, notify::Error>>,
+    ) -> Result<Box<dyn Send>, FileWatcherError> {
+        let watcher = match options.backend {
+            FileWatcherBackend::Recommended => Self::watch_with_fallback(
+                notify::recommended_watcher(notify_tx.clone())?,
+                path,
+                options.fallback_poll_interval,
+                notify_tx.clone(),
+            ),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            FileWatcherBackend::Inotify => Self::watch_with_fallback(
+                notify::INotifyWatcher::new(notify_tx.clone(), notify::Config::default())?,
+                path,
+                options.fallback_poll_interval,
+                notify_tx.clone(),
+            ),
+            #[cfg(target_os = "linux")]
+            FileWatcherBackend::Fanotify => {
+                return Ok(Box::new(FanotifyWatcher::new(path, notify_tx)?));
+            }
+            #[cfg(target_os = "macos")]
+            FileWatcherBackend::FsEvents => Self::watch(
+                notify::FsEventWatcher::new(notify_tx, notify::Config::default())?,
+                path,
+            ),
+            #[cfg(any(
+                target_os = "freebsd",
+                target_os = "openbsd",
+                target_os = "netbsd",
+                target_os = "dragonfly",
+                target_os = "ios"
+            ))]
+            FileWatcherBackend::Kqueue => Self::watch(
+                notify::KqueueWatcher::new(notify_tx, notify::Config::default())?,
+                path,
+            ),
+            #[cfg(target_os = "windows")]
+            FileWatcherBackend::ReadDirectoryChanges => Self::watch(
+                notify::ReadDirectoryChangesWatcher::new(notify_tx, notify::Config::default())?,
+                path,
+            ),
+            FileWatcherBackend::Poll(interval) => Self::watch(
+                PollWatcher::new(
+                    notify_tx,
+                    notify::Config::default().with_poll_interval(interval),
+                )?,
+                path,
+            ),
+        }?;
+        Ok(watcher)
+    }
+
+    fn watch<W>(mut watcher: W, path: &Path) -> Result<Box<dyn Send>, FileWatcherError>
+    where
+        W: Watcher + Send + 'static,
+    {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+        Ok(Box::new(watcher))
+    }
+
+    //falls back to polling for the subtrees which exceed the watch limit of inotify
+    fn watch_with_fallback<W>(
+        mut watcher: W,
+        path: &Path,
+        poll_interval: Duration,
+        notify_tx: Sender<Result<Event, notify::Error>>,
+    ) -> Result<Box<dyn Send>, FileWatcherError>
+    where
+        W: Watcher + Send + 'static,
+    {
+        match watcher.watch(path, RecursiveMode::Recursive) {
+            Ok(()) => return Ok(Box::new(watcher)),
+            Err(err) if matches!(err.kind, notify::ErrorKind::MaxFilesWatch) => {}
+            Err(err) => return Err(err.into()),
+        }
+        Self::log_watch_limit(path);
+        //remove the watches of the failed attempt
+        let _ = watcher.unwatch(path);
+        watcher.watch(path, RecursiveMode::NonRecursive)?;
+        let mut poll_watcher = PollWatcher::new(
+            notify_tx,
+            notify::Config::default().with_poll_interval(poll_interval),
+        )?;
+        for entry in fs::read_dir(path)? {
+            let subtree = entry?.path();
+            if !subtree.is_dir() {
+                continue;
+            }
+            match watcher.watch(&subtree, RecursiveMode::Recursive) {
+                Ok(()) => {}
+                Err(err) if matches!(err.kind, notify::ErrorKind::MaxFilesWatch) => {
+                    let _ = watcher.unwatch(&subtree);
+                    log::warn!("poll {:?} because of the watch limit", subtree);
+                    poll_watcher.watch(&subtree, RecursiveMode::Recursive)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Box::new((watcher, poll_watcher)))
+    }
+
+    //the limit of inotify, the other backends are limited by the open files
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn log_watch_limit(path: &Path) {
+        let required = Self::count_dirs(path);
+        let limit = fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+            .map(|limit| limit.trim().to_owned())
+            .unwrap_or_else(|_| "unknown".to_owned());
+        log::error!(
+            "watch limit reached for {:?}: {} directories need a watch, but fs.inotify.max_user_watches is {}. \
+            Raise the limit, e.g. with `sysctl fs.inotify.max_user_watches={}`. Falling back to polling for now.",
+            path,
+            required,
+            limit,
+            (required * 2).max(8192)
+        );
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn log_watch_limit(path: &Path) {
+        log::error!(
+            "watch limit reached for {:?}: {} directories need a watch. Raise the limit of open files, \
+            e.g. with `ulimit -n`. Falling back to polling for now.",
+            path,
+            Self::count_dirs(path)
+        );
+    }
+
+    fn count_dirs(path: &Path) -> usize {
+        let Ok(entries) = fs::read_dir(path) else {
+            return 0;
+        };
+        1 + entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+            .map(|entry| Self::count_dirs(&entry.path()))
+            .sum::<usize>()
+    }
+
     pub fn wait_for_event(
         &self,
         timeout: Option<Duration>,
     ) -> Result<FileWatcherEvent, RecvTimeoutError> {
-        match timeout {
-            Some(timeout) => self.events.recv_timeout(timeout),
-            None => self
-                .events
-                .recv()
-                .map_err(|_| RecvTimeoutError::Disconnected),
+        wait_for_event(&self.events, timeout)
+    }
+
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self) -> FileWatcherStream {
+        FileWatcherStream::new(self.events, self._watcher)
+    }
+
+    //waits like wait_for_event and then drains the pending events, up to max
+    pub fn wait_for_events(
+        &self,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<FileWatcherEvent>, RecvTimeoutError> {
+        wait_for_events(&self.events, max, timeout)
+    }
+
+    //path relative to the basepath, always separated by / so the patterns work on every platform.
+    //true if a name is not valid UTF-8 and was decoded lossy.
+    fn relative_path(basepath: &Path, path: &Path) -> Option<(String, bool)> {
+        let canonical;
+        let relative = match path.strip_prefix(basepath) {
+            Ok(relative) => relative,
+            Err(_) => {
+                canonical = Self::canonicalize(path)?;
+                canonical.strip_prefix(basepath).ok()?
+            }
+        };
+        let components = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+        let lossy = components
+            .iter()
+            .any(|component| matches!(component, Cow::Owned(_)));
+        Some((components.join("/"), lossy))
+    }
+
+    //removed files can't be resolved, but their directory
+    fn canonicalize(path: &Path) -> Option<PathBuf> {
+        fs::canonicalize(path).ok().or_else(|| {
+            Some(
+                fs::canonicalize(path.parent()?)
+                    .ok()?
+                    .join(path.file_name()?),
+            )
+        })
+    }
+
+    //once per account, its mailboxes may be named by any client
+    fn warn_non_utf8(account: &str) {
+        static WARNED: Lazy<Mutex<HashSet<String>>> = Lazy::new(Mutex::default);
+        if WARNED.lock().unwrap().insert(account.to_owned()) {
+            log::warn!(
+                "mailbox names of {} are not valid UTF-8, their changes sync the whole account",
+                account
+            );
+        }
+    }
+
+    //a pattern with a filter only matches the directory of a matching filename
+    fn mailbox_of(
+        basepath: &Path,
+        options: &FileWatcherOptions,
+        dir: &Path,
+        filename: Option<&str>,
+    ) -> Option<(String, Option<String>)> {
+        let (dir, lossy) = Self::relative_path(basepath, dir)?;
+        if dir.split('/').any(|component| {
+            options
+                .ignored_dirs
+                .iter()
+                .any(|ignored| ignored == component)
+        }) {
+            return None;
+        }
+        let caps = options
+            .path_patterns
+            .iter()
+            .filter(|(_, filter)| match (filter, filename) {
+                (Some(filter), Some(filename)) => filter.matches(filename),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .find_map(|(regex, _)| regex.captures(&dir))?;
+        let account = &caps["account"];
+        if lossy && account.contains(char::REPLACEMENT_CHARACTER) {
+            log::debug!("account {:?} is not valid UTF-8, skip its changes", account);
+            return None;
+        }
+        let account = options.account_layout.name(account);
+        //a lossy name would never be found by the sync, so the change is one of the account
+        let mailbox = caps
+            .name("mailbox")
+            .filter(|mailbox| {
+                let valid = !lossy || !mailbox.as_str().contains(char::REPLACEMENT_CHARACTER);
+                if !valid {
+                    Self::warn_non_utf8(&account);
+                }
+                valid
+            })
+            .map(|mailbox| options.mailbox_decoder.decode(mailbox.as_str()));
+        Some((account, mailbox))
+    }
+
+    fn emit_event(
+        debouncer: &mut EventDebouncer,
+        options: &FileWatcherOptions,
+        event: FileWatcherEvent,
+    ) -> Result<(), ProduceEventError> {
+        if let Some(comm) = event
+            .process
+            .as_ref()
+            .and_then(|process| process.comm.as_ref())
+        {
+            if options.ignored_processes.contains(comm) {
+                return Err(ProduceEventError::Skip);
+            }
+        }
+        if let Some(mailbox) = &event.mailbox {
+            if options.mailbox_filter.is_ignored(&event.account, mailbox) {
+                return Err(ProduceEventError::Skip);
+            }
+        }
+        if let Some(suppressor) = &options.suppressor {
+            if suppressor.is_suppressed(&event) {
+                log::debug!("suppress event of running sync {:?}", event);
+                return Err(ProduceEventError::Skip);
+            }
         }
+        debouncer.push(event)?;
+        Ok(())
     }
 
     fn produce_event(
-        events_tx: &Sender<FileWatcherEvent>,
+        debouncer: &mut EventDebouncer,
         basepath: &Path,
+        options: &FileWatcherOptions,
+        process: &Option<WriterProcess>,
+        kind: FileWatcherEventKind,
         path: &Path,
     ) -> Result<(), ProduceEventError> {
         let filename = path
             .file_name()
             .ok_or(ProduceEventError::Skip)?
-            .to_str()
-            .ok_or(ProduceEventError::Skip)?;
-        if filename == "dovecot.index.cache" || filename.starts_with(".temp") {
+            .to_string_lossy();
+        if options
+            .ignore_patterns
+            .iter()
+            .any(|pattern| pattern.matches(&filename))
+        {
             return Err(ProduceEventError::Skip);
         }
-        let path = if path.is_dir() {
+        let dir = if path.is_dir() {
             path
         } else {
             path.parent().ok_or(ProduceEventError::Skip)?
         };
-        let path = path
-            .as_os_str()
-            .to_str()
+        let (account, mailbox) = Self::mailbox_of(basepath, options, dir, Some(&filename))
+            .ok_or(ProduceEventError::Skip)?;
+        let event = FileWatcherEvent {
+            account,
+            mailbox,
+            kind,
+            path: path.to_path_buf(),
+            renamed_from: None,
+            process: process.clone(),
+        };
+        Self::emit_event(debouncer, options, event)
+    }
+
+    fn produce_access_event(
+        debouncer: &mut EventDebouncer,
+        basepath: &Path,
+        options: &FileWatcherOptions,
+        process: &Option<WriterProcess>,
+        last_access: &mut HashMap<(String, Option<String>), Instant>,
+        path: &Path,
+    ) -> Result<(), ProduceEventError> {
+        let cooldown = options.access_cooldown.ok_or(ProduceEventError::Skip)?;
+        let filename = path
+            .file_name()
             .ok_or(ProduceEventError::Skip)?
-            .strip_prefix(basepath.to_str().ok_or(ProduceEventError::Skip)?)
+            .to_string_lossy();
+        if path.is_dir() {
+            return Err(ProduceEventError::Skip);
+        }
+        let dir = path.parent().ok_or(ProduceEventError::Skip)?;
+        //the index of a dbox mailbox is next to its dbox-Mails directory
+        let (account, mailbox) = Self::mailbox_of(basepath, options, dir, Some(&filename))
+            .or_else(|| Self::mailbox_of(basepath, options, &dir.join("dbox-Mails"), None))
             .ok_or(ProduceEventError::Skip)?;
-        let caps = PATH_REGEX.captures(path).ok_or(ProduceEventError::Skip)?;
-        let account = &caps[1];
-        let mailbox = &caps[2];
-        events_tx.send(FileWatcherEvent {
-            account: account.to_owned(),
-            mailbox: decode_utf7_imap(mailbox.to_owned()),
-        })?;
-        Ok(())
+        let now = Instant::now();
+        let key = (account, mailbox);
+        if last_access
+            .get(&key)
+            .is_some_and(|last| now.duration_since(*last) < cooldown)
+        {
+            return Err(ProduceEventError::Skip);
+        }
+        last_access.insert(key.clone(), now);
+        let event = FileWatcherEvent {
+            account: key.0,
+            mailbox: key.1,
+            kind: FileWatcherEventKind::Access,
+            path: path.to_path_buf(),
+            renamed_from: None,
+            process: process.clone(),
+        };
+        Self::emit_event(debouncer, options, event)
+    }
+
+    //the first directory in or below dir which is a mailbox, with its path relative to dir and the
+    //file matching the filter of its pattern, e.g. the u.<uid> files of an sdbox mailbox
+    fn find_mailbox_dir(
+        basepath: &Path,
+        options: &FileWatcherOptions,
+        dir: &Path,
+    ) -> Option<MailboxDir> {
+        let mut pending = VecDeque::from([PathBuf::new()]);
+        while let Some(suffix) = pending.pop_front() {
+            let path = dir.join(&suffix);
+            let (dirs, files): (Vec<_>, Vec<_>) = fs::read_dir(&path)
+                .ok()?
+                .flatten()
+                .partition(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()));
+            let filenames = files
+                .iter()
+                .map(|entry| Some(entry.file_name().to_string_lossy().into_owned()));
+            if let Some((filename, mailbox)) =
+                iter::once(None).chain(filenames).find_map(|filename| {
+                    Self::mailbox_of(basepath, options, &path, filename.as_deref())
+                        .map(|mailbox| (filename, mailbox))
+                })
+            {
+                return Some((suffix, filename, mailbox));
+            }
+            pending.extend(dirs.iter().map(|entry| suffix.join(entry.file_name())));
+        }
+        None
+    }
+
+    //a renamed mailbox directory, the old name is found by the layout of the new one
+    fn produce_rename_event(
+        debouncer: &mut EventDebouncer,
+        basepath: &Path,
+        options: &FileWatcherOptions,
+        process: &Option<WriterProcess>,
+        from: &Path,
+        to: &Path,
+    ) -> Result<(), ProduceEventError> {
+        if !to.is_dir() {
+            return Err(ProduceEventError::Skip);
+        }
+        let (suffix, filename, (account, mailbox)) =
+            Self::find_mailbox_dir(basepath, options, to).ok_or(ProduceEventError::Skip)?;
+        let (from_account, from_mailbox) =
+            Self::mailbox_of(basepath, options, &from.join(suffix), filename.as_deref())
+                .ok_or(ProduceEventError::Skip)?;
+        if from_account != account {
+            return Err(ProduceEventError::Skip);
+        }
+        let event = FileWatcherEvent {
+            account,
+            mailbox,
+            kind: FileWatcherEventKind::Rename,
+            path: to.to_path_buf(),
+            renamed_from: from_mailbox,
+            process: process.clone(),
+        };
+        Self::emit_event(debouncer, options, event)
     }
 
     fn handle_events(
         basepath: PathBuf,
+        options: FileWatcherOptions,
         notify_rx: Receiver<Result<Event, notify::Error>>,
         events_tx: Sender<FileWatcherEvent>,
     ) {
         thread::spawn(move || {
-            for res in notify_rx {
+            let mut debouncer = EventDebouncer::new(events_tx, options.debounce);
+            let mut last_access = HashMap::new();
+            loop {
+                let res = match debouncer.timeout() {
+                    Some(timeout) => notify_rx.recv_timeout(timeout),
+                    None => notify_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
                 match res {
-                    Ok(event) => match event.kind {
-                        notify::EventKind::Create(_) => {
-                            for path in event.paths {
-                                let _ = Self::produce_event(&events_tx, &basepath, &path);
+                    Ok(Ok(event)) => {
+                        let process = event.attrs.process_id().map(|pid| WriterProcess {
+                            pid,
+                            comm: event.attrs.info().map(|info| info.to_owned()),
+                        });
+                        match event.kind {
+                            notify::EventKind::Create(_) => {
+                                for path in event.paths {
+                                    let _ = Self::produce_event(
+                                        &mut debouncer,
+                                        &basepath,
+                                        &options,
+                                        &process,
+                                        FileWatcherEventKind::Create,
+                                        &path,
+                                    );
+                                }
                             }
-                        }
-                        notify::EventKind::Remove(_) => {
-                            for path in event.paths {
-                                let _ = Self::produce_event(&events_tx, &basepath, &path);
+                            notify::EventKind::Remove(_) => {
+                                for path in event.paths {
+                                    let _ = Self::produce_event(
+                                        &mut debouncer,
+                                        &basepath,
+                                        &options,
+                                        &process,
+                                        FileWatcherEventKind::Remove,
+                                        &path,
+                                    );
+                                }
                             }
-                        }
-                        notify::EventKind::Modify(_) => {
-                            for path in event.paths {
-                                let _ = Self::produce_event(&events_tx, &basepath, &path);
+                            notify::EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                                if event.paths.len() == 2
+                                    && Self::produce_rename_event(
+                                        &mut debouncer,
+                                        &basepath,
+                                        &options,
+                                        &process,
+                                        &event.paths[0],
+                                        &event.paths[1],
+                                    )
+                                    .is_ok() => {}
+                            notify::EventKind::Modify(_) => {
+                                for path in event.paths {
+                                    let _ = Self::produce_event(
+                                        &mut debouncer,
+                                        &basepath,
+                                        &options,
+                                        &process,
+                                        FileWatcherEventKind::Modify,
+                                        &path,
+                                    );
+                                }
+                            }
+                            notify::EventKind::Access(AccessKind::Open(_)) => {
+                                for path in event.paths {
+                                    let _ = Self::produce_access_event(
+                                        &mut debouncer,
+                                        &basepath,
+                                        &options,
+                                        &process,
+                                        &mut last_access,
+                                        &path,
+                                    );
+                                }
                             }
+                            notify::EventKind::Access(_) => {}
+                            notify::EventKind::Any => {}
+                            notify::EventKind::Other => {}
                         }
-                        notify::EventKind::Access(_) => {}
-                        notify::EventKind::Any => {}
-                        notify::EventKind::Other => {}
-                    },
-                    Err(e) => log::error!("watch error: {:?}", e),
+                    }
+                    Ok(Err(e)) => log::error!("watch error: {:?}", e),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if debouncer.flush_due().is_err() {
+                    break;
                 }
             }
         });
@@ -140,14 +1034,59 @@ mod test {
     use std::{
         error::Error,
         fs::{self, File},
-        path::PathBuf,
-        time::Duration,
+        path::{Path, PathBuf},
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::{Duration, SystemTime},
     };
 
+    use notify::{EventHandler, RecursiveMode, Watcher, WatcherKind};
     use rstest::{fixture, rstest};
     use tempfile::TempDir;
 
-    use crate::watcher::FileWatcher;
+    use crate::{
+        accounts::AccountLayout,
+        watcher::{
+            FileWatcher, FileWatcherBackend, FileWatcherEventKind, FileWatcherOptions,
+            MailboxDecoder,
+        },
+    };
+
+    //a watcher exceeding the watch limit for the recursive watches of the paths
+    struct LimitedWatcher {
+        limited: Vec<PathBuf>,
+        watches: Arc<Mutex<Vec<(PathBuf, RecursiveMode)>>>,
+    }
+
+    impl Watcher for LimitedWatcher {
+        fn new<F: EventHandler>(_: F, _: notify::Config) -> notify::Result<Self> {
+            Ok(Self {
+                limited: Vec::new(),
+                watches: Arc::default(),
+            })
+        }
+
+        fn watch(&mut self, path: &Path, recursive_mode: RecursiveMode) -> notify::Result<()> {
+            if recursive_mode == RecursiveMode::Recursive
+                && self.limited.iter().any(|limited| limited == path)
+            {
+                return Err(notify::Error::new(notify::ErrorKind::MaxFilesWatch));
+            }
+            self.watches
+                .lock()
+                .unwrap()
+                .push((path.to_path_buf(), recursive_mode));
+            Ok(())
+        }
+
+        fn unwatch(&mut self, _: &Path) -> notify::Result<()> {
+            Ok(())
+        }
+
+        fn kind() -> WatcherKind {
+            WatcherKind::Inotify
+        }
+    }
 
     #[fixture]
     fn mail_directory() -> PathBuf {
@@ -158,6 +1097,39 @@ mod test {
         path
     }
 
+    #[rstest]
+    pub fn it_should_poll_the_accounts_exceeding_the_watch_limit(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let watches = Arc::default();
+        let watcher = LimitedWatcher {
+            limited: vec![mail_directory.clone(), mail_directory.join("acc2")],
+            watches: Arc::clone(&watches),
+        };
+        let (notify_tx, notify_rx) = mpsc::channel();
+        let _watcher = FileWatcher::watch_with_fallback(
+            watcher,
+            &mail_directory,
+            Duration::from_millis(100),
+            notify_tx,
+        )?;
+        let mut watched = watches.lock().unwrap().clone();
+        watched.sort();
+        assert_eq!(
+            vec![
+                (mail_directory.clone(), RecursiveMode::NonRecursive),
+                (mail_directory.join("acc1"), RecursiveMode::Recursive),
+            ],
+            watched
+        );
+        //acc2 is polled instead
+        let mail = mail_directory.join("acc2/Mail/mailboxes/mailbox1/dbox-Mails/1.eml");
+        File::create_new(&mail)?;
+        let event = notify_rx.recv_timeout(Duration::from_secs(2))??;
+        assert_eq!(vec![mail], event.paths);
+        Ok(())
+    }
+
     #[rstest]
     pub fn it_should_reqport_new_files(mail_directory: PathBuf) -> Result<(), Box<dyn Error>> {
         let watcher = FileWatcher::new(&mail_directory).unwrap();
@@ -166,7 +1138,12 @@ mod test {
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("mailbox1", event.mailbox);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        assert_eq!(FileWatcherEventKind::Create, event.kind);
+        assert_eq!(
+            mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"),
+            event.path
+        );
         Ok(())
     }
     #[rstest]
@@ -183,12 +1160,12 @@ mod test {
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("mailbox1", event.mailbox);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
         let event = watcher
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("mailbox2", event.mailbox);
+        assert_eq!(Some("mailbox2"), event.mailbox.as_deref());
         Ok(())
     }
     #[rstest]
@@ -200,7 +1177,8 @@ mod test {
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("mailbox1", event.mailbox);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        assert_eq!(FileWatcherEventKind::Remove, event.kind);
         Ok(())
     }
     #[rstest]
@@ -215,22 +1193,349 @@ mod test {
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("Später", event.mailbox);
+        assert_eq!(Some("Später"), event.mailbox.as_deref());
         Ok(())
     }
     #[rstest]
     pub fn it_should_reqport_new_files_in_encoded_folders_with_subfolder(
         mail_directory: PathBuf,
     ) -> Result<(), Box<dyn Error>> {
-        fs::create_dir_all(mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/Documents/dbox-Mails"))
+        fs::create_dir_all(
+            mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/Documents/dbox-Mails"),
+        )
+        .unwrap();
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        File::create_new(
+            mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/Documents/dbox-Mails/1.eml"),
+        )?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(Some("Später/Documents"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reqport_new_files_in_mdbox(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("acc3/mdbox/mailboxes/INBOX/dbox-Mails"))?;
+        fs::create_dir_all(mail_directory.join("acc3/mdbox/storage"))?;
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        File::create_new(mail_directory.join("acc3/mdbox/storage/dovecot.map.index.log"))?;
+        File::create_new(mail_directory.join("acc3/mdbox/storage/m.1"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc3", event.account);
+        assert_eq!(None, event.mailbox);
+        File::create_new(
+            mail_directory.join("acc3/mdbox/mailboxes/INBOX/dbox-Mails/dovecot.index.log"),
+        )?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
+        assert_eq!("acc3", event.account);
+        assert_eq!(Some("INBOX"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reqport_new_uid_files_in_sdbox(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("acc3/Mail/mailboxes/INBOX"))?;
         let watcher = FileWatcher::new(&mail_directory).unwrap();
-        File::create_new(mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/Documents/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc3/Mail/mailboxes/INBOX/dovecot.index.log"))?;
+        File::create_new(mail_directory.join("acc3/Mail/mailboxes/INBOX/u.1"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc3", event.account);
+        assert_eq!(Some("INBOX"), event.mailbox.as_deref());
+        assert_eq!(
+            mail_directory.join("acc3/Mail/mailboxes/INBOX/u.1"),
+            event.path
+        );
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reqport_new_files_with_custom_path_pattern(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("maildir/acc1/.Sent/new"))?;
+        let options = FileWatcherOptions::default()
+            .with_path_pattern(r"^/?maildir/(?P<account>[^/]+)/\.(?P<mailbox>[^/]+)/new$")?;
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("maildir/acc1/.Sent/new/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(Some("Sent"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reject_path_pattern_without_account() {
+        assert!(FileWatcherOptions::default()
+            .with_path_pattern(r"^(?P<mailbox>.+)$")
+            .is_err());
+    }
+    #[rstest]
+    pub fn it_should_reqport_new_files_with_account_layout(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(
+            mail_directory.join("example.org/frank/Mail/mailboxes/INBOX/dbox-Mails"),
+        )?;
+        let options = FileWatcherOptions::default()
+            .with_account_layout(AccountLayout::new(2, Some("{1}@{0}")));
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(
+            mail_directory.join("example.org/frank/Mail/mailboxes/INBOX/dbox-Mails/u.1"),
+        )?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("frank@example.org", event.account);
+        assert_eq!(Some("INBOX"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[cfg(unix)]
+    #[rstest]
+    pub fn it_should_reqport_new_files_in_symlinked_root(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let link = TempDir::new()?.into_path().join("mail");
+        std::os::unix::fs::symlink(&mail_directory, &link)?;
+        let watcher = FileWatcher::new(&link).unwrap();
+        File::create_new(link.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
         let event = watcher
             .wait_for_event(Some(Duration::from_secs(2)))
             .unwrap();
         assert_eq!("acc1", event.account);
-        assert_eq!("Später/Documents", event.mailbox);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_report_accessed_mailboxes(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::write(
+            mail_directory.join("acc1/Mail/mailboxes/mailbox1/dovecot.index"),
+            "",
+        )?;
+        let options = FileWatcherOptions::default().with_sync_on_access(Duration::from_secs(60));
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::open(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dovecot.index"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        assert_eq!(FileWatcherEventKind::Access, event.kind);
+        //the cooldown is not over yet
+        File::open(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dovecot.index"))?;
+        assert!(watcher
+            .wait_for_event(Some(Duration::from_millis(500)))
+            .is_err());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reqport_new_files_with_poll_backend(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let options = FileWatcherOptions::default()
+            .with_backend(FileWatcherBackend::Poll(Duration::from_millis(100)));
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_debounce_events_of_the_same_mailbox(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let options = FileWatcherOptions::default().with_debounce(Duration::from_millis(300));
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/2.eml"))?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox2/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/3.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("mailbox1"), event.mailbox.as_deref());
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("mailbox2"), event.mailbox.as_deref());
+        assert!(watcher
+            .wait_for_event(Some(Duration::from_millis(500)))
+            .is_err());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_notice_the_removal_of_the_root(mail_directory: PathBuf) {
+        let root = mail_directory.join("acc1");
+        let watcher = FileWatcher::new(&root).unwrap();
+        assert!(watcher.is_root_available());
+        fs::rename(&root, mail_directory.join("acc1.old")).unwrap();
+        assert!(!watcher.is_root_available());
+        fs::create_dir(&root).unwrap();
+        assert!(!watcher.is_root_available());
+        let watcher = FileWatcher::new(&root).unwrap();
+        assert!(watcher.is_root_available());
+    }
+    #[rstest]
+    pub fn it_should_skip_ignored_files(mail_directory: PathBuf) -> Result<(), Box<dyn Error>> {
+        let options = FileWatcherOptions::default()
+            .with_ignore_patterns(&["*.lock".to_owned(), "maildirfolder".to_owned()])?;
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.lock"))?;
+        File::create_new(
+            mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/maildirfolder"),
+        )?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox2/dbox-Mails/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("mailbox2"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_report_pending_events_as_batch(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox2/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc2/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        thread::sleep(Duration::from_millis(200));
+        let events = watcher.wait_for_events(2, Some(Duration::from_secs(2)))?;
+        assert_eq!(2, events.len());
+        assert_eq!("acc1", events[0].account);
+        assert_eq!("acc1", events[1].account);
+        let events = watcher.wait_for_events(10, Some(Duration::from_secs(2)))?;
+        assert_eq!(1, events.len());
+        assert_eq!("acc2", events[0].account);
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_reqport_renamed_mailboxes(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        fs::rename(
+            mail_directory.join("acc1/Mail/mailboxes/mailbox1"),
+            mail_directory.join("acc1/Mail/mailboxes/mailbox3"),
+        )?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(FileWatcherEventKind::Rename, event.kind);
+        assert_eq!(Some("mailbox3"), event.mailbox.as_deref());
+        assert_eq!(Some("mailbox1"), event.renamed_from.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_report_renamed_nested_mailboxes(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("acc1/Mail/mailboxes/Lists/rust/dbox-Mails"))?;
+        fs::create_dir_all(mail_directory.join("acc1/Mail/mailboxes/Archive"))?;
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        fs::rename(
+            mail_directory.join("acc1/Mail/mailboxes/Lists"),
+            mail_directory.join("acc1/Mail/mailboxes/Archive/Lists"),
+        )?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(FileWatcherEventKind::Rename, event.kind);
+        assert_eq!(Some("Archive/Lists/rust"), event.mailbox.as_deref());
+        assert_eq!(Some("Lists/rust"), event.renamed_from.as_deref());
+        Ok(())
+    }
+    #[cfg(unix)]
+    #[rstest]
+    pub fn it_should_reqport_new_files_in_non_utf8_folders(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let mailbox = mail_directory
+            .join("acc1/Mail/mailboxes")
+            .join(OsStr::from_bytes(b"Caf\xe9"))
+            .join("dbox-Mails");
+        fs::create_dir_all(&mailbox)?;
+        let watcher = FileWatcher::new(&mail_directory).unwrap();
+        File::create_new(mailbox.join("1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!("acc1", event.account);
+        assert_eq!(None, event.mailbox);
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_skip_ignored_dirs(mail_directory: PathBuf) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/index"))?;
+        let options = FileWatcherOptions::default()
+            .with_path_pattern(r"^/?(?P<account>[^/]+)/Mail/mailboxes/(?P<mailbox>.+)$")?
+            .with_ignored_dirs(&["index".to_owned()]);
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(
+            mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/index/dovecot.index"),
+        )?;
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox2/dbox-Mails/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("mailbox2/dbox-Mails"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_decode_mailboxes_with_the_configured_decoder(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/dbox-Mails"))?;
+        let options = FileWatcherOptions::default().with_mailbox_decoder(MailboxDecoder::Raw);
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/Sp&AOQ-ter/dbox-Mails/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("Sp&AOQ-ter"), event.mailbox.as_deref());
+        let options = FileWatcherOptions::default()
+            .with_mailbox_decoder(MailboxDecoder::custom(|name| name.to_uppercase()));
+        let watcher = FileWatcher::with_options(&mail_directory, options).unwrap();
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        let event = watcher
+            .wait_for_event(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(Some("MAILBOX1"), event.mailbox.as_deref());
+        Ok(())
+    }
+    #[rstest]
+    pub fn it_should_find_mailboxes_changed_since(
+        mail_directory: PathBuf,
+    ) -> Result<(), Box<dyn Error>> {
+        File::create_new(mail_directory.join("acc1/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        thread::sleep(Duration::from_millis(100));
+        let since = SystemTime::now();
+        thread::sleep(Duration::from_millis(100));
+        File::create_new(mail_directory.join("acc2/Mail/mailboxes/mailbox1/dbox-Mails/1.eml"))?;
+        File::create_new(mail_directory.join("acc2/Mail/mailboxes/mailbox1/dbox-Mails/2.eml"))?;
+        let events =
+            FileWatcher::changed_since(&mail_directory, &FileWatcherOptions::default(), since)?;
+        assert_eq!(1, events.len());
+        assert_eq!("acc2", events[0].account);
+        assert_eq!(Some("mailbox1"), events[0].mailbox.as_deref());
         Ok(())
     }
 }